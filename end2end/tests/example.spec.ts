@@ -1,9 +1,26 @@
 import { test, expect } from "@playwright/test";
 
-test("homepage has title and links to intro page", async ({ page }) => {
+// These tests expect the server to run against the mock backend, which echoes
+// each message back, e.g., via `just end2end`.
+
+test("homepage starts a new conversation", async ({ page }) => {
+  await page.goto("http://localhost:3000/");
+
+  await expect(page).toHaveTitle("Chat Base");
+  await expect(page).toHaveURL(/\/c\/[0-9a-f-]{36}$/);
+  await expect(page.getByPlaceholder("Type a message…")).toBeVisible();
+});
+
+test("assistant answers a message", async ({ page }) => {
   await page.goto("http://localhost:3000/");
 
-  await expect(page).toHaveTitle("Welcome to Leptos");
+  const input = page.getByPlaceholder("Type a message…");
+  await input.fill("Hello, mock!");
+  await input.press("Enter");
 
-  await expect(page.locator("h1")).toHaveText("Welcome to Leptos!");
+  // The mock echoes the message, so it appears in two chat bubbles: once from
+  // the user and once from the assistant.
+  const bubbles = page.locator(".chat-bubble", { hasText: "Hello, mock!" });
+  await expect(bubbles).toHaveCount(2);
+  await expect(page.getByPlaceholder("Type a message…")).toBeVisible();
 });
@@ -1,13 +1,15 @@
 use crate::{
-	chat::Chat,
+	chat::{Chat, ConversationSidebar, ConversationsChanged, chat_path},
 	error_template::{AppError, ErrorTemplate}
 };
 use leptos::prelude::*;
 use leptos_meta::*;
 use leptos_router::{
-	StaticSegment,
-	components::{Route, Router, Routes}
+	ParamSegment, StaticSegment,
+	components::{Redirect, Route, Router, Routes},
+	hooks::use_params_map
 };
+use uuid::Uuid;
 
 /// The application shell.
 pub fn shell(options: LeptosOptions) -> impl IntoView
@@ -61,9 +63,66 @@ pub fn App() -> impl IntoView
 					}
 					.into_view()
 				}>
-					<Route path=StaticSegment("") view=Chat/>
+					<Route path=StaticSegment("") view=NewChat/>
+					<Route
+						path=(StaticSegment("c"), ParamSegment("conversation_id"))
+						view=ConversationPage
+					/>
 				</Routes>
 			</main>
 		</Router>
 	}
 }
+
+/// Starts a new conversation, by redirecting to a fresh conversation
+/// identifier.
+#[component]
+pub fn NewChat() -> impl IntoView
+{
+	view! { <Redirect path=chat_path(Uuid::new_v4())/> }
+}
+
+/// The page that hosts a conversation, alongside the sidebar that lists every
+/// saved conversation. The conversation is specified by the route's
+/// `conversation_id` parameter.
+#[component]
+pub fn ConversationPage() -> impl IntoView
+{
+	let params = use_params_map();
+	// Memoize the identifier, so that the chat is only replaced when the user
+	// actually switches conversations.
+	let conversation_id = Memo::new(move |_| {
+		params
+			.read()
+			.get("conversation_id")
+			.and_then(|id| id.parse::<Uuid>().ok())
+	});
+	provide_context(ConversationsChanged(Trigger::new()));
+
+	view! {
+		<div class="drawer lg:drawer-open">
+			<input id="sidebar" type="checkbox" class="drawer-toggle"/>
+			<div class="drawer-content">
+				{move || match conversation_id()
+				{
+					Some(id) => view! { <Chat conversation_id=id/> }.into_any(),
+					None =>
+					{
+						let mut outside_errors = Errors::default();
+						outside_errors
+							.insert_with_default_key(AppError::NotFound);
+						view! { <ErrorTemplate outside_errors/> }.into_any()
+					}
+				}}
+			</div>
+			<div class="drawer-side">
+				<label
+					for="sidebar"
+					aria-label="Close sidebar"
+					class="drawer-overlay"
+				></label>
+				<ConversationSidebar current=conversation_id.into()/>
+			</div>
+		</div>
+	}
+}
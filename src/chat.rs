@@ -1,12 +1,52 @@
+#[cfg(feature = "ssr")]
+mod backend;
 #[allow(clippy::module_inception)]
 mod chat;
+#[cfg(feature = "ssr")]
+mod context;
+#[cfg(feature = "ssr")]
+mod dice;
 mod icons;
+mod json;
+mod markdown;
+#[cfg(feature = "ssr")]
+mod mock;
+#[cfg(feature = "ssr")]
+mod prompts;
+#[cfg(feature = "ssr")]
+mod replay;
+mod sidebar;
+#[cfg(feature = "ssr")]
+mod store;
+#[cfg(feature = "ssr")]
+mod tools;
 mod types;
+mod world;
 #[cfg(feature = "ssr")]
 mod ws;
 
+#[cfg(feature = "ssr")]
+pub use backend::*;
 pub use chat::*;
+#[cfg(feature = "ssr")]
+pub use context::*;
+#[cfg(feature = "ssr")]
+pub use dice::*;
 pub use icons::*;
+pub use json::*;
+pub use markdown::*;
+#[cfg(feature = "ssr")]
+pub use mock::*;
+#[cfg(feature = "ssr")]
+pub use prompts::*;
+#[cfg(feature = "ssr")]
+pub use replay::*;
+pub use sidebar::*;
+#[cfg(feature = "ssr")]
+pub use store::*;
+#[cfg(feature = "ssr")]
+pub use tools::*;
 pub use types::*;
+pub use world::*;
 #[cfg(feature = "ssr")]
 pub use ws::*;
@@ -0,0 +1,1493 @@
+use async_openai::{
+	Client,
+	config::{Config, OpenAIConfig},
+	error::{ApiError, OpenAIError},
+	types::{
+		ChatCompletionRequestMessage, ChatCompletionResponseFormat,
+		ChatCompletionResponseFormatType, ChatCompletionTool,
+		ChatCompletionToolType, CreateChatCompletionRequestArgs,
+		CreateChatCompletionStreamResponse, FinishReason as OpenAIFinishReason,
+		FunctionObject, Stop
+	}
+};
+use async_trait::async_trait;
+use futures::{
+	StreamExt, TryStreamExt,
+	stream::{self, BoxStream}
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{
+	collections::{HashMap, HashSet},
+	fmt::Debug,
+	sync::{Arc, LazyLock, Mutex},
+	time::{Duration, Instant}
+};
+use thiserror::Error;
+use tracing::{trace, warn};
+
+use super::{
+	GenerationParams, Message, MockBackend, Recorder, ReplayBackend, Role,
+	TokenCounter, ToolCall, ToolDefinition, Usage, chat_client, get_base_url,
+	get_key
+};
+use crate::error_template::AppError;
+
+////////////////////////////////////////////////////////////////////////////////
+//                               Chat backends.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A service that generates chat completions, e.g., an OpenAI-compatible
+/// server or a native Ollama server. The [`BackendRouter`] holds the
+/// configured backends.
+#[async_trait]
+pub trait ChatBackend: Debug + Send + Sync
+{
+	/// The name of the backend, for logging.
+	fn name(&self) -> &str;
+
+	/// List the models that the backend serves, sorted by name.
+	async fn list_models(&self) -> Result<Vec<String>, BackendError>;
+
+	/// Start a chat completion, streaming the response as it is generated.
+	///
+	/// # Arguments
+	///
+	/// - `request`: The completion request.
+	///
+	/// # Returns
+	///
+	/// The stream of pieces of the response.
+	async fn stream_completion(
+		&self,
+		request: CompletionRequest
+	) -> Result<CompletionStream, BackendError>;
+
+	/// Generate a chat completion, waiting for the whole response. By default,
+	/// this [assembles](CompletionAssembler) a
+	/// [streamed](Self::stream_completion) completion.
+	///
+	/// # Arguments
+	///
+	/// - `request`: The completion request.
+	///
+	/// # Returns
+	///
+	/// The content of the response.
+	async fn complete(
+		&self,
+		request: CompletionRequest
+	) -> Result<String, BackendError>
+	{
+		let stream = self.stream_completion(request).await?;
+		let completion = CompletionAssembler::collect(stream).await?;
+		Ok(completion.message.content)
+	}
+
+	/// Count the tokens that each of the specified messages occupies in the
+	/// model's context. By default, this uses the configured
+	/// [`TokenCounter`].
+	///
+	/// # Arguments
+	///
+	/// - `messages`: The messages.
+	///
+	/// # Returns
+	///
+	/// The number of tokens in each message, in order.
+	async fn count_tokens(&self, messages: &[Message]) -> Vec<usize>
+	{
+		TokenCounter::configured().count_all(messages).await
+	}
+
+	/// Whether the [token counts](Self::count_tokens) are exact, rather than
+	/// estimates.
+	fn counts_exact(&self) -> bool { TokenCounter::configured().is_exact() }
+}
+
+/// A request for a chat completion, in terms that every [`ChatBackend`]
+/// understands.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompletionRequest
+{
+	/// The resolved generation parameters.
+	pub params: GenerationParams,
+
+	/// The messages to send to the chat assistant.
+	pub messages: Vec<Message>,
+
+	/// Whether to ask for a JSON object.
+	pub json_mode: bool,
+
+	/// The tools that the assistant may call.
+	pub tools: Vec<ToolDefinition>
+}
+
+/// A piece of a streamed chat completion.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionChunk
+{
+	/// A fragment of the content.
+	Content(String),
+
+	/// A piece of a tool call. Pieces with the same index belong to the same
+	/// call, and their arguments concatenate.
+	ToolCall
+	{
+		/// The position of the call among the response's calls.
+		index: usize,
+
+		/// The identifier of the call, if this piece carries it.
+		id: Option<String>,
+
+		/// The name of the tool, if this piece carries it.
+		name: Option<String>,
+
+		/// A fragment of the arguments, as JSON text.
+		arguments: String
+	},
+
+	/// The number of tokens that the completion used, as reported by the
+	/// backend.
+	Usage(Usage),
+
+	/// Why the backend stopped generating, as reported by the backend.
+	Finish(FinishReason)
+}
+
+/// Why a [`ChatBackend`] stopped generating a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason
+{
+	/// The response is complete.
+	Stop,
+
+	/// The response hit the token limit, so it is cut off.
+	Length,
+
+	/// The assistant called tools, and awaits their results.
+	ToolCalls,
+
+	/// The backend withheld some of the response.
+	ContentFilter
+}
+
+/// The stream of pieces of a chat completion.
+pub type CompletionStream =
+	BoxStream<'static, Result<CompletionChunk, BackendError>>;
+
+/// A chat completion, [assembled](CompletionAssembler) from its pieces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion
+{
+	/// The response, as an assistant message, including any tool calls.
+	pub message: Message,
+
+	/// The number of tokens that the completion used, if the backend reported
+	/// it.
+	pub usage: Option<Usage>,
+
+	/// Why the backend stopped generating, if it said.
+	pub finish_reason: Option<FinishReason>
+}
+
+/// Assembles a [`Completion`] from the pieces of a streamed completion. The
+/// content concatenates, and so do the arguments of each tool call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionAssembler
+{
+	/// The completion so far.
+	completion: Completion
+}
+
+impl Default for CompletionAssembler
+{
+	fn default() -> Self
+	{
+		Self {
+			completion: Completion {
+				message: Role::Assistant.empty(),
+				usage: None,
+				finish_reason: None
+			}
+		}
+	}
+}
+
+impl CompletionAssembler
+{
+	/// Add the specified piece to the completion.
+	///
+	/// # Arguments
+	///
+	/// - `chunk`: The piece of the completion.
+	///
+	/// # Returns
+	///
+	/// The fragment of content that the piece carries, if any.
+	pub fn push(&mut self, chunk: CompletionChunk) -> Option<String>
+	{
+		let completion = &mut self.completion;
+		match chunk
+		{
+			CompletionChunk::Content(fragment) =>
+			{
+				completion.message.content.push_str(&fragment);
+				return Some(fragment)
+			},
+			CompletionChunk::ToolCall {
+				index,
+				id,
+				name,
+				arguments
+			} =>
+			{
+				let calls = &mut completion.message.tool_calls;
+				if index >= calls.len()
+				{
+					calls.resize(
+						index + 1,
+						ToolCall {
+							id: String::new(),
+							name: String::new(),
+							arguments: String::new()
+						}
+					);
+				}
+				let call = &mut calls[index];
+				if let Some(id) = id
+				{
+					call.id = id;
+				}
+				call.name.push_str(&name.unwrap_or_default());
+				call.arguments.push_str(&arguments);
+			},
+			CompletionChunk::Usage(usage) => completion.usage = Some(usage),
+			CompletionChunk::Finish(reason) =>
+			{
+				completion.finish_reason = Some(reason)
+			},
+		}
+		None
+	}
+
+	/// Finish assembling the completion.
+	pub fn finish(self) -> Completion { self.completion }
+
+	/// Assemble the completion from every piece of the specified stream.
+	///
+	/// # Arguments
+	///
+	/// - `stream`: The stream of pieces of the completion.
+	///
+	/// # Returns
+	///
+	/// The completion, or the first error in the stream.
+	pub async fn collect(
+		stream: CompletionStream
+	) -> Result<Completion, BackendError>
+	{
+		let assembler = stream
+			.try_fold(Self::default(), |mut assembler, chunk| async move {
+				assembler.push(chunk);
+				Ok(assembler)
+			})
+			.await?;
+		Ok(assembler.finish())
+	}
+}
+
+/// An error that occurred while talking to a [`ChatBackend`].
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendError
+{
+	#[error("Invalid request: {0}")]
+	Request(String),
+
+	#[error("Backend unavailable: {0}")]
+	Unavailable(String),
+
+	#[error("Backend failed: {0}")]
+	Backend(String),
+
+	#[error("Invalid response: {0}")]
+	Response(String)
+}
+
+impl From<BackendError> for AppError
+{
+	fn from(_error: BackendError) -> Self { AppError::ChatError }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Backend routing.                              //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The kind of a [`BackendProfile`], i.e., the API that the backend speaks.
+#[derive(
+	Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind
+{
+	/// Any server that implements the OpenAI chat completions API.
+	#[default]
+	#[serde(rename = "openai")]
+	OpenAi,
+
+	/// Ollama's native API.
+	Ollama,
+
+	/// The [`MockBackend`], which needs no model server.
+	Mock,
+
+	/// The [`ReplayBackend`], which plays back recorded exchanges.
+	Replay
+}
+
+/// A named backend in the configuration, i.e., an entry of the
+/// `BACKENDS_PATH` file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendProfile
+{
+	/// The name of the backend, by which conversations choose it.
+	pub name: String,
+
+	/// The kind of the backend.
+	#[serde(default)]
+	pub kind: BackendKind,
+
+	/// The base URL of the backend's API, or `None` for the default of its
+	/// kind, i.e., `OPENAI_API_URL` or `OLLAMA_URL`.
+	#[serde(default)]
+	pub url: Option<String>,
+
+	/// The API key, or `None` for `OPENAI_TOKEN`. Only the OpenAI kind uses
+	/// a key.
+	#[serde(default)]
+	pub token: Option<String>,
+
+	/// The model to use when the conversation chooses none, or when the
+	/// backend stands in for another, or `None` for `CHAT_MODEL`.
+	#[serde(default)]
+	pub model: Option<String>,
+
+	/// The path to the [script](super::MockScript) of the mock kind, or
+	/// `None` for `MOCK_SCRIPT`. For the replay kind, the path to the
+	/// [fixtures](super::Exchange), or `None` for `REPLAY_PATH`.
+	#[serde(default)]
+	pub script: Option<String>
+}
+
+/// The contents of the `BACKENDS_PATH` file.
+#[derive(Debug, Deserialize)]
+struct BackendsFile
+{
+	/// The backend profiles, in order of preference.
+	#[serde(default)]
+	backends: Vec<BackendProfile>
+}
+
+impl BackendProfile
+{
+	/// Get the profile of the backend that the environment describes, i.e.,
+	/// `CHAT_BACKEND` (`openai`, `ollama`, `mock`, or `replay`) along with the
+	/// settings for that kind.
+	pub fn from_env() -> Self
+	{
+		let kind = match std::env::var("CHAT_BACKEND")
+		{
+			Ok(kind) if kind.eq_ignore_ascii_case("ollama") =>
+			{
+				BackendKind::Ollama
+			},
+			Ok(kind) if kind.eq_ignore_ascii_case("mock") => BackendKind::Mock,
+			Ok(kind) if kind.eq_ignore_ascii_case("replay") =>
+			{
+				BackendKind::Replay
+			},
+			Ok(kind) if !kind.eq_ignore_ascii_case("openai") =>
+			{
+				warn!("Unknown chat backend, using OpenAI: {kind}");
+				BackendKind::OpenAi
+			},
+			_ => BackendKind::OpenAi
+		};
+		Self {
+			name: DEFAULT_BACKEND.to_string(),
+			kind,
+			url: None,
+			token: None,
+			model: None,
+			script: None
+		}
+	}
+
+	/// Connect to the backend that the profile describes.
+	///
+	/// # Arguments
+	///
+	/// - `recorder`: The recorder of the backend's exchanges, if any. Only the
+	///   kinds that talk to a model server record their exchanges.
+	pub fn connect(&self, recorder: Option<Recorder>) -> RoutedBackend
+	{
+		let backend: Arc<dyn ChatBackend> = match self.kind
+		{
+			BackendKind::OpenAi =>
+			{
+				let backend = OpenAiBackend::new(Client::with_config(
+					OpenAIConfig::new()
+						.with_api_base(
+							self.url.clone().unwrap_or_else(get_base_url)
+						)
+						.with_api_key(
+							self.token.clone().unwrap_or_else(get_key)
+						)
+				));
+				Arc::new(match recorder
+				{
+					Some(recorder) => backend.with_recorder(recorder),
+					None => backend
+				})
+			},
+			BackendKind::Ollama =>
+			{
+				let backend = OllamaBackend::new(
+					self.url.clone().unwrap_or_else(get_ollama_url)
+				);
+				Arc::new(match recorder
+				{
+					Some(recorder) => backend.with_recorder(recorder),
+					None => backend
+				})
+			},
+			BackendKind::Mock => Arc::new(match &self.script
+			{
+				Some(script) => MockBackend::read_or_echo(Some(script)),
+				None => MockBackend::configured()
+			}),
+			BackendKind::Replay => Arc::new(match &self.script
+			{
+				Some(fixtures) => ReplayBackend::read_or_empty(Some(fixtures))
+					.with_timing(true),
+				None => ReplayBackend::configured()
+			})
+		};
+		RoutedBackend {
+			name: self.name.clone(),
+			model: self.model.clone(),
+			backend
+		}
+	}
+}
+
+/// A [`ChatBackend`] under the name that its [profile](BackendProfile) gives
+/// it.
+#[derive(Debug, Clone)]
+pub struct RoutedBackend
+{
+	/// The name of the backend.
+	pub name: String,
+
+	/// The model to use when the conversation chooses none, or when the
+	/// backend stands in for another, if any.
+	pub model: Option<String>,
+
+	/// The backend.
+	pub backend: Arc<dyn ChatBackend>
+}
+
+/// Routes chat completions to the named backends, and keeps track of which
+/// backends are healthy. A backend that fails to connect is unhealthy for a
+/// while, during which it is tried only after every healthy backend.
+#[derive(Debug)]
+pub struct BackendRouter
+{
+	/// The backends, in order of preference. The first is the default.
+	backends: Vec<RoutedBackend>,
+
+	/// When each unhealthy backend failed, keyed by name.
+	unhealthy: Mutex<HashMap<String, Instant>>
+}
+
+impl BackendRouter
+{
+	/// Create a router for the specified backends.
+	///
+	/// # Arguments
+	///
+	/// - `backends`: The backends, in order of preference. The first is the
+	///   default.
+	///
+	/// # Panics
+	///
+	/// If there are no backends.
+	pub fn new(backends: Vec<RoutedBackend>) -> Self
+	{
+		assert!(!backends.is_empty(), "A router needs at least one backend");
+		Self {
+			backends,
+			unhealthy: Mutex::new(HashMap::new())
+		}
+	}
+
+	/// Get the configured router. If `BACKENDS_PATH` names a TOML file of
+	/// `[[backends]]` tables, then the router holds those backends. Otherwise,
+	/// or if the file is unusable, the router holds the single backend that
+	/// the environment [describes](BackendProfile::from_env). If `RECORD_DIR`
+	/// names a directory, then every backend that talks to a model server
+	/// [records](Recorder) its exchanges there. Every session
+	/// shares the router, and thus its knowledge of the backends' health.
+	pub fn configured() -> Arc<Self>
+	{
+		static ROUTER: LazyLock<Arc<BackendRouter>> = LazyLock::new(|| {
+			let profiles = match std::env::var("BACKENDS_PATH")
+			{
+				Ok(path) => read_profiles(&path).unwrap_or_else(|e| {
+					warn!("Failed to read backends: {path}: {e}");
+					vec![]
+				}),
+				Err(_) => vec![]
+			};
+			let profiles = match profiles.is_empty()
+			{
+				true => vec![BackendProfile::from_env()],
+				false => profiles
+			};
+			let recorder = std::env::var("RECORD_DIR").ok().map(Recorder::new);
+			Arc::new(BackendRouter::new(
+				profiles
+					.iter()
+					.map(|profile| profile.connect(recorder.clone()))
+					.collect()
+			))
+		});
+		Arc::clone(&ROUTER)
+	}
+
+	/// Get the names of the backends, in order of preference. The first is
+	/// the default.
+	pub fn names(&self) -> Vec<String>
+	{
+		self.backends
+			.iter()
+			.map(|backend| backend.name.clone())
+			.collect()
+	}
+
+	/// Get the specified backend. Unknown names get the default backend, with
+	/// a warning.
+	///
+	/// # Arguments
+	///
+	/// - `name`: The name of the backend, or `None` for the default.
+	pub fn get(&self, name: Option<&str>) -> &RoutedBackend
+	{
+		name.and_then(|name| {
+			let backend = self.backends.iter().find(|b| b.name == name);
+			if backend.is_none()
+			{
+				warn!("Unknown backend, using the default: {name}");
+			}
+			backend
+		})
+		.unwrap_or(&self.backends[0])
+	}
+
+	/// Get the backends to try, in order, for a completion on the specified
+	/// backend: first the healthy backends, starting with the specified one
+	/// and then in order of preference, and finally the unhealthy ones, in
+	/// the same order.
+	///
+	/// # Arguments
+	///
+	/// - `name`: The name of the chosen backend, or `None` for the default.
+	pub fn route(&self, name: Option<&str>) -> Vec<RoutedBackend>
+	{
+		let chosen = self.get(name).name.clone();
+		let mut route = self.backends.clone();
+		// The sort is stable, so the order of preference holds otherwise.
+		route.sort_by_key(|backend| {
+			(!self.is_healthy(&backend.name), backend.name != chosen)
+		});
+		route
+	}
+
+	/// Whether the specified backend is healthy, i.e., has not failed to
+	/// connect within the last [`UNHEALTHY_DURATION`].
+	///
+	/// # Arguments
+	///
+	/// - `name`: The name of the backend.
+	pub fn is_healthy(&self, name: &str) -> bool
+	{
+		match self.unhealthy.lock().unwrap().get(name)
+		{
+			Some(failed) => failed.elapsed() >= UNHEALTHY_DURATION,
+			None => true
+		}
+	}
+
+	/// Record that the specified backend failed to connect.
+	///
+	/// # Arguments
+	///
+	/// - `name`: The name of the backend.
+	pub fn mark_unhealthy(&self, name: &str)
+	{
+		self.unhealthy
+			.lock()
+			.unwrap()
+			.insert(name.to_string(), Instant::now());
+	}
+
+	/// Record that the specified backend connected.
+	///
+	/// # Arguments
+	///
+	/// - `name`: The name of the backend.
+	pub fn mark_healthy(&self, name: &str)
+	{
+		self.unhealthy.lock().unwrap().remove(name);
+	}
+}
+
+/// Read the [backend profiles](BackendProfile) from the specified TOML file.
+///
+/// # Arguments
+///
+/// - `path`: The path to the file.
+///
+/// # Returns
+///
+/// The profiles, in order of preference.
+fn read_profiles(path: &str) -> Result<Vec<BackendProfile>, String>
+{
+	let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+	let file =
+		toml::from_str::<BackendsFile>(&text).map_err(|e| e.to_string())?;
+	let mut names = HashSet::new();
+	for profile in &file.backends
+	{
+		if !names.insert(&profile.name)
+		{
+			return Err(format!("Duplicate backend: {}", profile.name))
+		}
+	}
+	Ok(file.backends)
+}
+
+/// Get the base URL of the Ollama server, for Ollama backends whose profile
+/// gives none.
+fn get_ollama_url() -> String
+{
+	std::env::var("OLLAMA_URL").unwrap_or_else(|_| OLLAMA_URL.into())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              OpenAI backend.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [`ChatBackend`] for any server that implements the OpenAI chat
+/// completions API, e.g., LM Studio, vLLM, or OpenAI itself.
+#[derive(Debug, Clone)]
+pub struct OpenAiBackend
+{
+	/// The chat client.
+	client: Client<OpenAIConfig>,
+
+	/// The HTTP client for streaming completions. The chat client reports the
+	/// transport errors of its streams only as text, so the backend streams
+	/// completions itself, with the chat client's configuration.
+	http: reqwest::Client,
+
+	/// The recorder of the backend's exchanges, if any.
+	recorder: Option<Recorder>
+}
+
+/// The body of an error response of the OpenAI API. Some servers also send it
+/// as an event of a streamed response.
+#[derive(Debug, Deserialize)]
+struct OpenAiErrorBody
+{
+	/// The error.
+	error: ApiError
+}
+
+impl OpenAiBackend
+{
+	/// Create a backend that uses the specified client.
+	///
+	/// # Arguments
+	///
+	/// - `client`: The chat client.
+	pub fn new(client: Client<OpenAIConfig>) -> Self
+	{
+		Self {
+			client,
+			http: reqwest::Client::new(),
+			recorder: None
+		}
+	}
+
+	/// Record every exchange with the server.
+	///
+	/// # Arguments
+	///
+	/// - `recorder`: The recorder.
+	pub fn with_recorder(mut self, recorder: Recorder) -> Self
+	{
+		self.recorder = Some(recorder);
+		self
+	}
+
+	/// Create a backend for the configured OpenAI API. `OPENAI_API_URL` and
+	/// `OPENAI_TOKEN` configure the client.
+	pub fn configured() -> Self { Self::new(chat_client()) }
+}
+
+#[async_trait]
+impl ChatBackend for OpenAiBackend
+{
+	fn name(&self) -> &str { "openai" }
+
+	async fn list_models(&self) -> Result<Vec<String>, BackendError>
+	{
+		let response =
+			self.client.models().list().await.map_err(openai_error)?;
+		let mut models = response
+			.data
+			.into_iter()
+			.map(|model| model.id)
+			.collect::<Vec<_>>();
+		models.sort();
+		Ok(models)
+	}
+
+	async fn stream_completion(
+		&self,
+		request: CompletionRequest
+	) -> Result<CompletionStream, BackendError>
+	{
+		let recording = self
+			.recorder
+			.as_ref()
+			.map(|recorder| recorder.start(BackendKind::OpenAi, &request));
+		let CompletionRequest {
+			params,
+			messages,
+			json_mode,
+			tools
+		} = request;
+		// Convert the messages to the OpenAI message type.
+		let messages: Vec<ChatCompletionRequestMessage> = messages
+			.iter()
+			.map(ChatCompletionRequestMessage::try_from)
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|e| BackendError::Request(e.to_string()))?;
+		let mut args = CreateChatCompletionRequestArgs::default();
+		args.model(params.model.clone().unwrap_or_default())
+			.messages(messages)
+			.stream(true);
+		if let Some(max_tokens) = params.max_tokens
+		{
+			args.max_tokens(max_tokens);
+		}
+		if let Some(temperature) = params.temperature
+		{
+			args.temperature(temperature);
+		}
+		if let Some(top_p) = params.top_p
+		{
+			args.top_p(top_p);
+		}
+		if !params.stop.is_empty()
+		{
+			args.stop(Stop::StringArray(params.stop.clone()));
+		}
+		if let Some(seed) = params.seed
+		{
+			args.seed(seed);
+		}
+		if let Some(presence_penalty) = params.presence_penalty
+		{
+			args.presence_penalty(presence_penalty);
+		}
+		if let Some(frequency_penalty) = params.frequency_penalty
+		{
+			args.frequency_penalty(frequency_penalty);
+		}
+		if json_mode
+		{
+			args.response_format(ChatCompletionResponseFormat {
+				r#type: ChatCompletionResponseFormatType::JsonObject
+			});
+		}
+		if !tools.is_empty()
+		{
+			args.tools(
+				tools
+					.into_iter()
+					.map(|tool| ChatCompletionTool {
+						r#type: ChatCompletionToolType::Function,
+						function: FunctionObject {
+							name: tool.name,
+							description: Some(tool.description),
+							parameters: Some(tool.parameters)
+						}
+					})
+					.collect::<Vec<_>>()
+			);
+		}
+		let request = args
+			.build()
+			.map_err(|e| BackendError::Request(e.to_string()))?;
+		let config = self.client.config();
+		let response = self
+			.http
+			.post(config.url("/chat/completions"))
+			.query(&config.query())
+			.headers(config.headers())
+			.json(&request)
+			.send()
+			.await
+			.map_err(http_error)?;
+		let status = response.status();
+		let lines = response_lines(response);
+		let lines = match recording
+		{
+			Some(recording) => recording.record(status, lines),
+			None => lines
+		};
+		openai_response(status, lines).await
+	}
+}
+
+/// Parse the specified streamed response of the OpenAI API. The
+/// [`ReplayBackend`] parses recorded responses this way too.
+///
+/// # Arguments
+///
+/// - `status`: The HTTP status of the response.
+/// - `lines`: The lines of the body of the response.
+///
+/// # Returns
+///
+/// The stream of pieces of the completion.
+pub(super) async fn openai_response(
+	status: StatusCode,
+	lines: ResponseLines
+) -> Result<CompletionStream, BackendError>
+{
+	if !status.is_success()
+	{
+		let body = response_body(lines).await;
+		let message = serde_json::from_str::<OpenAiErrorBody>(&body)
+			.map(|body| body.error.message)
+			.unwrap_or(body);
+		return Err(BackendError::Backend(format!("{status}: {message}")))
+	}
+	Ok(lines
+		.map(|line| line.and_then(|line| openai_line(&line)))
+		.map_ok(|chunks| stream::iter(chunks.into_iter().map(Ok)))
+		.try_flatten()
+		.boxed())
+}
+
+/// Classify the specified OpenAI error. Failures to reach the server make the
+/// backend [unavailable](BackendError::Unavailable).
+fn openai_error(error: OpenAIError) -> BackendError
+{
+	match error
+	{
+		OpenAIError::Reqwest(e) => http_error(e),
+		OpenAIError::JSONDeserialize(e) =>
+		{
+			BackendError::Response(e.to_string())
+		},
+		e => BackendError::Backend(e.to_string())
+	}
+}
+
+/// Parse the specified line of a streamed OpenAI response, i.e., of a stream
+/// of server-sent events. Only `data` lines carry anything, and the servers
+/// send the data of each event on a single line.
+///
+/// # Arguments
+///
+/// - `line`: The line.
+///
+/// # Returns
+///
+/// The pieces of the completion that the line carries.
+fn openai_line(line: &[u8]) -> Result<Vec<CompletionChunk>, BackendError>
+{
+	let line = std::str::from_utf8(line)
+		.map_err(|e| BackendError::Response(e.to_string()))?;
+	let Some(data) = line.trim_end().strip_prefix("data:")
+	else
+	{
+		return Ok(vec![])
+	};
+	let data = data.trim_start();
+	if data == "[DONE]"
+	{
+		return Ok(vec![])
+	}
+	if let Ok(body) = serde_json::from_str::<OpenAiErrorBody>(data)
+	{
+		return Err(BackendError::Backend(body.error.message))
+	}
+	let fragment =
+		serde_json::from_str::<CreateChatCompletionStreamResponse>(data)
+			.map_err(|e| BackendError::Response(e.to_string()))?;
+	trace!("Received chat fragment: {:#?}", fragment);
+	Ok(openai_chunks(fragment))
+}
+
+/// Split the specified OpenAI stream response into
+/// [completion chunks](CompletionChunk).
+fn openai_chunks(
+	mut fragment: CreateChatCompletionStreamResponse
+) -> Vec<CompletionChunk>
+{
+	let mut chunks = vec![];
+	let Some(choice) = fragment.choices.first_mut()
+	else
+	{
+		return chunks
+	};
+	// Tool calls arrive in pieces, keyed by index. The first piece of each call
+	// carries its identifier and name.
+	for chunk in choice.delta.tool_calls.take().into_iter().flatten()
+	{
+		let Ok(index) = usize::try_from(chunk.index)
+		else
+		{
+			continue
+		};
+		let (name, arguments) = chunk
+			.function
+			.map(|function| (function.name, function.arguments))
+			.unwrap_or_default();
+		chunks.push(CompletionChunk::ToolCall {
+			index,
+			id: chunk.id,
+			name,
+			arguments: arguments.unwrap_or_default()
+		});
+	}
+	if let Some(content) = choice.delta.content.take()
+		&& !content.is_empty()
+	{
+		chunks.push(CompletionChunk::Content(content));
+	}
+	// The function call is OpenAI's deprecated predecessor of the tool call.
+	let reason = match choice.finish_reason
+	{
+		Some(OpenAIFinishReason::Stop) => Some(FinishReason::Stop),
+		Some(OpenAIFinishReason::Length) => Some(FinishReason::Length),
+		Some(
+			OpenAIFinishReason::ToolCalls | OpenAIFinishReason::FunctionCall
+		) => Some(FinishReason::ToolCalls),
+		Some(OpenAIFinishReason::ContentFilter) =>
+		{
+			Some(FinishReason::ContentFilter)
+		},
+		None => None
+	};
+	if let Some(reason) = reason
+	{
+		chunks.push(CompletionChunk::Finish(reason));
+	}
+	chunks
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Ollama backend.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [`ChatBackend`] for Ollama's native API, which, unlike its
+/// OpenAI-compatible API, reports the tokens that each completion used and
+/// honors every sampling option.
+#[derive(Debug, Clone)]
+pub struct OllamaBackend
+{
+	/// The HTTP client.
+	client: reqwest::Client,
+
+	/// The base URL of the Ollama server, e.g., `http://localhost:11434`.
+	url: String,
+
+	/// The recorder of the backend's exchanges, if any.
+	recorder: Option<Recorder>
+}
+
+/// A chat message in Ollama's native API.
+#[derive(Debug, Serialize, Deserialize)]
+struct OllamaMessage
+{
+	/// The role of the message: `system`, `user`, `assistant`, or `tool`.
+	role: String,
+
+	/// The content of the message.
+	#[serde(default)]
+	content: String,
+
+	/// The tools that the assistant called, if any.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	tool_calls: Vec<OllamaToolCall>
+}
+
+/// A tool call in Ollama's native API. Unlike OpenAI's, the arguments are a
+/// JSON object rather than JSON text, and the call has no identifier.
+#[derive(Debug, Serialize, Deserialize)]
+struct OllamaToolCall
+{
+	/// The called function.
+	function: OllamaFunction
+}
+
+/// The function of an [`OllamaToolCall`].
+#[derive(Debug, Serialize, Deserialize)]
+struct OllamaFunction
+{
+	/// The name of the tool.
+	name: String,
+
+	/// The arguments of the call.
+	#[serde(default)]
+	arguments: Value
+}
+
+/// A line of Ollama's streamed chat response.
+#[derive(Debug, Deserialize)]
+struct OllamaChatChunk
+{
+	/// The next piece of the assistant's message.
+	message: Option<OllamaMessage>,
+
+	/// The number of tokens in the prompt, reported with the last line.
+	prompt_eval_count: Option<usize>,
+
+	/// The number of tokens in the response, reported with the last line.
+	eval_count: Option<usize>,
+
+	/// Why the server stopped generating, reported with the last line, e.g.,
+	/// `stop` or `length`.
+	done_reason: Option<String>,
+
+	/// The error that ended the response, if any.
+	error: Option<String>
+}
+
+/// The response of Ollama's model listing.
+#[derive(Debug, Deserialize)]
+struct OllamaModels
+{
+	/// The models that the server has.
+	models: Vec<OllamaModel>
+}
+
+/// A model in [Ollama's model listing](OllamaModels).
+#[derive(Debug, Deserialize)]
+struct OllamaModel
+{
+	/// The name of the model, e.g., `llama3:latest`.
+	name: String
+}
+
+impl OllamaBackend
+{
+	/// Create a backend for the Ollama server at the specified URL.
+	///
+	/// # Arguments
+	///
+	/// - `url`: The base URL of the Ollama server.
+	pub fn new(url: impl Into<String>) -> Self
+	{
+		Self {
+			client: reqwest::Client::new(),
+			url: url.into().trim_end_matches('/').to_string(),
+			recorder: None
+		}
+	}
+
+	/// Record every exchange with the server.
+	///
+	/// # Arguments
+	///
+	/// - `recorder`: The recorder.
+	pub fn with_recorder(mut self, recorder: Recorder) -> Self
+	{
+		self.recorder = Some(recorder);
+		self
+	}
+
+	/// Create a backend for the configured Ollama server. `OLLAMA_URL`
+	/// specifies its base URL.
+	pub fn configured() -> Self { Self::new(get_ollama_url()) }
+}
+
+#[async_trait]
+impl ChatBackend for OllamaBackend
+{
+	fn name(&self) -> &str { "ollama" }
+
+	async fn list_models(&self) -> Result<Vec<String>, BackendError>
+	{
+		let response = self
+			.client
+			.get(format!("{}/api/tags", self.url))
+			.send()
+			.await
+			.and_then(reqwest::Response::error_for_status)
+			.map_err(http_error)?
+			.json::<OllamaModels>()
+			.await
+			.map_err(|e| BackendError::Response(e.to_string()))?;
+		let mut models = response
+			.models
+			.into_iter()
+			.map(|model| model.name)
+			.collect::<Vec<_>>();
+		models.sort();
+		Ok(models)
+	}
+
+	async fn stream_completion(
+		&self,
+		request: CompletionRequest
+	) -> Result<CompletionStream, BackendError>
+	{
+		let recording = self
+			.recorder
+			.as_ref()
+			.map(|recorder| recorder.start(BackendKind::Ollama, &request));
+		let body = ollama_request(request)?;
+		trace!("Ollama request: {:#?}", body);
+		let response = self
+			.client
+			.post(format!("{}/api/chat", self.url))
+			.json(&body)
+			.send()
+			.await
+			.map_err(http_error)?;
+		let status = response.status();
+		let lines = response_lines(response);
+		let lines = match recording
+		{
+			Some(recording) => recording.record(status, lines),
+			None => lines
+		};
+		ollama_response(status, lines).await
+	}
+}
+
+/// Parse the specified streamed response of Ollama's chat endpoint. The
+/// [`ReplayBackend`] parses recorded responses this way too.
+///
+/// # Arguments
+///
+/// - `status`: The HTTP status of the response.
+/// - `lines`: The lines of the body of the response.
+///
+/// # Returns
+///
+/// The stream of pieces of the completion.
+pub(super) async fn ollama_response(
+	status: StatusCode,
+	lines: ResponseLines
+) -> Result<CompletionStream, BackendError>
+{
+	if !status.is_success()
+	{
+		let body = response_body(lines).await;
+		let message = serde_json::from_str::<OllamaChatChunk>(&body)
+			.ok()
+			.and_then(|chunk| chunk.error)
+			.unwrap_or(body);
+		return Err(BackendError::Backend(format!("{status}: {message}")))
+	}
+	// The response is newline-delimited JSON. Ollama sends each tool call
+	// whole, without an identifier, so number the calls in order of
+	// arrival.
+	let mut calls = 0;
+	Ok(lines
+		.try_filter(|line| {
+			std::future::ready(!line.iter().all(u8::is_ascii_whitespace))
+		})
+		.and_then(|line| {
+			std::future::ready(
+				serde_json::from_slice::<OllamaChatChunk>(&line)
+					.map_err(|e| BackendError::Response(e.to_string()))
+			)
+		})
+		.map(move |chunk| {
+			let chunk = chunk?;
+			trace!("Received chat fragment: {:#?}", chunk);
+			if let Some(error) = chunk.error
+			{
+				return Err(BackendError::Backend(error))
+			}
+			let mut chunks = vec![];
+			if let Some(message) = chunk.message
+			{
+				if !message.content.is_empty()
+				{
+					chunks.push(CompletionChunk::Content(message.content));
+				}
+				for call in message.tool_calls
+				{
+					chunks.push(CompletionChunk::ToolCall {
+						index: calls,
+						id: Some(format!("call_{calls}")),
+						name: Some(call.function.name),
+						arguments: call.function.arguments.to_string()
+					});
+					calls += 1;
+				}
+			}
+			if let (Some(prompt_tokens), Some(completion_tokens)) =
+				(chunk.prompt_eval_count, chunk.eval_count)
+			{
+				chunks.push(CompletionChunk::Usage(Usage {
+					prompt_tokens,
+					completion_tokens,
+					exact: true
+				}));
+			}
+			// Ollama also stops to load or unload a model, which says
+			// nothing about the response.
+			match chunk.done_reason.as_deref()
+			{
+				Some("stop") =>
+				{
+					chunks.push(CompletionChunk::Finish(FinishReason::Stop))
+				},
+				Some("length") =>
+				{
+					chunks.push(CompletionChunk::Finish(FinishReason::Length))
+				},
+				_ =>
+				{}
+			}
+			Ok(stream::iter(chunks.into_iter().map(Ok)))
+		})
+		.try_flatten()
+		.boxed())
+}
+
+/// Create the body of a request to Ollama's chat endpoint.
+///
+/// # Arguments
+///
+/// - `request`: The completion request.
+///
+/// # Returns
+///
+/// The body, as JSON.
+fn ollama_request(request: CompletionRequest) -> Result<Value, BackendError>
+{
+	let CompletionRequest {
+		params,
+		messages,
+		json_mode,
+		tools
+	} = request;
+	let messages = messages
+		.into_iter()
+		.map(|message| {
+			let tool_calls = message
+				.tool_calls
+				.into_iter()
+				.map(|call| {
+					// Some models send no arguments at all for a tool without
+					// parameters.
+					let arguments = match call.arguments.trim()
+					{
+						"" => Value::Object(Map::new()),
+						arguments => serde_json::from_str(arguments)
+							.map_err(|e| BackendError::Request(e.to_string()))?
+					};
+					Ok(OllamaToolCall {
+						function: OllamaFunction {
+							name: call.name,
+							arguments
+						}
+					})
+				})
+				.collect::<Result<Vec<_>, BackendError>>()?;
+			let role = match message.role
+			{
+				Role::Assistant => "assistant",
+				Role::System => "system",
+				Role::User => "user",
+				Role::Tool => "tool"
+			};
+			Ok(OllamaMessage {
+				role: role.to_string(),
+				content: message.content,
+				tool_calls
+			})
+		})
+		.collect::<Result<Vec<_>, BackendError>>()?;
+	let mut options = Map::new();
+	if let Some(max_tokens) = params.max_tokens
+	{
+		options.insert("num_predict".into(), json!(max_tokens));
+	}
+	if let Some(temperature) = params.temperature
+	{
+		options.insert("temperature".into(), json!(temperature));
+	}
+	if let Some(top_p) = params.top_p
+	{
+		options.insert("top_p".into(), json!(top_p));
+	}
+	if !params.stop.is_empty()
+	{
+		options.insert("stop".into(), json!(params.stop));
+	}
+	if let Some(seed) = params.seed
+	{
+		options.insert("seed".into(), json!(seed));
+	}
+	if let Some(presence_penalty) = params.presence_penalty
+	{
+		options.insert("presence_penalty".into(), json!(presence_penalty));
+	}
+	if let Some(frequency_penalty) = params.frequency_penalty
+	{
+		options.insert("frequency_penalty".into(), json!(frequency_penalty));
+	}
+	let mut body = json!({
+		"model": params.model.unwrap_or_default(),
+		"messages": messages,
+		"stream": true,
+		"options": options
+	});
+	if json_mode
+	{
+		body["format"] = json!("json");
+	}
+	if !tools.is_empty()
+	{
+		body["tools"] = tools
+			.into_iter()
+			.map(|tool| {
+				json!({
+					"type": "function",
+					"function": {
+						"name": tool.name,
+						"description": tool.description,
+						"parameters": tool.parameters
+					}
+				})
+			})
+			.collect();
+	}
+	Ok(body)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              HTTP transport.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Classify the specified HTTP error. Failures to reach the server make the
+/// backend [unavailable](BackendError::Unavailable).
+fn http_error(error: reqwest::Error) -> BackendError
+{
+	match error.is_connect() || error.is_timeout()
+	{
+		true => BackendError::Unavailable(error.to_string()),
+		false => BackendError::Backend(error.to_string())
+	}
+}
+
+/// The lines of the body of a streamed response, each with its line feed, if
+/// any.
+pub(super) type ResponseLines =
+	BoxStream<'static, Result<Vec<u8>, BackendError>>;
+
+/// Split the body of the specified response into lines, as it arrives. The
+/// lines do not necessarily align with the chunks of the body.
+///
+/// # Arguments
+///
+/// - `response`: The response.
+///
+/// # Returns
+///
+/// The stream of lines.
+fn response_lines(response: reqwest::Response) -> ResponseLines
+{
+	stream::try_unfold(
+		(response.bytes_stream(), Vec::<u8>::new()),
+		|(mut bytes, mut buffer)| async move {
+			loop
+			{
+				if let Some(end) = buffer.iter().position(|b| *b == b'\n')
+				{
+					let line = buffer.drain(..=end).collect::<Vec<_>>();
+					return Ok(Some((line, (bytes, buffer))))
+				}
+				match bytes.next().await
+				{
+					Some(chunk) =>
+					{
+						buffer.extend_from_slice(&chunk.map_err(|e| {
+							BackendError::Response(e.to_string())
+						})?)
+					},
+					None if buffer.is_empty() => return Ok(None),
+					None =>
+					{
+						let line = std::mem::take(&mut buffer);
+						return Ok(Some((line, (bytes, buffer))))
+					}
+				}
+			}
+		}
+	)
+	.boxed()
+}
+
+/// Read the rest of the body of a response, for an error message. The body of
+/// an error is short, and any part of it is better than nothing, so a failure
+/// to read it just ends it.
+///
+/// # Arguments
+///
+/// - `lines`: The lines of the body.
+///
+/// # Returns
+///
+/// The body, as text.
+async fn response_body(lines: ResponseLines) -> String
+{
+	let body = lines
+		.take_while(|line| std::future::ready(line.is_ok()))
+		.filter_map(|line| std::future::ready(line.ok()))
+		.concat()
+		.await;
+	String::from_utf8_lossy(&body).trim().to_string()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The default base URL of the Ollama server.
+const OLLAMA_URL: &str = "http://localhost:11434";
+
+/// The name of the backend that the environment describes, when there is no
+/// `BACKENDS_PATH` file.
+const DEFAULT_BACKEND: &str = "default";
+
+/// How long a backend that failed to connect stays unhealthy.
+const UNHEALTHY_DURATION: Duration = Duration::from_secs(30);
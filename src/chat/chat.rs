@@ -1,33 +1,68 @@
 use codee::binary::BincodeSerdeCodec;
-use leptos::{html, prelude::*, server::LocalResource};
+use leptos::{html, prelude::*, server::LocalResource, task::spawn_local};
 use leptos_use::{
 	UseClipboardReturn, UseWebSocketReturn, core::ConnectionReadyState,
 	use_clipboard, use_websocket
 };
 use log::{debug, trace};
-use std::time::Duration;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	str::FromStr,
+	time::Duration
+};
 use uuid::Uuid;
 
-use crate::chat::{AppMessage, Message, Role};
+use crate::{
+	chat::{
+		AppMessage, ChatRequest, Conversation, ConversationSettings,
+		ConversationSummary, ConversationsChanged, GenerationParams, JsonView,
+		Markdown, Message, MessageTree, PromptInfo, Role, StreamingMarkdown,
+		TokenCounts, Usage, World, WorldPanel, parse_output
+	},
+	error_template::AppError
+};
 
 use super::icons::{
 	AssistantImage, CopiedImage, CopyImage, DeleteImage, EditImage,
-	RegenerateImage, RewindImage, SystemImage, UserImage
+	RegenerateImage, RewindImage, StopImage, SystemImage, UserImage
 };
 
 ////////////////////////////////////////////////////////////////////////////////
 //                              Chat utilities.                               //
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Find the index of the item with the specified identifier.
-fn to_index<I, T>(id: Uuid, iter: &I) -> Option<usize>
-where
-	for<'i> &'i I: IntoIterator<Item = &'i (Uuid, T)>
+/// Parse a generation parameter from the content of a settings field. Answer
+/// `None`, i.e., use the server's default, if the field is empty or invalid.
+fn parse_param<T: FromStr>(value: &str) -> Option<T>
+{
+	value.trim().parse().ok()
+}
+
+/// Render an optional generation parameter as the content of a settings field.
+fn show_param<T: ToString>(value: Option<T>) -> String
+{
+	value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Parse stop sequences from the content of a settings field: one sequence per
+/// line, with `\n` and `\t` standing for line feed and tab, respectively.
+fn parse_stop(value: &str) -> Vec<String>
 {
-	iter.into_iter()
-		.enumerate()
-		.find(|(_, (i, _))| *i == id)
-		.map(|(index, _)| index)
+	value
+		.lines()
+		.filter(|line| !line.is_empty())
+		.map(|line| line.replace("\\n", "\n").replace("\\t", "\t"))
+		.collect()
+}
+
+/// Render stop sequences as the content of a settings field. This is the
+/// inverse of [`parse_stop`].
+fn show_stop(stop: &[String]) -> String
+{
+	stop.iter()
+		.map(|stop| stop.replace('\n', "\\n").replace('\t', "\\t"))
+		.collect::<Vec<_>>()
+		.join("\n")
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -35,37 +70,196 @@ where
 ////////////////////////////////////////////////////////////////////////////////
 
 /// A complete interactive chat with an AI assistant.
+///
+/// # Arguments
+///
+/// * `conversation_id` - Specifies the conversation. If the conversation was
+///   saved previously, then its history is loaded from storage. Every change to
+///   the history is saved, except that a new conversation is not saved until it
+///   contains more than just the system message.
 #[component]
-pub fn Chat() -> impl IntoView
+pub fn Chat(conversation_id: Uuid) -> impl IntoView
 {
-	// The system message. We need to use a local resource in order to read this
-	// signal in an effect.
-	let system_message =
-		LocalResource::new(|| async move { system_message(None).await });
+	// The initial history and settings: either those of the saved
+	// conversation, or just the default system message for a new conversation.
+	// Also answers whether the conversation was saved previously. We need to
+	// use a local resource in order to read this signal in an effect.
+	let history = LocalResource::new(move || async move {
+		match load_conversation(conversation_id).await?
+		{
+			Some(conversation) =>
+			{
+				Ok((true, conversation.tree, conversation.settings))
+			},
+			None =>
+			{
+				let message =
+					system_message(None, BTreeMap::new(), None).await?;
+				Ok::<_, ServerFnError>((
+					false,
+					MessageTree::with_root(message),
+					ConversationSettings::default()
+				))
+			}
+		}
+	});
 	// An invisible component that we can scroll into view to ensure that the
 	// last message, even if incomplete, is always at the bottom.
 	let bottom = NodeRef::<html::Div>::new();
-	// The messages.
-	let (messages, set_messages) = signal(Vec::<(Uuid, Message)>::new());
-	Effect::new(move |_| {
-		if let Some(message) = system_message.get()
+	// The message tree, including every alternative branch.
+	let (tree, set_tree) = signal(MessageTree::default());
+	// The messages along the active branch, i.e., the visible history.
+	let messages = Memo::new(move |_| tree.with(MessageTree::active_branch));
+	// The settings that shape the conversation.
+	let (settings, set_settings) = signal(ConversationSettings::default());
+	// The prompts in the server's prompt library. We need to use a local
+	// resource in order to read this signal in a closure.
+	let library =
+		LocalResource::new(|| async move { list_system_prompts().await });
+	let prompts = Signal::derive(move || {
+		library
+			.get()
+			.and_then(|prompts| prompts.as_ref().ok().cloned())
+			.unwrap_or_default()
+	});
+	// The names of the server's chat backends, the default first.
+	let backends = LocalResource::new(|| async move { list_backends().await });
+	let backends = Signal::derive(move || {
+		backends
+			.get()
+			.and_then(|backends| backends.as_ref().ok().cloned())
+			.unwrap_or_default()
+	});
+	// The chosen backend, or `None` for the server's default.
+	let backend = Signal::derive(move || settings().backend);
+	// The chosen prompt, if it is in the library.
+	let prompt = Signal::derive(move || {
+		let chosen = settings().prompt;
+		prompts().into_iter().find(|info| match &chosen
 		{
-			if let Ok(message) = &*message
+			Some(chosen) => *chosen == info.id,
+			None => info.is_default
+		})
+	});
+	// The model world, if the chosen prompt drives one. The world follows the
+	// active branch, so it is rebuilt whenever the visible history changes.
+	let world = LocalResource::new(move || {
+		let drives_world = prompt().is_some_and(|prompt| prompt.world);
+		let messages = messages()
+			.into_iter()
+			.map(|(_, message)| message)
+			.collect::<Vec<_>>();
+		async move {
+			match drives_world
 			{
-				set_messages.update(|messages| {
-					messages.push((Uuid::new_v4(), message.clone()));
-				});
+				true => world_state(messages).await.ok(),
+				false => None
 			}
 		}
 	});
+	let world = Signal::derive(move || world.get().and_then(|w| (*w).clone()));
+	// The token counts of the visible history, relative to the model's context
+	// window, keyed by message. The chosen backend does the counting.
+	let token_counts = LocalResource::new(move || {
+		let (ids, messages): (Vec<_>, Vec<_>) = messages().into_iter().unzip();
+		let backend = backend();
+		async move {
+			count_tokens(messages, backend)
+				.await
+				.ok()
+				.map(|counts| (ids, counts))
+		}
+	});
+	let message_tokens = Signal::derive(move || {
+		token_counts
+			.get()
+			.and_then(|counts| (*counts).clone())
+			.map(|(ids, counts)| {
+				ids.into_iter()
+					.zip(counts.counts)
+					.collect::<BTreeMap<_, _>>()
+			})
+			.unwrap_or_default()
+	});
+	let token_counts = Signal::derive(move || {
+		token_counts
+			.get()
+			.and_then(|counts| (*counts).clone())
+			.map(|(_, counts)| counts)
+	});
+	// Whether the chosen prompt has changed on disk since the system message
+	// was rendered.
+	let (prompt_changed, set_prompt_changed) = signal(false);
+	// Whether the conversation has been saved.
+	let (saved, set_saved) = signal(false);
+	Effect::new(move |_| {
+		if let Some(history) = history.get()
+			&& let Ok((was_saved, history, settings)) = &*history
+		{
+			set_saved(*was_saved);
+			set_tree(history.clone());
+			set_settings(settings.clone());
+		}
+	});
+	// How to save the history. Notifies the sidebar, so that it can refresh its
+	// list of conversations.
+	let changed = use_context::<ConversationsChanged>();
+	let save = move || {
+		if !saved.get_untracked()
+			&& messages
+				.get_untracked()
+				.iter()
+				.all(|(_, m)| m.role == Role::System)
+		{
+			return
+		}
+		set_saved(true);
+		let tree = tree.get_untracked();
+		let settings = settings.get_untracked();
+		spawn_local(async move {
+			match save_conversation(conversation_id, tree, settings).await
+			{
+				Ok(summary) =>
+				{
+					trace!("Saved conversation: {summary:?}");
+					if let Some(ConversationsChanged(changed)) = changed
+					{
+						changed.notify();
+					}
+				},
+				Err(e) => debug!("Failed to save conversation: {e}")
+			}
+		});
+	};
 	// The user's latest incomplete message.
 	let (user_message, set_user_message) = signal(String::new());
 	// The assistant's latest incomplete message.
 	let (assistant_message, set_assistant_message) = signal(String::new());
 	// Which message the user is editing, is any.
 	let (editing, set_editing) = signal(None::<Uuid>);
+	// The identifier of the chat request in progress, if any. Replies to any
+	// other request are stale, and must be discarded.
+	let (request, set_request) = signal(None::<Uuid>);
+	// The identifiers of the messages sent with the latest chat request, in
+	// order, so that the server can refer to them by index.
+	let (sent, set_sent) = signal(Vec::<Uuid>::new());
+	// The messages that the server left out of the assistant's context for
+	// the latest chat request, and whether it summarized them instead.
+	let (excluded, set_excluded) = signal(BTreeSet::<Uuid>::new());
+	let (summarized, set_summarized) = signal(false);
+	// Whether to show the notice about the messages left out.
+	let (context_trimmed, set_context_trimmed) = signal(false);
+	// The number of tokens that the latest chat request used.
+	let (usage, set_usage) = signal(None::<Usage>);
 	// Whether the assistant is busy generating the next message.
-	let (pending, set_pending) = signal(false);
+	let pending = move || request().is_some();
+	// Whether the request in progress is waiting for the assistant to become
+	// available.
+	let (queued, set_queued) = signal(false);
+	// The error that concluded the latest request, if any.
+	let (error, set_error) = signal(None::<AppError>);
+	// The parameters that control how the assistant generates its messages.
+	let (params, set_params) = signal(GenerationParams::default());
 	// Whether to disable message-specific actions in the user interface.
 	let disabled = pending;
 	let UseWebSocketReturn {
@@ -83,14 +277,70 @@ pub fn Chat() -> impl IntoView
 	// How to obtain the next message from the assistant. Accepts the
 	// complete message history, which must already contain the user's latest
 	// message.
-	let chat = move |messages: &Vec<(Uuid, Message)>| {
-		set_pending(true);
-		let messages = messages
-			.iter()
-			.map(|(_, message)| message.clone())
-			.collect::<Vec<_>>();
-		trace!("Sending messages: {:#?}", messages);
-		send(&AppMessage::StartChat(messages));
+	let chat = {
+		let send = send.clone();
+		move |messages: &Vec<(Uuid, Message)>| {
+			let id = Uuid::new_v4();
+			set_request(Some(id));
+			set_error(None);
+			set_sent(messages.iter().map(|(id, _)| *id).collect());
+			set_excluded(BTreeSet::new());
+			set_context_trimmed(false);
+			let messages = messages
+				.iter()
+				.map(|(_, message)| message.clone())
+				.collect::<Vec<_>>();
+			trace!("Sending messages: {id}: {:#?}", messages);
+			send(&AppMessage::StartChat(ChatRequest {
+				id,
+				messages,
+				prompt: settings.get_untracked().prompt,
+				params: params.get_untracked(),
+				backend: settings.get_untracked().backend
+			}));
+		}
+	};
+	// How to cancel the assistant's message in progress. The assistant answers
+	// with `ChatCancelled`, which concludes the message.
+	let cancel = move || {
+		if let Some(id) = request.get_untracked()
+		{
+			trace!("Cancelling chat: {id}");
+			send(&AppMessage::CancelChat(id));
+		}
+	};
+	// How to conclude the assistant's latest message, whether it completed
+	// normally or was cancelled. Clears `pending` and updates the history with
+	// the concluded message. Scrolls the history to the bottom.
+	let conclude = move || {
+		set_request(None);
+		set_queued(false);
+		let complete = set_assistant_message
+			.try_update(|message| {
+				let trimmed = message.trim();
+				let complete = Role::Assistant.message(trimmed.into());
+				message.clear();
+				complete
+			})
+			.unwrap();
+		// Sometimes the assistant declines to create more content. This is
+		// fine, but we don't want to add an empty message to the history. If
+		// the assistant was regenerating a message, then restore the message
+		// that it was supposed to replace.
+		set_tree.update(move |tree| {
+			if !complete.content.is_empty()
+			{
+				tree.append(complete);
+			}
+			else if tree.can_resume()
+			{
+				tree.resume();
+			}
+			trace!("History: {:#?}", tree.active_branch());
+		});
+		save();
+		let bottom = bottom.get().unwrap();
+		bottom.scroll_into_view_with_bool(false);
 	};
 	// How to update the history with the next message from the assistant. Also
 	// scrolls the history to the bottom.
@@ -98,48 +348,127 @@ pub fn Chat() -> impl IntoView
 		// Read the next message from the websocket.
 		if let Some(message) = message()
 		{
+			// The prompt library changed on disk, so refresh it. Offer to
+			// apply the change if it affects the chosen prompt.
+			if let AppMessage::SystemPromptChanged(id) = &message
+			{
+				trace!("System prompt changed: {id}");
+				if prompt
+					.with_untracked(|p| p.as_ref().is_some_and(|p| p.id == *id))
+				{
+					set_prompt_changed(true);
+				}
+				library.refetch();
+				return
+			}
+			// Discard replies to any request other than the one in progress,
+			// e.g., late fragments from a cancelled request.
+			if message.request_id() != request.get_untracked()
+			{
+				debug!("Discarding stale message: {:?}", message);
+				return
+			}
 			match message
 			{
+				// The assistant is busy, but will get to the request later.
+				AppMessage::ChatQueued(_) =>
+				{
+					trace!("Chat queued");
+					set_queued(true);
+				},
 				// Update the assistant's latest incomplete message with the
 				// new fragment.
-				AppMessage::NextChatFragment(fragment) =>
+				AppMessage::NextChatFragment(_, fragment) =>
 				{
 					trace!("Received fragment: {fragment}");
+					set_queued(false);
 					set_assistant_message.update(|m| {
 						m.push_str(&fragment);
 					});
 					let bottom = bottom.get().unwrap();
 					bottom.scroll_into_view_with_bool(false);
 				},
-				// The chat completion is done. Clear `pending` and update
-				// the history with the completed message. Scroll the
-				// history to the bottom.
-				AppMessage::ChatCompleted =>
+				// The assistant's response did not match the prompt's schema,
+				// so the assistant is starting over.
+				AppMessage::ChatRetried(_) =>
 				{
-					trace!("Chat completed");
-					set_pending(false);
-					let complete = set_assistant_message
+					trace!("Chat retried");
+					set_assistant_message(String::new());
+				},
+				// The assistant called some tools. Whatever it said so far
+				// belongs with the calls, so add both to the history.
+				AppMessage::ToolCalls(_, calls) =>
+				{
+					trace!("Tool calls: {:#?}", calls);
+					set_queued(false);
+					let content = set_assistant_message
 						.try_update(|message| {
-							let trimmed = message.trim();
-							let complete =
-								Role::Assistant.message(trimmed.into());
+							let trimmed = message.trim().to_string();
 							message.clear();
-							complete
+							trimmed
 						})
 						.unwrap();
-					// Sometimes the assistant declines to create more
-					// content. This is fine, but we don't want to add an
-					// empty message to the history.
-					if !complete.content.is_empty()
-					{
-						set_messages.update(move |messages| {
-							messages.push((Uuid::new_v4(), complete));
-							trace!("History: {messages:#?}");
+					set_tree.update(move |tree| {
+						tree.append(Message {
+							tool_calls: calls,
+							..Role::Assistant.message(content)
 						});
-					}
+					});
+				},
+				// A tool answered one of the assistant's calls.
+				AppMessage::ToolResult(_, message) =>
+				{
+					trace!("Tool result: {:#?}", message);
+					set_tree.update(move |tree| {
+						tree.append(message);
+					});
 					let bottom = bottom.get().unwrap();
 					bottom.scroll_into_view_with_bool(false);
 				},
+				// The conversation outgrew the assistant's context, so the
+				// server left out some of the oldest messages.
+				AppMessage::ContextTrimmed(_, indices, summary) =>
+				{
+					trace!("Context trimmed: {:?}", indices);
+					let ids = sent.with_untracked(|sent| {
+						indices
+							.iter()
+							.filter_map(|index| sent.get(*index).copied())
+							.collect()
+					});
+					set_excluded(ids);
+					set_summarized(summary);
+					set_context_trimmed(true);
+				},
+				// The assistant is done, and the server has tallied the tokens
+				// that the chat used.
+				AppMessage::ChatUsage(_, tallied) =>
+				{
+					trace!("Chat usage: {:?}", tallied);
+					set_usage(Some(tallied));
+				},
+				// The chat completion is done.
+				AppMessage::ChatCompleted(_) =>
+				{
+					trace!("Chat completed");
+					conclude();
+				},
+				// The chat completion was cancelled by the user. Keep
+				// whatever the assistant managed to say before it stopped.
+				AppMessage::ChatCancelled(_) =>
+				{
+					trace!("Chat cancelled");
+					conclude();
+				},
+				// The chat completion failed or was rejected. Keep whatever
+				// the assistant managed to say, and tell the user what went
+				// wrong.
+				AppMessage::Error(_, e) =>
+				{
+					debug!("Chat failed: {e}");
+					conclude();
+					set_error(Some(e));
+				},
 				unexpected =>
 				{
 					debug!("Unexpected message: {:?}", unexpected);
@@ -149,7 +478,8 @@ pub fn Chat() -> impl IntoView
 	});
 	// How to identify the last message in the history.
 	let last_message = move || messages().last().map(|(id, _)| *id);
-	// How to regenerate am assistant message.
+	// How to regenerate an assistant message. The new message becomes an
+	// alternative to the old one.
 	let regenerate = {
 		let chat = chat.clone();
 		move |id| {
@@ -158,10 +488,7 @@ pub fn Chat() -> impl IntoView
 				{
 					let chat = chat.clone();
 					Some(move |id| {
-						set_messages.update(|messages| {
-							let index = to_index(id, messages).unwrap();
-							messages.remove(index);
-						});
+						set_tree.update(|tree| tree.rewind_before(id));
 						chat(&messages());
 					})
 				}
@@ -172,7 +499,8 @@ pub fn Chat() -> impl IntoView
 			})
 		}
 	};
-	// How to rewind the conversation to the specified message.
+	// How to rewind the conversation to the specified message. The subsequent
+	// messages are retained, and become an alternative to whatever comes next.
 	let rewind = move |id| {
 		Signal::derive(move || {
 			if Some(id) == last_message()
@@ -182,65 +510,226 @@ pub fn Chat() -> impl IntoView
 			else
 			{
 				Some(move |id| {
-					set_messages.update(|messages| {
-						let index = to_index(id, messages).unwrap();
-						messages.truncate(index + 1);
-					});
+					set_tree.update(|tree| tree.rewind(id));
+					save();
 				})
 			}
 		})
 	};
 
+	// How to switch between the alternatives to the specified message.
+	let switch = move |id, offset| {
+		set_tree.update(|tree| tree.switch(id, offset));
+		save();
+	};
+	// How to render the system message from the specified prompt and
+	// variables. The rendered prompt replaces the system message, keeping the
+	// rest of the history.
+	let render_prompt =
+		move |prompt: Option<String>, variables: BTreeMap<String, String>| {
+			spawn_local(async move {
+				match system_message(prompt.clone(), variables.clone(), None)
+					.await
+				{
+					Ok(message) =>
+					{
+						set_tree.update(|tree| {
+							tree.set_system(message);
+						});
+						set_settings.update(|settings| {
+							settings.prompt = prompt;
+							settings.variables = variables;
+						});
+						set_prompt_changed(false);
+						save();
+					},
+					Err(e) => debug!("Failed to render system prompt: {e}")
+				}
+			});
+		};
+	// How to swap the system prompt.
+	let swap_prompt = move |prompt: String| {
+		render_prompt(Some(prompt), settings.get_untracked().variables);
+	};
+	// How to fill in the variables of the system prompt.
+	let configure_prompt = move |variables: BTreeMap<String, String>| {
+		render_prompt(settings.get_untracked().prompt, variables);
+	};
+	// How to choose the backend. The models differ between backends, so the
+	// chosen model reverts to the default.
+	let swap_backend = move |backend: String| {
+		set_settings.update(|settings| settings.backend = Some(backend));
+		set_params.update(|params| params.model = None);
+		save();
+	};
+
 	view! {
 		<div class="h-screen flex flex-col">
-			<div class="overflow-y-auto flex-grow">
-				<Transition fallback=move || view! {
-					<div class="mx-auto h-64 w-2/3">
-						<div class="skeleton h-full w-full"></div>
-					</div>
-				}>
-					{move || {
-						let _ = system_message.get();
-					}}
-				</Transition>
-				<For
-					each=messages
-					key=move |(id, _)| *id
-					children={
-						move |(id, message)| view! {
-							<ChatMessage
-								id=id
-								message=message
-								disabled=disabled
-								editing=editing
-								set_editing=set_editing
-								edit=move |id, content| {
-									set_messages.update(|messages| {
-										let index = to_index(id, messages).unwrap();
-										let (id, message) = &mut messages[index];
-										*id = Uuid::new_v4();
-										message.content = content;
-									});
-								}
-								regenerate={regenerate.clone()(id)}
-								rewind={rewind(id)}
-								delete=move |id| {
-									set_messages.update(|messages| {
-										let index = to_index(id, messages)
-											.unwrap();
-										messages.remove(index);
-									});
-								}
-							/>
+			<div class="navbar flex-none bg-base-200">
+				<div class="flex-1">
+					<label
+						for="sidebar"
+						class="btn btn-ghost btn-sm drawer-button lg:hidden"
+					>
+						"☰"
+					</label>
+					<span class="text-lg font-bold">"Chat Base"</span>
+				</div>
+				<div class="flex flex-none items-center gap-2">
+					<PromptPicker
+						prompts=prompts
+						prompt=Signal::derive(move || settings().prompt)
+						disabled=disabled
+						select=swap_prompt
+					/>
+					<BackendPicker
+						backends=backends
+						backend=backend
+						select=swap_backend
+					/>
+					<ModelPicker
+						backend=backend
+						params=params
+						set_params=set_params
+					/>
+				</div>
+			</div>
+			<div class="flex flex-grow min-h-0">
+				<div class="overflow-y-auto flex-grow">
+					<Transition fallback=move || view! {
+						<div class="mx-auto h-64 w-2/3">
+							<div class="skeleton h-full w-full"></div>
+						</div>
+					}>
+						{move || {
+							let _ = history.get();
+						}}
+					</Transition>
+					<For
+						each=messages
+						key=move |(id, _)| *id
+						children={
+							move |(id, message)| view! {
+								<div
+									class:opacity-50=move || {
+										excluded.with(|e| e.contains(&id))
+									}
+									title=move || {
+										excluded
+											.with(|e| e.contains(&id))
+											.then_some(
+												"Left out of the assistant's \
+												context"
+											)
+									}
+								>
+									<ChatMessage
+										id=id
+										message=message
+										disabled=disabled
+										editing=editing
+										set_editing=set_editing
+										edit=move |id, content| {
+											let edited = tree.with_untracked(|tree| {
+												tree.get(id)
+													.filter(|m| m.content != content)
+													.map(|m| m.role.message(content))
+											});
+											if let Some(edited) = edited
+											{
+												set_tree.update(|tree| {
+													if edited.role == Role::System
+													{
+														// The edited system message
+														// replaces the original, so
+														// that it applies to every
+														// branch.
+														tree.set_system(edited);
+													}
+													else
+													{
+														// The edited message becomes an
+														// alternative to the original.
+														tree.add_sibling(id, edited);
+													}
+												});
+												save();
+											}
+										}
+										regenerate={regenerate.clone()(id)}
+										rewind={rewind(id)}
+										delete=move |id| {
+											set_tree.update(|tree| tree.remove(id));
+											save();
+										}
+										branch={Signal::derive(move || {
+											tree.with(|tree| tree.siblings(id))
+										})}
+										switch=switch
+										prompt=prompt
+										variables=Signal::derive(move || {
+											settings().variables
+										})
+										configure=configure_prompt
+										tokens=Signal::derive(move || {
+											message_tokens
+												.with(|t| t.get(&id).copied())
+										})
+									/>
+								</div>
+							}
 						}
-					}
-				/>
-				<Show when=pending>
-					<IncompleteAssistantMessage message=assistant_message />
+					/>
+					<Show when=move || !pending() && tree.with(MessageTree::can_resume)>
+						<div class="flex justify-center">
+							<button
+								type="button"
+								class="btn btn-ghost btn-sm"
+								on:click=move |_| {
+									set_tree.update(MessageTree::resume);
+									save();
+								}
+							>
+								"Restore rewound messages"
+							</button>
+						</div>
+					</Show>
+					<Show when=pending>
+						<IncompleteAssistantMessage
+							message=assistant_message
+							queued=queued
+						/>
+					</Show>
+					<div node_ref=bottom class="h-4"></div>
+				</div>
+				<Show when=move || world().is_some()>
+					<div class="hidden lg:block w-80 flex-none overflow-y-auto p-2">
+						<WorldPanel world=Signal::derive(move || {
+							world().unwrap_or_default()
+						})/>
+					</div>
 				</Show>
-				<div node_ref=bottom class="h-4"></div>
 			</div>
 			<div class="flex-none mt-4 mb-8">
+				<GenerationSettings params=params set_params=set_params/>
+				<Show when=prompt_changed>
+					<PromptChangedAlert
+						apply=move || {
+							configure_prompt(settings.get_untracked().variables)
+						}
+						dismiss=move || set_prompt_changed(false)
+					/>
+				</Show>
+				<Show when=context_trimmed>
+					<ContextTrimmedAlert
+						count=Signal::derive(move || excluded.with(BTreeSet::len))
+						summarized=summarized
+						dismiss=move || set_context_trimmed(false)
+					/>
+				</Show>
+				{move || error().map(|e| view! {
+					<ChatErrorAlert error=e dismiss=move || set_error(None)/>
+				})}
 				<form on:submit={
 					let chat = chat.clone();
 					move |ev| {
@@ -259,13 +748,11 @@ pub fn Chat() -> impl IntoView
 							if trimmed.is_some()
 							{
 								// Add the untrimmed message to the history.
-								set_messages.update(|messages| {
-									messages.push((
-										Uuid::new_v4(),
-										Role::User.message(message)
-									))
+								set_tree.update(|tree| {
+									tree.append(Role::User.message(message));
 								});
 								set_user_message(String::new());
+								save();
 							}
 							// Allow the assistant to generate the next message,
 							// even if the user didn't enter a message.
@@ -282,33 +769,389 @@ pub fn Chat() -> impl IntoView
 						</div>
 					}>
 					{
-						// We don't need the system message, but we do want to
-						// ghost the input while the system message is loading.
-						let _ = system_message.get();
+						// We don't need the history, but we do want to ghost the
+						// input while the history is loading.
+						let _ = history.get();
 						view! {
 							<div class="flex justify-center">
-								<input
-									id="user_message"
-									type="text"
-									placeholder="Type a message…"
-									on:input=move |ev| {
-										set_user_message(event_target_value(&ev))
+								<Show
+									when=pending
+									fallback=move || view! {
+										<input
+											id="user_message"
+											type="text"
+											placeholder="Type a message…"
+											on:input=move |ev| {
+												set_user_message(
+													event_target_value(&ev)
+												)
+											}
+											prop:value=user_message
+											class="w-5/6"
+											autofocus
+										/>
 									}
-									prop:value=user_message
-									class="w-5/6"
-									autofocus
-								/>
+								>
+									<StopButton click=cancel.clone()/>
+								</Show>
 							</div>
 						}
 					}
 					</Transition>
 					<input type="submit" hidden/>
 				</form>
+				<ContextMeter counts=token_counts usage=usage/>
+			</div>
+		</div>
+	}
+}
+
+/// Represents a picker for the system prompt of the conversation. The choices
+/// are the prompts in the server's prompt library. Hidden if the library is
+/// empty.
+///
+/// # Arguments
+///
+/// * `prompts` - Specifies the prompts in the server's prompt library.
+/// * `prompt` - Specifies the chosen prompt, or `None` for the server's
+///   default.
+/// * `disabled` - Indicates whether the picker should be disabled.
+/// * `select` - A function that swaps the system prompt, it accepts the
+///   identifier of the chosen prompt.
+#[component]
+pub fn PromptPicker<D, S>(
+	prompts: Signal<Vec<PromptInfo>>,
+	prompt: Signal<Option<String>>,
+	disabled: D,
+	select: S
+) -> impl IntoView
+where
+	D: Fn() -> bool + Clone + Send + Sync + 'static,
+	S: Fn(String) + Clone + Send + Sync + 'static
+{
+	view! {
+		<Show when=move || !prompts().is_empty()>
+			<select
+				class="select select-bordered select-sm"
+				disabled={
+					let disabled = disabled.clone();
+					move || disabled()
+				}
+				on:change={
+					let select = select.clone();
+					move |ev| select(event_target_value(&ev))
+				}
+			>
+				{move || {
+					let selected = prompt();
+					prompts()
+						.into_iter()
+						.map(|info| {
+							let is_selected = match &selected
+							{
+								Some(selected) => *selected == info.id,
+								None => info.is_default
+							};
+							view! {
+								<option
+									value=info.id
+									title=info.description
+									selected=is_selected
+								>
+									{info.name}
+								</option>
+							}
+						})
+						.collect_view()
+				}}
+			</select>
+		</Show>
+	}
+}
+
+/// Represents a picker for the backend that generates the assistant's
+/// messages, i.e., one of the server's configured LLM servers. Hidden unless
+/// the server has several backends.
+///
+/// # Arguments
+///
+/// * `backends` - Specifies the names of the server's backends, the default
+///   first.
+/// * `backend` - Specifies the chosen backend, or `None` for the server's
+///   default.
+/// * `select` - A function that swaps the backend, it accepts the name of the
+///   chosen backend.
+#[component]
+pub fn BackendPicker<S>(
+	backends: Signal<Vec<String>>,
+	backend: Signal<Option<String>>,
+	select: S
+) -> impl IntoView
+where
+	S: Fn(String) + Clone + Send + Sync + 'static
+{
+	view! {
+		<Show when=move || { backends().len() > 1 }>
+			<select
+				class="select select-bordered select-sm"
+				on:change={
+					let select = select.clone();
+					move |ev| select(event_target_value(&ev))
+				}
+			>
+				{move || {
+					let backends = backends();
+					let selected = backend()
+						.filter(|backend| backends.contains(backend))
+						.or_else(|| backends.first().cloned());
+					backends
+						.into_iter()
+						.map(|name| {
+							let is_selected = selected.as_ref() == Some(&name);
+							let value = name.clone();
+							view! {
+								<option value=value selected=is_selected>
+									{name}
+								</option>
+							}
+						})
+						.collect_view()
+				}}
+			</select>
+		</Show>
+	}
+}
+
+/// Represents a picker for the model that generates the assistant's messages.
+/// The choices are the models reported by the chosen backend, plus the
+/// server's default model.
+///
+/// # Arguments
+///
+/// * `backend` - Specifies the chosen backend, or `None` for the server's
+///   default.
+/// * `params` - Specifies the generation parameters, which include the model.
+/// * `set_params` - Updates the generation parameters.
+#[component]
+pub fn ModelPicker(
+	backend: Signal<Option<String>>,
+	params: ReadSignal<GenerationParams>,
+	set_params: WriteSignal<GenerationParams>
+) -> impl IntoView
+{
+	// The available models. We need to use a local resource in order to read
+	// this signal in a closure.
+	let models = LocalResource::new(move || {
+		let backend = backend();
+		async move { list_models(backend).await }
+	});
+	view! {
+		<div class="flex items-center gap-2">
+			<select
+				class="select select-bordered select-sm"
+				on:change=move |ev| {
+					let model = event_target_value(&ev);
+					set_params.update(|p| {
+						p.model = Some(model).filter(|model| !model.is_empty())
+					});
+				}
+			>
+				<option value="" selected=move || params().model.is_none()>
+					"Default model"
+				</option>
+				{move || {
+					let selected = params().model;
+					let mut models = models
+						.get()
+						.and_then(|models| models.as_ref().ok().cloned())
+						.unwrap_or_default();
+					// Keep the selection visible even if the backend no longer
+					// reports it, e.g., because it was unloaded.
+					if let Some(model) = &selected
+						&& !models.contains(model)
+					{
+						models.push(model.clone());
+					}
+					models
+						.into_iter()
+						.map(|model| {
+							let is_selected = selected.as_ref() == Some(&model);
+							let value = model.clone();
+							view! {
+								<option
+									value=value
+									selected=is_selected
+								>
+									{model}
+								</option>
+							}
+						})
+						.collect_view()
+				}}
+			</select>
+			<div class="tooltip tooltip-bottom" data-tip="Refresh models">
+				<button
+					type="button"
+					class="btn btn-ghost btn-sm"
+					on:click=move |_| models.refetch()
+				>
+					"↻"
+				</button>
+			</div>
+		</div>
+	}
+}
+
+/// Represents a settings panel for editing the generation parameters. Empty
+/// fields use the server's defaults, which are shown as placeholders. The model
+/// is chosen separately, via the [`ModelPicker`].
+///
+/// # Arguments
+///
+/// * `params` - Specifies the generation parameters.
+/// * `set_params` - Updates the generation parameters.
+#[component]
+pub fn GenerationSettings(
+	params: ReadSignal<GenerationParams>,
+	set_params: WriteSignal<GenerationParams>
+) -> impl IntoView
+{
+	// The server's defaults. We need to use a local resource in order to read
+	// this signal in a closure.
+	let defaults =
+		LocalResource::new(|| async move { generation_defaults().await });
+	// How to obtain the server's default for a parameter, for use as a
+	// placeholder.
+	let default = move |get: fn(&GenerationParams) -> String| {
+		move || {
+			defaults
+				.get()
+				.and_then(|defaults| defaults.as_ref().ok().map(get))
+				.unwrap_or_default()
+		}
+	};
+	view! {
+		<div class="flex justify-center mb-4">
+			<div class="collapse collapse-arrow bg-base-200 w-5/6">
+				<input type="checkbox"/>
+				<div class="collapse-title text-sm">"Generation settings"</div>
+				<div class="collapse-content grid grid-cols-4 gap-2 text-left">
+					<ParamField
+						label="Max tokens"
+						step="1"
+						placeholder=default(|d| show_param(d.max_tokens))
+						value=move || show_param(params().max_tokens)
+						set_value=move |v| set_params.update(|p| {
+							p.max_tokens = parse_param(&v)
+						})
+					/>
+					<ParamField
+						label="Seed"
+						step="1"
+						placeholder=default(|d| show_param(d.seed))
+						value=move || show_param(params().seed)
+						set_value=move |v| set_params.update(|p| {
+							p.seed = parse_param(&v)
+						})
+					/>
+					<ParamField
+						label="Temperature"
+						step="0.05"
+						placeholder=default(|d| show_param(d.temperature))
+						value=move || show_param(params().temperature)
+						set_value=move |v| set_params.update(|p| {
+							p.temperature = parse_param(&v)
+						})
+					/>
+					<ParamField
+						label="Top P"
+						step="0.05"
+						placeholder=default(|d| show_param(d.top_p))
+						value=move || show_param(params().top_p)
+						set_value=move |v| set_params.update(|p| {
+							p.top_p = parse_param(&v)
+						})
+					/>
+					<ParamField
+						label="Presence penalty"
+						step="0.1"
+						placeholder=default(|d| show_param(d.presence_penalty))
+						value=move || show_param(params().presence_penalty)
+						set_value=move |v| set_params.update(|p| {
+							p.presence_penalty = parse_param(&v)
+						})
+					/>
+					<ParamField
+						label="Frequency penalty"
+						step="0.1"
+						placeholder=default(|d| show_param(d.frequency_penalty))
+						value=move || show_param(params().frequency_penalty)
+						set_value=move |v| set_params.update(|p| {
+							p.frequency_penalty = parse_param(&v)
+						})
+					/>
+					<label class="form-control col-span-4">
+						<div class="label">
+							<span class="label-text">
+								"Stop sequences (one per line)"
+							</span>
+						</div>
+						<textarea
+							class="textarea textarea-bordered textarea-sm"
+							rows="2"
+							placeholder=default(|d| show_stop(&d.stop))
+							prop:value=move || show_stop(&params().stop)
+							on:change=move |ev| {
+								let value = event_target_value(&ev);
+								set_params.update(|p| p.stop = parse_stop(&value));
+							}
+						></textarea>
+					</label>
+				</div>
 			</div>
 		</div>
 	}
 }
 
+/// Represents a numeric field in the [generation settings](GenerationSettings).
+///
+/// # Arguments
+///
+/// * `label` - Specifies the label of the field.
+/// * `step` - Specifies the granularity of the field.
+/// * `placeholder` - Obtains the placeholder, i.e., the server's default.
+/// * `value` - Obtains the content of the field.
+/// * `set_value` - Updates the content of the field.
+#[component]
+pub fn ParamField<P, S, V>(
+	label: &'static str,
+	step: &'static str,
+	placeholder: P,
+	value: V,
+	set_value: S
+) -> impl IntoView
+where
+	P: Fn() -> String + Send + Sync + 'static,
+	S: Fn(String) + 'static,
+	V: Fn() -> String + Send + Sync + 'static
+{
+	view! {
+		<label class="form-control">
+			<div class="label">
+				<span class="label-text">{label}</span>
+			</div>
+			<input
+				type="number"
+				step=step
+				class="input input-bordered input-sm"
+				placeholder=placeholder
+				prop:value=value
+				on:change=move |ev| set_value(event_target_value(&ev))
+			/>
+		</label>
+	}
+}
+
 /// Represents the main chat component used to render a chat message.
 ///
 /// # Arguments
@@ -325,8 +1168,21 @@ pub fn Chat() -> impl IntoView
 /// * `rewind` - Enables the user to rewind the conversation to the specified
 ///   message.
 /// * `delete` - Enables the user to delete the message.
+/// * `branch` - Obtains the position of the message among its alternatives, and
+///   the number of alternatives.
+/// * `switch` - Enables the user to switch to an alternative, offset from the
+///   message by the specified amount.
+/// * `prompt` - Specifies the system prompt, if it is in the server's library.
+///   This is used for system and assistant messages only.
+/// * `variables` - Specifies the values of the system prompt's variables. This
+///   is used for system messages only.
+/// * `configure` - Enables the user to fill in the system prompt's variables,
+///   or to reset an edited system message. This is available for system
+///   messages only.
+/// * `tokens` - Obtains the number of tokens in the message, once counted. This
+///   is used for user and assistant messages only.
 #[component]
-pub fn ChatMessage<D, E, F, R, X>(
+pub fn ChatMessage<C, D, E, F, R, W, X>(
 	id: Uuid,
 	message: Message,
 	disabled: D,
@@ -335,13 +1191,21 @@ pub fn ChatMessage<D, E, F, R, X>(
 	edit: E,
 	regenerate: Signal<Option<F>>,
 	rewind: Signal<Option<R>>,
-	delete: X
+	delete: X,
+	branch: Signal<(usize, usize)>,
+	switch: W,
+	prompt: Signal<Option<PromptInfo>>,
+	variables: Signal<BTreeMap<String, String>>,
+	configure: C,
+	tokens: Signal<Option<usize>>
 ) -> impl IntoView
 where
+	C: Fn(BTreeMap<String, String>) + Clone + Send + Sync + 'static,
 	D: Fn() -> bool + Send + Sync + Clone + 'static,
 	E: FnMut(Uuid, String) + 'static,
 	F: FnMut(Uuid) + Clone + Send + Sync + 'static,
 	R: FnMut(Uuid) + Clone + Send + Sync + 'static,
+	W: FnMut(Uuid, isize) + Clone + Send + Sync + 'static,
 	X: FnMut(Uuid) + 'static
 {
 	match message.role
@@ -357,10 +1221,29 @@ where
 				regenerate=regenerate
 				rewind=rewind
 				delete=delete
+				branch=branch
+				switch=switch
+				structured=Signal::derive(move || {
+					prompt().is_some_and(|prompt| prompt.schema.is_some())
+				})
+				tokens=tokens
+			/>
+		}
+		.into_any(),
+		Role::System => view! {
+			<SystemMessage
+				id=id
+				message=message
+				disabled=disabled
+				editing=editing
+				set_editing=set_editing
+				edit=edit
+				prompt=prompt
+				variables=variables
+				configure=configure
 			/>
 		}
 		.into_any(),
-		Role::System => view! { <SystemMessage message=message/> }.into_any(),
 		Role::User => view! {
 			<UserMessage
 				id=id
@@ -371,22 +1254,236 @@ where
 				disabled=disabled
 				rewind=rewind
 				delete=delete
+				branch=branch
+				switch=switch
+				tokens=tokens
 			/>
 		}
-		.into_any()
+		.into_any(),
+		Role::Tool => view! { <ToolMessage message=message/> }.into_any()
+	}
+}
+
+/// Represents the result of a tool call, as a compact card that names the tool
+/// and shows the arguments that the assistant passed to it.
+///
+/// # Arguments
+///
+/// * `message` - Specifies the tool message.
+#[component]
+pub fn ToolMessage(message: Message) -> impl IntoView
+{
+	let (name, arguments) = message
+		.tool_call
+		.map(|call| (call.name, call.arguments))
+		.unwrap_or_default();
+	view! {
+		<div class="flex justify-center">
+			<div class="card card-compact w-1/2 bg-slate-300 text-black xl-shadow">
+				<div class="card-body text-xs">
+					<div class="flex items-center gap-2">
+						<span class="badge badge-neutral font-mono">{ name }</span>
+						<code class="truncate">{ arguments }</code>
+					</div>
+					<pre class="whitespace-pre-wrap">{ message.content }</pre>
+				</div>
+			</div>
+		</div>
 	}
 }
 
-/// A system message.
+/// Represents a system message. If the system prompt declares variables, then
+/// the card includes a form to fill them in. The form starts open if none of
+/// the variables has a value yet, e.g., before the chat starts. The message
+/// itself is editable, and an edited message can be reset to the rendered
+/// prompt from the server's library.
+///
+/// # Arguments
+///
+/// * `id` - Specifies the message identifier.
+/// * `message` - Specifies the content of the message.
+/// * `disabled` - Indicates whether the actions should be disabled.
+/// * `editing` - Indicates which message is being edited, if any.
+/// * `set_editing` - Updates the `editing` indicator.
+/// * `edit` - Updates the content of the message.
+/// * `prompt` - Specifies the system prompt, if it is in the server's library.
+/// * `variables` - Specifies the values of the system prompt's variables.
+/// * `configure` - Fills in the system prompt's variables, re-rendering the
+///   message.
 #[component]
-pub fn SystemMessage(message: Message) -> impl IntoView
+pub fn SystemMessage<C, D, E>(
+	id: Uuid,
+	message: Message,
+	disabled: D,
+	editing: ReadSignal<Option<Uuid>>,
+	set_editing: WriteSignal<Option<Uuid>>,
+	mut edit: E,
+	prompt: Signal<Option<PromptInfo>>,
+	variables: Signal<BTreeMap<String, String>>,
+	configure: C
+) -> impl IntoView
+where
+	C: Fn(BTreeMap<String, String>) + Clone + Send + Sync + 'static,
+	D: Fn() -> bool + Clone + Send + Sync + 'static,
+	E: FnMut(Uuid, String) + 'static
 {
+	// A reference to the message editor, for focusing and selecting the text.
+	let textarea = NodeRef::<html::Textarea>::new();
+	// The content of the message editor.
+	let (content, set_content) = signal(message.content);
+	// Whether any editor is open.
+	let editor_open = move || editing().is_some();
+	// Whether the message is being edited.
+	let editing = move || editing() == Some(id);
+	// The declared variables of the system prompt.
+	let declared =
+		move || prompt().map(|prompt| prompt.variables).unwrap_or_default();
+	// The values in the form, which apply only once submitted.
+	let draft = RwSignal::new(variables.get_untracked());
 	view! {
 		<div class="flex justify-center">
 			<div class="card w-2/3 bg-slate-500 text-black xl-shadow">
 				<figure><SystemImage /></figure>
-				<div class="card-body text-xs whitespace-pre font-mono">
-					<p>{message.content}</p>
+				<div class="card-body text-xs">
+					{
+						let disabled = disabled.clone();
+						let configure = configure.clone();
+						view! {
+							<Show when=move || !declared().is_empty()>
+								<div class="collapse collapse-arrow bg-slate-400">
+									<input
+										type="checkbox"
+										checked=variables.get_untracked().is_empty()
+									/>
+									<div class="collapse-title text-sm">
+										"Prompt variables"
+									</div>
+									<form
+										class="collapse-content grid grid-cols-2 gap-2 text-left"
+										on:submit={
+											let configure = configure.clone();
+											move |ev| {
+												// Do not actually submit the form.
+												ev.prevent_default();
+												configure(draft.get_untracked());
+											}
+										}
+									>
+										<For
+											each=declared
+											key=|variable| variable.name.clone()
+											children=move |variable| {
+												let name = variable.name.clone();
+												let value = {
+													let name = name.clone();
+													move || draft.with(|draft| {
+														draft
+															.get(&name)
+															.cloned()
+															.unwrap_or_default()
+													})
+												};
+												view! {
+													<label class="form-control">
+														<div class="label">
+															<span
+																class="label-text"
+																title=variable.description
+															>
+																{variable.name}
+															</span>
+														</div>
+														<input
+															type="text"
+															class="input input-bordered input-sm"
+															placeholder=variable.default
+															prop:value=value
+															on:input=move |ev| {
+																let value = event_target_value(&ev);
+																draft.update(|draft| {
+																	draft.insert(name.clone(), value);
+																});
+															}
+														/>
+													</label>
+												}
+											}
+										/>
+										<div class="col-span-2 flex justify-end">
+											<button
+												type="submit"
+												class="btn btn-sm"
+												disabled={
+													let disabled = disabled.clone();
+													move || disabled()
+												}
+											>
+												"Apply"
+											</button>
+										</div>
+									</form>
+								</div>
+							</Show>
+						}
+					}
+					<MessageEditor
+						id=id
+						node_ref=textarea
+						editing=editing
+						message=content
+						set_message=set_content
+						close=move |id| {
+							set_editing(None);
+							edit(id, content());
+						}
+					/>
+					<p
+						class="whitespace-pre font-mono"
+						hidden=editing
+					>
+						{content}
+					</p>
+					<div class="card-actions justify-end">
+						<EditButton
+							id=id
+							disabled={
+								let disabled = disabled.clone();
+								move || disabled() || (editor_open() && !editing())
+							}
+							click=move |id| {
+								if !editor_open()
+								{
+									// Open the editor first, then focus it and
+									// place the cursor at the end of the content.
+									set_editing(Some(id));
+									let textarea = textarea.get().unwrap();
+									textarea.focus().unwrap();
+									textarea.set_selection_start(
+										Some(content().len() as u32)
+									).unwrap();
+								}
+								else
+								{
+									set_editing(None);
+								}
+							}
+						/>
+						<Show when=move || prompt().is_some()>
+							<ResetButton
+								id=id
+								disabled={
+									let disabled = disabled.clone();
+									move || disabled() || editor_open()
+								}
+								click={
+									// Re-rendering the prompt discards any
+									// edits.
+									let configure = configure.clone();
+									move |_| configure(variables.get_untracked())
+								}
+							/>
+						</Show>
+					</div>
 				</div>
 			</div>
 		</div>
@@ -407,8 +1504,13 @@ pub fn SystemMessage(message: Message) -> impl IntoView
 /// * `rewind` - Enables the user to rewind the conversation to the specified
 ///   message.
 /// * `delete` - Enables the user to delete the message.
+/// * `branch` - Obtains the position of the message among its alternatives, and
+///   the number of alternatives.
+/// * `switch` - Enables the user to switch to an alternative, offset from the
+///   message by the specified amount.
+/// * `tokens` - Obtains the number of tokens in the message, once counted.
 #[component]
-pub fn UserMessage<D, E, R, X>(
+pub fn UserMessage<D, E, R, W, X>(
 	id: Uuid,
 	message: Message,
 	disabled: D,
@@ -416,12 +1518,16 @@ pub fn UserMessage<D, E, R, X>(
 	set_editing: WriteSignal<Option<Uuid>>,
 	edit: E,
 	rewind: Signal<Option<R>>,
-	delete: X
+	delete: X,
+	branch: Signal<(usize, usize)>,
+	switch: W,
+	tokens: Signal<Option<usize>>
 ) -> impl IntoView
 where
 	D: Fn() -> bool + Clone + Send + Sync + 'static,
 	E: FnMut(Uuid, String) + 'static,
 	R: FnMut(Uuid) + Clone + Send + Sync + 'static,
+	W: FnMut(Uuid, isize) + Clone + Send + Sync + 'static,
 	X: FnMut(Uuid) + 'static
 {
 	// This is a workaround for shortcomings in the `view!` macro and the type
@@ -436,6 +1542,7 @@ where
 		<ChatBubble
 			id=id
 			message=message
+			structured=Signal::stored(false)
 			chat_class={ "chat-end mr-8".to_string() }
 			portrait={ view! { <UserImage/> } }
 			bubble_color={ "bg-sky-300".to_string() }
@@ -446,6 +1553,9 @@ where
 			regenerate=regenerate
 			rewind=rewind
 			delete=delete
+			branch=branch
+			switch=switch
+			tokens=tokens
 		/>
 	}
 }
@@ -465,8 +1575,15 @@ where
 /// * `rewind` - Enables the user to rewind the conversation to the specified
 ///   message.
 /// * `delete` - Enables the user to delete the message.
+/// * `branch` - Obtains the position of the message among its alternatives, and
+///   the number of alternatives.
+/// * `switch` - Enables the user to switch to an alternative, offset from the
+///   message by the specified amount.
+/// * `structured` - Indicates whether the message should be JSON, i.e., whether
+///   the system prompt has a schema.
+/// * `tokens` - Obtains the number of tokens in the message, once counted.
 #[component]
-pub fn AssistantMessage<D, E, F, R, X>(
+pub fn AssistantMessage<D, E, F, R, W, X>(
 	id: Uuid,
 	message: Message,
 	disabled: D,
@@ -475,19 +1592,25 @@ pub fn AssistantMessage<D, E, F, R, X>(
 	edit: E,
 	regenerate: Signal<Option<F>>,
 	rewind: Signal<Option<R>>,
-	delete: X
+	delete: X,
+	branch: Signal<(usize, usize)>,
+	switch: W,
+	structured: Signal<bool>,
+	tokens: Signal<Option<usize>>
 ) -> impl IntoView
 where
 	D: Fn() -> bool + Clone + Send + Sync + 'static,
 	E: FnMut(Uuid, String) + 'static,
 	F: FnMut(Uuid) + Clone + Send + Sync + 'static,
 	R: FnMut(Uuid) + Clone + Send + Sync + 'static,
+	W: FnMut(Uuid, isize) + Clone + Send + Sync + 'static,
 	X: FnMut(Uuid) + 'static
 {
 	view! {
 		<ChatBubble
 			id=id
 			message=message
+			structured=structured
 			chat_class={ "chat-start ml-8".to_string() }
 			portrait={ view! { <AssistantImage/> } }
 			bubble_color={ "bg-green-300".to_string() }
@@ -498,6 +1621,9 @@ where
 			regenerate=regenerate
 			rewind=rewind
 			delete=delete
+			branch=branch
+			switch=switch
+			tokens=tokens
 		/>
 	}
 }
@@ -507,7 +1633,10 @@ where
 /// # Arguments
 ///
 /// * `id` - The message identifier.
-/// * `message` - The content of the message.
+/// * `message` - The content of the message, along with any tool calls, which
+///   are shown as badges.
+/// * `structured` - A boolean indicating whether the message should be JSON. If
+///   so, and it is, then the parsed structure is displayed instead of the text.
 /// * `chat_class` - The class to apply to the chat bubble.
 /// * `portrait` - The portrait to display with the message.
 /// * `bubble_color` - The color of the chat bubble.
@@ -520,10 +1649,16 @@ where
 /// * `rewind` - A function that enables the user to rewind the conversation to
 ///   the message.
 /// * `delete` - A function that enables the user to delete the message.
+/// * `branch` - A signal of the position of the message among its alternatives,
+///   and the number of alternatives.
+/// * `switch` - A function that enables the user to switch to an alternative,
+///   offset from the message by the specified amount.
+/// * `tokens` - A signal of the number of tokens in the message, once counted.
 #[component]
-pub fn ChatBubble<D, E, F, P, R, X>(
+pub fn ChatBubble<D, E, F, P, R, W, X>(
 	id: Uuid,
 	message: Message,
+	structured: Signal<bool>,
 	chat_class: String,
 	portrait: P,
 	bubble_color: String,
@@ -533,7 +1668,10 @@ pub fn ChatBubble<D, E, F, P, R, X>(
 	mut edit: E,
 	regenerate: Signal<Option<F>>,
 	rewind: Signal<Option<R>>,
-	delete: X
+	delete: X,
+	branch: Signal<(usize, usize)>,
+	switch: W,
+	tokens: Signal<Option<usize>>
 ) -> impl IntoView
 where
 	D: Fn() -> bool + Clone + Send + Sync + 'static,
@@ -541,6 +1679,7 @@ where
 	F: FnMut(Uuid) + Clone + Send + Sync + 'static,
 	P: IntoView,
 	R: FnMut(Uuid) + Clone + Send + Sync + 'static,
+	W: FnMut(Uuid, isize) + Clone + Send + Sync + 'static,
 	X: FnMut(Uuid) + 'static
 {
 	let UseClipboardReturn {
@@ -553,6 +1692,8 @@ where
 	let textarea = NodeRef::<html::Textarea>::new();
 	// The content of the message editor.
 	let (content, set_content) = signal(message.content);
+	// The tools that the assistant called, if any.
+	let tool_calls = message.tool_calls;
 	// Whether any editor is open.
 	let editor_open = move || editing().is_some();
 	// Whether the message is being edited.
@@ -567,7 +1708,7 @@ where
 					"
 						chat-bubble
 						{} xl-shadow
-						whitespace-pre-wrap hyphens-auto
+						hyphens-auto
 					",
 					bubble_color
 				)
@@ -583,19 +1724,59 @@ where
 						edit(id, content());
 					}
 				/>
+				{
+					(!tool_calls.is_empty()).then(|| view! {
+						<div class="flex flex-wrap gap-1 mb-1">
+							{
+								tool_calls
+									.into_iter()
+									.map(|call| view! {
+										<span
+											class="badge badge-neutral font-mono text-xs"
+											title=call.arguments
+										>
+											{ call.name }
+										</span>
+									})
+									.collect_view()
+							}
+						</div>
+					})
+				}
 				{
 					move || {
 						if editing() { ().into_any() }
+						else if let Some(value) = structured()
+							.then(|| parse_output(&content()).ok())
+							.flatten()
+						{
+							view! {
+								<div class="text-black">
+									<JsonView value=value/>
+								</div>
+							}.into_any()
+						}
 						else
 						{
 							view! {
-								<div class="text-black">{content}</div>
+								<div class="text-black">
+									<Markdown content=content/>
+								</div>
 							}.into_any()
 						}
 					}
 				}
 			</div>
 			<div class="chat-footer">
+				<BranchSwitcher
+					id=id
+					branch=branch
+					disabled={
+						let disabled = disabled.clone();
+						move || disabled() || editor_open()
+					}
+					click=switch
+				/>
 				{
 					let disabled = disabled.clone();
 					view! {
@@ -678,6 +1859,11 @@ where
 					disabled=move || disabled() || editor_open()
 					click=delete
 				/>
+				{move || tokens().map(|tokens| view! {
+					<span class="ml-1 text-xs opacity-50">
+						{tokens}" tokens"
+					</span>
+				})}
 			</div>
 		</div>
 	}
@@ -688,9 +1874,13 @@ where
 /// # Arguments
 ///
 /// * `message` - Obtains the message content.
+/// * `queued` - Indicates whether the message is waiting for the assistant to
+///   become available.
 #[component]
-pub fn IncompleteAssistantMessage(message: ReadSignal<String>)
--> impl IntoView
+pub fn IncompleteAssistantMessage(
+	message: ReadSignal<String>,
+	queued: ReadSignal<bool>
+) -> impl IntoView
 {
 	view! {
 		<div class="chat chat-start ml-8">
@@ -700,10 +1890,15 @@ pub fn IncompleteAssistantMessage(message: ReadSignal<String>)
 			<div class="
 				chat-bubble
 				bg-green-300 xl-shadow
-				whitespace-pre-wrap hyphens-auto
+				hyphens-auto
 			">
 				<div class="text-black">
-					{message}
+					<Show when=queued>
+						<span class="badge badge-ghost badge-sm mr-2">
+							"Waiting for the assistant"
+						</span>
+					</Show>
+					<StreamingMarkdown content=message/>
 					<span class="loading loading-dots loading-xs"></span>
 				</div>
 			</div>
@@ -711,6 +1906,194 @@ pub fn IncompleteAssistantMessage(message: ReadSignal<String>)
 	}
 }
 
+/// Represents the error that concluded the latest chat request.
+///
+/// # Arguments
+///
+/// * `error` - Specifies the error.
+/// * `dismiss` - A function that dismisses the alert.
+#[component]
+pub fn ChatErrorAlert<X>(error: AppError, mut dismiss: X) -> impl IntoView
+where
+	X: FnMut() + 'static
+{
+	let description = match error
+	{
+		AppError::ChatBusy =>
+		{
+			"The assistant is busy with another message. Try again when it \
+			finishes."
+		},
+		AppError::InvalidOutput(_) =>
+		{
+			"The assistant's response does not match the prompt's schema."
+		},
+		AppError::ContextExceeded(_) =>
+		{
+			"The system message and the latest message are too long for the \
+			assistant's context."
+		},
+		_ => "The assistant could not respond."
+	};
+	view! {
+		<div class="flex justify-center mb-4">
+			<div role="alert" class="alert alert-error w-5/6">
+				<span>{description}" ("{error.to_string()}")"</span>
+				<button
+					type="button"
+					class="btn btn-ghost btn-xs"
+					on:click=move |_| dismiss()
+				>
+					"Dismiss"
+				</button>
+			</div>
+		</div>
+	}
+}
+
+/// Represents a notice that the chosen system prompt has changed on disk.
+///
+/// # Arguments
+///
+/// * `apply` - A function that re-renders the system message from the changed
+///   prompt.
+/// * `dismiss` - A function that dismisses the alert.
+#[component]
+pub fn PromptChangedAlert<A, X>(apply: A, dismiss: X) -> impl IntoView
+where
+	A: Fn() + Send + Sync + 'static,
+	X: Fn() + Send + Sync + 'static
+{
+	view! {
+		<div class="flex justify-center mb-4">
+			<div role="alert" class="alert alert-info w-5/6">
+				<span>
+					"The system prompt has changed. Applying it replaces the \
+					system message, including any edits."
+				</span>
+				<div class="flex gap-2">
+					<button
+						type="button"
+						class="btn btn-sm"
+						on:click=move |_| apply()
+					>
+						"Apply"
+					</button>
+					<button
+						type="button"
+						class="btn btn-ghost btn-sm"
+						on:click=move |_| dismiss()
+					>
+						"Dismiss"
+					</button>
+				</div>
+			</div>
+		</div>
+	}
+}
+
+/// Represents a notice that the conversation outgrew the assistant's context,
+/// so the oldest messages were left out of the latest request. The messages
+/// left out are dimmed in the history.
+///
+/// # Arguments
+///
+/// * `count` - Specifies the number of messages left out.
+/// * `summarized` - Indicates whether the messages were replaced by a summary.
+/// * `dismiss` - A function that dismisses the alert.
+#[component]
+pub fn ContextTrimmedAlert<X>(
+	count: Signal<usize>,
+	summarized: ReadSignal<bool>,
+	dismiss: X
+) -> impl IntoView
+where
+	X: Fn() + Send + Sync + 'static
+{
+	view! {
+		<div class="flex justify-center mb-4">
+			<div role="alert" class="alert alert-warning w-5/6">
+				<span>
+					{move || match count()
+					{
+						1 => "1 earlier message did not fit in the assistant's \
+							context, ".to_string(),
+						count => format!(
+							"{count} earlier messages did not fit in the \
+							assistant's context, "
+						)
+					}}
+					{move || match summarized()
+					{
+						true => "so the assistant saw a summary instead.",
+						false => "so the assistant did not see them."
+					}}
+				</span>
+				<button
+					type="button"
+					class="btn btn-ghost btn-sm"
+					on:click=move |_| dismiss()
+				>
+					"Dismiss"
+				</button>
+			</div>
+		</div>
+	}
+}
+
+/// Represents a meter of the conversation's size relative to the model's
+/// context window, along with the number of tokens that the latest chat request
+/// used.
+///
+/// # Arguments
+///
+/// * `counts` - Specifies the token counts of the conversation, once counted.
+/// * `usage` - Specifies the number of tokens that the latest chat request
+///   used, if any.
+#[component]
+pub fn ContextMeter(
+	counts: Signal<Option<TokenCounts>>,
+	usage: ReadSignal<Option<Usage>>
+) -> impl IntoView
+{
+	// Whether the counts are estimates.
+	let estimated = |exact: bool| if exact { "" } else { " (estimated)" };
+	move || {
+		counts().map(|counts| {
+			let total = counts.total();
+			let window = counts.window.max(1);
+			let color = match total * 100 / window
+			{
+				percent if percent >= 100 => "progress-error",
+				percent if percent >= 75 => "progress-warning",
+				_ => "progress-success"
+			};
+			view! {
+				<div class="flex flex-col items-center mt-2 text-xs opacity-75">
+					<progress
+						class={format!("progress {color} w-5/6")}
+						value=total.min(window)
+						max=window
+					></progress>
+					<span>
+						{format!(
+							"{total} of {window} tokens in context{}",
+							estimated(counts.exact)
+						)}
+						{move || usage().map(|usage| format!(
+							"; the latest response used {} prompt and {} \
+							completion tokens{}",
+							usage.prompt_tokens,
+							usage.completion_tokens,
+							estimated(usage.exact)
+						))}
+					</span>
+				</div>
+			}
+		})
+	}
+}
+
 /// Represents a message editor.
 ///
 /// # Arguments
@@ -785,6 +2168,65 @@ where
 	}
 }
 
+/// Represents a switcher between the alternatives to a message, e.g., "< 2/3
+/// >". Hidden unless the message has alternatives.
+///
+/// # Arguments
+///
+/// * `id` - Specifies the message whose alternatives to switch between.
+/// * `branch` - Obtains the position of the message among its alternatives, and
+///   the number of alternatives.
+/// * `disabled` - Indicates whether the switcher should be disabled.
+/// * `click` - A function that handles a click event, it accepts the `id` and
+///   the offset of the requested alternative, i.e., `-1` or `1`.
+#[component]
+pub fn BranchSwitcher<D, W>(
+	id: Uuid,
+	branch: Signal<(usize, usize)>,
+	disabled: D,
+	click: W
+) -> impl IntoView
+where
+	D: Fn() -> bool + Clone + Send + Sync + 'static,
+	W: FnMut(Uuid, isize) + Clone + Send + Sync + 'static
+{
+	let index = move || branch().0;
+	let count = move || branch().1;
+	view! {
+		<Show when=move || { count() > 1 }>
+			<div class="inline-flex items-center text-xs">
+				<button
+					class="btn btn-ghost btn-xs disabled:opacity-25"
+					disabled={
+						let disabled = disabled.clone();
+						move || disabled() || index() == 0
+					}
+					on:click={
+						let mut click = click.clone();
+						move |_| click(id, -1)
+					}
+				>
+					"<"
+				</button>
+				<span>{move || format!("{}/{}", index() + 1, count())}</span>
+				<button
+					class="btn btn-ghost btn-xs disabled:opacity-25"
+					disabled={
+						let disabled = disabled.clone();
+						move || disabled() || index() + 1 == count()
+					}
+					on:click={
+						let mut click = click.clone();
+						move |_| click(id, 1)
+					}
+				>
+					">"
+				</button>
+			</div>
+		</Show>
+	}
+}
+
 /// Represents a rewind button used to rewind the conversation to a specified
 /// message.
 ///
@@ -844,6 +2286,36 @@ where
 	}
 }
 
+/// Represents a reset button used to restore the system message to the
+/// rendered prompt from the server's library.
+///
+/// # Arguments
+///
+/// * `id` - Specifies the message to reset.
+/// * `disabled` - Indicates whether the button should be disabled.
+/// * `click` - A function that handles a click event, it accepts the `id`.
+#[component]
+pub fn ResetButton<D, F>(id: Uuid, disabled: D, mut click: F) -> impl IntoView
+where
+	D: Fn() -> bool + Send + Sync + 'static,
+	F: FnMut(Uuid) + 'static
+{
+	view! {
+		<div
+			class="tooltip tooltip-bottom"
+			data-tip="Reset to the original prompt"
+		>
+			<button
+				class="btn btn-circle btn-ghost btn-xs disabled:opacity-25"
+				disabled=disabled
+				on:click=move |_| click(id)
+			>
+				<RegenerateImage />
+			</button>
+		</div>
+	}
+}
+
 /// Represents a copy button used to copy a message to the clipboard.
 ///
 /// # Arguments
@@ -884,6 +2356,33 @@ where
 	}
 }
 
+/// Represents a stop button used to cancel the assistant's message in
+/// progress.
+///
+/// # Arguments
+///
+/// * `click` - A function that handles a click event.
+#[component]
+pub fn StopButton<S>(mut click: S) -> impl IntoView
+where
+	S: FnMut() + 'static
+{
+	view! {
+		<div
+			class="tooltip tooltip-top"
+			data-tip="Stop response"
+		>
+			<button
+				type="button"
+				class="btn btn-circle btn-outline btn-error"
+				on:click=move |_| click()
+			>
+				<StopImage />
+			</button>
+		</div>
+	}
+}
+
 /// Represents a delete button used to delete a message.
 ///
 /// # Arguments
@@ -917,31 +2416,256 @@ where
 //                               System prompt.                               //
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Get the system prompt to use for the chat completion.
-#[cfg(feature = "ssr")]
-fn get_system_prompt() -> String
+/// List the system prompts in the server's
+/// [prompt library](crate::chat::PromptLibrary), sorted by name.
+#[server(ListSystemPromptsFn)]
+pub async fn list_system_prompts() -> Result<Vec<PromptInfo>, ServerFnError>
 {
-	let path =
-		std::env::var("SYSTEM_PROMPT").expect("SYSTEM_PROMPT must be set");
-	let bytes = std::fs::read(path).expect("Failed to read system prompt");
-	String::from_utf8_lossy(&bytes).to_string()
+	use crate::chat::PromptLibrary;
+	PromptLibrary::configured().list().map_err(|e| {
+		ServerFnError::new(format!("Failed to list system prompts: {e}"))
+	})
 }
 
-/// Get a [system message](Message) with the [system prompt](get_system_prompt).
+/// Get a [system message](Message) with the specified system prompt from the
+/// server's [prompt library](crate::chat::PromptLibrary), rendered with the
+/// specified variables. The message is empty if no prompt is specified and the
+/// server has no default prompt.
 ///
 /// # Arguments
 ///
+/// * `prompt` - The identifier of the system prompt, or `None` for the server's
+///   default.
+/// * `variables` - The values of the prompt's variables, keyed by name.
 /// * `delay` - The delay to use to test the loading state of the chat. This
 ///   should ordinarily be `None` in production.
-#[server(SystemMessageFn)]
+#[server(SystemMessageFn, input = leptos::server_fn::codec::Json)]
 pub async fn system_message(
+	prompt: Option<String>,
+	variables: BTreeMap<String, String>,
 	delay: Option<Duration>
 ) -> Result<Message, ServerFnError>
 {
+	use crate::chat::PromptLibrary;
 	if let Some(delay) = delay
 	{
 		// The delay is used to test the loading state of the chat.
 		tokio::time::sleep(delay).await;
 	}
-	Ok(Role::System.message(get_system_prompt()))
+	let prompt = PromptLibrary::configured()
+		.render(prompt.as_deref(), &variables)
+		.map_err(|e| {
+			ServerFnError::new(format!("Failed to load system prompt: {e}"))
+		})?;
+	Ok(Role::System
+		.message(prompt.map(|prompt| prompt.body).unwrap_or_default()))
+}
+
+/// Get the [model world](World) that results from replaying the action steps
+/// in the specified messages.
+///
+/// # Arguments
+///
+/// * `messages` - The messages of the conversation, in order.
+#[server(WorldStateFn, input = leptos::server_fn::codec::Json)]
+pub async fn world_state(messages: Vec<Message>)
+-> Result<World, ServerFnError>
+{
+	Ok(World::replay(&messages))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Token counting.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Count the tokens in the specified messages, relative to the model's context
+/// window, as the specified [chat backend](crate::chat::ChatBackend) counts
+/// them. Whether the counts are exact depends on the backend.
+///
+/// # Arguments
+///
+/// * `messages` - The messages of the conversation, in order.
+/// * `backend` - The name of the backend, or `None` for the server's default.
+#[server(CountTokensFn, input = leptos::server_fn::codec::Json)]
+pub async fn count_tokens(
+	messages: Vec<Message>,
+	backend: Option<String>
+) -> Result<TokenCounts, ServerFnError>
+{
+	use crate::chat::{BackendRouter, get_context_window};
+	let backend = BackendRouter::configured()
+		.get(backend.as_deref())
+		.backend
+		.clone();
+	Ok(TokenCounts {
+		counts: backend.count_tokens(&messages).await,
+		window: get_context_window(),
+		exact: backend.counts_exact()
+	})
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                           Generation parameters.                           //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Get the server's default [generation parameters](GenerationParams), i.e.,
+/// the parameters that apply when a request leaves them absent.
+#[server(GenerationDefaultsFn)]
+pub async fn generation_defaults() -> Result<GenerationParams, ServerFnError>
+{
+	use crate::chat::GenerationConfig;
+	let config = GenerationConfig::default();
+	Ok(config.resolve(&GenerationParams::default()))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                  Models.                                   //
+////////////////////////////////////////////////////////////////////////////////
+
+/// List the names of the configured
+/// [chat backends](crate::chat::BackendRouter), the default first.
+#[server(ListBackendsFn)]
+pub async fn list_backends() -> Result<Vec<String>, ServerFnError>
+{
+	use crate::chat::BackendRouter;
+	Ok(BackendRouter::configured().names())
+}
+
+/// List the models available from the specified
+/// [chat backend](crate::chat::ChatBackend), sorted by name.
+///
+/// # Arguments
+///
+/// * `backend` - The name of the backend, or `None` for the server's default.
+#[server(ListModelsFn)]
+pub async fn list_models(
+	backend: Option<String>
+) -> Result<Vec<String>, ServerFnError>
+{
+	use crate::chat::BackendRouter;
+	BackendRouter::configured()
+		.get(backend.as_deref())
+		.backend
+		.list_models()
+		.await
+		.map_err(|e| ServerFnError::new(format!("Failed to list models: {e}")))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                               Conversations.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Run the specified operation against the
+/// [conversation store](crate::chat::ConversationStore) on a blocking thread,
+/// translating any error into a [`ServerFnError`].
+#[cfg(feature = "ssr")]
+async fn with_store<T, F>(op: F) -> Result<T, ServerFnError>
+where
+	T: Send + 'static,
+	F: FnOnce(
+			&'static dyn crate::chat::ConversationStore
+		) -> Result<T, crate::chat::StoreError>
+		+ Send
+		+ 'static
+{
+	use crate::chat::conversation_store;
+	tokio::task::spawn_blocking(move || op(conversation_store()))
+		.await
+		.map_err(|e| ServerFnError::new(format!("Store task failed: {e}")))?
+		.map_err(|e| ServerFnError::new(format!("Store error: {e}")))
+}
+
+/// List the summaries of every saved conversation, most recently updated
+/// first.
+#[server(ListConversationsFn)]
+pub async fn list_conversations()
+-> Result<Vec<ConversationSummary>, ServerFnError>
+{
+	with_store(|store| store.list()).await
+}
+
+/// Load the specified conversation. Answer `None` if no such conversation has
+/// been saved.
+///
+/// # Arguments
+///
+/// * `id` - The conversation identifier.
+#[server(LoadConversationFn)]
+pub async fn load_conversation(
+	id: Uuid
+) -> Result<Option<Conversation>, ServerFnError>
+{
+	with_store(move |store| store.load(id)).await
+}
+
+/// Save the message tree and settings of the specified conversation, creating
+/// the conversation if necessary. The tree is too deeply nested for a
+/// URL-encoded form, so it travels as JSON.
+///
+/// # Arguments
+///
+/// * `id` - The conversation identifier.
+/// * `tree` - The complete message tree.
+/// * `settings` - The conversation settings.
+#[server(SaveConversationFn, input = leptos::server_fn::codec::Json)]
+pub async fn save_conversation(
+	id: Uuid,
+	tree: MessageTree,
+	settings: ConversationSettings
+) -> Result<ConversationSummary, ServerFnError>
+{
+	with_store(move |store| store.save(id, tree, settings)).await
+}
+
+/// Rename the specified conversation.
+///
+/// # Arguments
+///
+/// * `id` - The conversation identifier.
+/// * `title` - The new title.
+#[server(RenameConversationFn)]
+pub async fn rename_conversation(
+	id: Uuid,
+	title: String
+) -> Result<(), ServerFnError>
+{
+	let title = title.trim().to_string();
+	if title.is_empty()
+	{
+		return Err(ServerFnError::new("Title must not be empty"))
+	}
+	with_store(move |store| store.rename(id, title)).await
+}
+
+/// Delete the specified conversation.
+///
+/// # Arguments
+///
+/// * `id` - The conversation identifier.
+#[server(DeleteConversationFn)]
+pub async fn delete_conversation(id: Uuid) -> Result<(), ServerFnError>
+{
+	with_store(move |store| store.delete(id)).await
+}
+
+/// Duplicate the specified conversation, giving the copy a fresh identifier.
+///
+/// # Arguments
+///
+/// * `id` - The identifier of the conversation to duplicate.
+#[server(DuplicateConversationFn)]
+pub async fn duplicate_conversation(
+	id: Uuid
+) -> Result<ConversationSummary, ServerFnError>
+{
+	use crate::chat::StoreError;
+	with_store(move |store| {
+		let original = store.load(id)?.ok_or(StoreError::NotFound(id))?;
+		let copy =
+			store.save(Uuid::new_v4(), original.tree, original.settings)?;
+		let title = format!("Copy of {}", original.title);
+		store.rename(copy.id, title.clone())?;
+		Ok(ConversationSummary { title, ..copy })
+	})
+	.await
 }
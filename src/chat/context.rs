@@ -0,0 +1,385 @@
+use futures::future::join_all;
+use serde::Deserialize;
+use std::{
+	collections::HashMap,
+	hash::{DefaultHasher, Hash, Hasher},
+	sync::{LazyLock, Mutex}
+};
+use tracing::debug;
+
+use super::{Message, Role};
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Token counting.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Counts the tokens in messages. If `TOKENIZER_URL` names a tokenizer
+/// endpoint, like the `/tokenize` endpoint of a llama.cpp server, then the
+/// counts are exact, at least for the model that the endpoint serves.
+/// Otherwise, or if the endpoint fails, the counts are
+/// [estimates](estimate_tokens).
+#[derive(Debug)]
+pub struct TokenCounter
+{
+	/// The URL of the tokenizer endpoint, if any.
+	url: Option<String>,
+
+	/// The HTTP client for the tokenizer endpoint.
+	client: reqwest::Client,
+
+	/// The token counts of recently tokenized texts, keyed by hash, so that
+	/// the same messages are not tokenized over and over.
+	cache: Mutex<HashMap<u64, usize>>
+}
+
+/// The response of a tokenizer endpoint.
+#[derive(Debug, Deserialize)]
+struct TokenizeResponse
+{
+	/// The tokens of the text.
+	tokens: Vec<serde_json::Value>
+}
+
+impl TokenCounter
+{
+	/// Create a token counter.
+	///
+	/// # Arguments
+	///
+	/// - `url`: The URL of the tokenizer endpoint, or `None` to estimate.
+	pub fn new(url: Option<String>) -> Self
+	{
+		Self {
+			url,
+			client: reqwest::Client::new(),
+			cache: Mutex::new(HashMap::new())
+		}
+	}
+
+	/// Get the configured token counter, which `TOKENIZER_URL` configures.
+	pub fn configured() -> &'static Self
+	{
+		static COUNTER: LazyLock<TokenCounter> = LazyLock::new(|| {
+			TokenCounter::new(std::env::var("TOKENIZER_URL").ok())
+		});
+		&COUNTER
+	}
+
+	/// Whether the counts come from a tokenizer, rather than estimation.
+	pub fn is_exact(&self) -> bool { self.url.is_some() }
+
+	/// Count the tokens that the specified message occupies in the model's
+	/// context, including the overhead of the message framing.
+	///
+	/// # Arguments
+	///
+	/// - `message`: The message.
+	///
+	/// # Returns
+	///
+	/// The number of tokens.
+	pub async fn count(&self, message: &Message) -> usize
+	{
+		let Some(url) = &self.url
+		else
+		{
+			return estimate_tokens(message)
+		};
+		let text = message_text(message);
+		let key = {
+			let mut hasher = DefaultHasher::new();
+			text.hash(&mut hasher);
+			hasher.finish()
+		};
+		if let Some(count) = self.cache.lock().unwrap().get(&key)
+		{
+			return *count
+		}
+		match self.tokenize(url, &text).await
+		{
+			Ok(count) =>
+			{
+				let count = count + TOKENS_PER_MESSAGE;
+				let mut cache = self.cache.lock().unwrap();
+				if cache.len() >= TOKEN_CACHE_CAPACITY
+				{
+					cache.clear();
+				}
+				cache.insert(key, count);
+				count
+			},
+			Err(e) =>
+			{
+				debug!("Failed to tokenize; estimating: {e}");
+				estimate_tokens(message)
+			}
+		}
+	}
+
+	/// Count the tokens in each of the specified messages.
+	///
+	/// # Arguments
+	///
+	/// - `messages`: The messages.
+	///
+	/// # Returns
+	///
+	/// The number of tokens in each message, in order.
+	pub async fn count_all(&self, messages: &[Message]) -> Vec<usize>
+	{
+		join_all(messages.iter().map(|message| self.count(message))).await
+	}
+
+	/// Ask the tokenizer endpoint how many tokens the specified text has.
+	async fn tokenize(&self, url: &str, text: &str) -> reqwest::Result<usize>
+	{
+		let response = self
+			.client
+			.post(url)
+			.json(&serde_json::json!({ "content": text }))
+			.send()
+			.await?
+			.error_for_status()?
+			.json::<TokenizeResponse>()
+			.await?;
+		Ok(response.tokens.len())
+	}
+}
+
+/// Get the text of the specified message that occupies the model's context,
+/// i.e., the content and any tool calls.
+fn message_text(message: &Message) -> String
+{
+	let mut text = message.content.clone();
+	for call in &message.tool_calls
+	{
+		text.push_str(&call.name);
+		text.push_str(&call.arguments);
+	}
+	text
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Context budget.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Estimate the number of tokens that the specified message occupies in the
+/// model's context. The estimate assumes about four characters per token,
+/// which is close for English prose under the common tokenizers, and adds the
+/// overhead of the message framing. Tool calls count too.
+///
+/// # Arguments
+///
+/// - `message`: The message.
+///
+/// # Returns
+///
+/// The estimated number of tokens.
+pub fn estimate_tokens(message: &Message) -> usize
+{
+	let characters = message_text(message).chars().count();
+	characters.div_ceil(CHARACTERS_PER_TOKEN) + TOKENS_PER_MESSAGE
+}
+
+/// The messages of a chat, fitted to a context budget by
+/// [`fit_context`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FittedContext
+{
+	/// The messages that fit, in order.
+	pub messages: Vec<Message>,
+
+	/// The indices of the messages that were left out, in order.
+	pub excluded: Vec<usize>,
+
+	/// The number of tokens in the messages that fit.
+	pub tokens: usize
+}
+
+/// Fit the specified messages to the specified budget by leaving out the
+/// oldest turns. The system message, if it comes first, is pinned, as is the
+/// latest message, so the result may still exceed the budget. A
+/// [tool message](Role::Tool) goes with the assistant message that called the
+/// tool, because the model rejects a tool result without its call.
+///
+/// # Arguments
+///
+/// - `messages`: The messages, in order.
+/// - `sizes`: The number of tokens in each message, as
+///   [counted](TokenCounter::count_all).
+/// - `budget`: The maximum number of tokens.
+///
+/// # Returns
+///
+/// The messages that fit, and the indices of those that do not.
+pub fn fit_context(
+	messages: &[Message],
+	sizes: &[usize],
+	budget: usize
+) -> FittedContext
+{
+	let mut tokens = sizes.iter().sum::<usize>();
+	let mut excluded = vec![];
+	let first = match messages.first()
+	{
+		Some(message) if message.role == Role::System => 1,
+		_ => 0
+	};
+	let last = messages.len().saturating_sub(1);
+	let mut index = first;
+	while index < last && tokens > budget
+	{
+		excluded.push(index);
+		tokens -= sizes[index];
+		index += 1;
+		// Take any results of the excluded message's tool calls along with
+		// it.
+		while index < last && messages[index].role == Role::Tool
+		{
+			excluded.push(index);
+			tokens -= sizes[index];
+			index += 1;
+		}
+	}
+	// The pinned latest message may be a tool message, whose call must then
+	// stay too, along with any other results of the call.
+	if messages
+		.get(index)
+		.is_some_and(|message| message.role == Role::Tool)
+	{
+		while let Some(restored) = excluded.pop()
+		{
+			tokens += sizes[restored];
+			if messages[restored].role != Role::Tool
+			{
+				break
+			}
+		}
+	}
+	let messages = messages
+		.iter()
+		.enumerate()
+		.filter(|(index, _)| excluded.binary_search(index).is_err())
+		.map(|(_, message)| message.clone())
+		.collect();
+	FittedContext {
+		messages,
+		excluded,
+		tokens
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Summaries.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Summaries of the oldest turns of chats, which did not fit in the context
+/// window. A summary is keyed by a hash of the messages that it covers, so that
+/// a later turn of the same conversation can extend the summary with just the
+/// messages that it newly leaves out, and so that editing a covered message
+/// invalidates the summary.
+#[derive(Debug, Default)]
+pub struct SummaryCache
+{
+	/// The summaries, keyed by hash of the messages that they cover.
+	summaries: Mutex<HashMap<u64, String>>
+}
+
+/// A summary from a [`SummaryCache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedSummary
+{
+	/// The number of leading messages that the summary covers.
+	pub covered: usize,
+
+	/// The summary.
+	pub summary: String
+}
+
+impl SummaryCache
+{
+	/// Create an empty summary cache.
+	pub fn new() -> Self { Self::default() }
+
+	/// Get the summary that covers the longest run of leading messages of the
+	/// specified messages.
+	///
+	/// # Arguments
+	///
+	/// - `messages`: The messages, in order.
+	///
+	/// # Returns
+	///
+	/// The summary, or `None` if no summary covers even the first message.
+	pub fn longest(&self, messages: &[&Message]) -> Option<CachedSummary>
+	{
+		let keys = prefix_keys(messages);
+		let summaries = self.summaries.lock().unwrap();
+		keys.iter().enumerate().rev().find_map(|(index, key)| {
+			summaries.get(key).map(|summary| CachedSummary {
+				covered: index + 1,
+				summary: summary.clone()
+			})
+		})
+	}
+
+	/// Remember the summary of the specified messages.
+	///
+	/// # Arguments
+	///
+	/// - `messages`: The messages that the summary covers, in order.
+	/// - `summary`: The summary.
+	pub fn insert(&self, messages: &[&Message], summary: String)
+	{
+		let Some(&key) = prefix_keys(messages).last()
+		else
+		{
+			return
+		};
+		let mut summaries = self.summaries.lock().unwrap();
+		if summaries.len() >= SUMMARY_CACHE_CAPACITY
+		{
+			summaries.clear();
+		}
+		summaries.insert(key, summary);
+	}
+}
+
+/// Hash each run of leading messages of the specified messages.
+///
+/// # Arguments
+///
+/// - `messages`: The messages, in order.
+///
+/// # Returns
+///
+/// The hash of the first message, then of the first two, and so on.
+fn prefix_keys(messages: &[&Message]) -> Vec<u64>
+{
+	let mut hasher = DefaultHasher::new();
+	messages
+		.iter()
+		.map(|message| {
+			message.role.hash(&mut hasher);
+			message_text(message).hash(&mut hasher);
+			hasher.finish()
+		})
+		.collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The average number of characters per token, for the purpose of
+/// [estimation](estimate_tokens).
+const CHARACTERS_PER_TOKEN: usize = 4;
+
+/// The number of tokens that frame each message, e.g., the role.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// The maximum number of token counts that the [`TokenCounter`] caches.
+const TOKEN_CACHE_CAPACITY: usize = 4096;
+
+/// The maximum number of summaries that the [`SummaryCache`] caches.
+const SUMMARY_CACHE_CAPACITY: usize = 1024;
@@ -0,0 +1,558 @@
+use rand::{Rng, distributions::WeightedIndex, prelude::Distribution};
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+////////////////////////////////////////////////////////////////////////////////
+//                                   Dice.                                    //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A dice expression in the usual tabletop notation: a sum of dice terms and
+/// constants, e.g., `3d6+2` or `1d8+1d6-1`. A dice term may explode, i.e.,
+/// roll again whenever a die shows its highest face (`4d6!`), and may keep
+/// only its highest or lowest dice (`2d20kh1` for advantage, `2d20kl1` for
+/// disadvantage). `d%` is short for `d100`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceExpression
+{
+	/// The normalized text of the expression.
+	source: String,
+
+	/// The terms of the sum.
+	terms: Vec<Term>
+}
+
+/// A single term of a [dice expression](DiceExpression).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term
+{
+	/// Whether the term is subtracted rather than added.
+	negative: bool,
+
+	/// What the term comprises.
+	kind: TermKind
+}
+
+/// What a [term](Term) comprises.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TermKind
+{
+	/// A constant.
+	Constant(u32),
+
+	/// A number of like dice.
+	Dice
+	{
+		/// The number of dice.
+		count: u32,
+
+		/// The number of faces on each die.
+		sides: u32,
+
+		/// Whether a die that shows its highest face rolls again.
+		explode: bool,
+
+		/// Which dice count toward the total, if not all of them.
+		keep: Option<Keep>
+	}
+}
+
+/// Which dice of a term count toward the total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keep
+{
+	/// Keep the specified number of highest dice.
+	Highest(u32),
+
+	/// Keep the specified number of lowest dice.
+	Lowest(u32)
+}
+
+/// An error in a [dice expression](DiceExpression).
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DiceError
+{
+	#[error("Empty dice expression")]
+	Empty,
+
+	#[error("Unexpected {0:?} in dice expression")]
+	Unexpected(char),
+
+	#[error("Expected a number in dice expression")]
+	MissingNumber,
+
+	#[error("Too many dice: at most {MAX_DICE} may be rolled at once")]
+	TooManyDice,
+
+	#[error("Dice must have between 1 and {MAX_SIDES} sides")]
+	InvalidSides,
+
+	#[error("Cannot keep {0} dice of {1}")]
+	InvalidKeep(u32, u32),
+
+	#[error("One-sided dice cannot explode")]
+	InvalidExplode
+}
+
+impl FromStr for DiceExpression
+{
+	type Err = DiceError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err>
+	{
+		let source = s
+			.chars()
+			.filter(|c| !c.is_whitespace())
+			.collect::<String>()
+			.to_lowercase();
+		if source.is_empty()
+		{
+			return Err(DiceError::Empty)
+		}
+		let mut parser = Parser {
+			chars: source.chars().peekable(),
+			dice: 0
+		};
+		let mut terms = vec![];
+		let mut negative = parser.eat('-');
+		if !negative
+		{
+			parser.eat('+');
+		}
+		loop
+		{
+			terms.push(Term {
+				negative,
+				kind: parser.term()?
+			});
+			negative = match parser.chars.next()
+			{
+				None => break,
+				Some('+') => false,
+				Some('-') => true,
+				Some(c) => return Err(DiceError::Unexpected(c))
+			};
+		}
+		Ok(Self { source, terms })
+	}
+}
+
+impl fmt::Display for DiceExpression
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	{
+		write!(f, "{}", self.source)
+	}
+}
+
+impl DiceExpression
+{
+	/// Roll the dice.
+	///
+	/// # Arguments
+	///
+	/// - `rng`: The source of randomness. A seeded generator makes the roll
+	///   reproducible.
+	///
+	/// # Returns
+	///
+	/// The outcome of the roll.
+	pub fn roll(&self, rng: &mut impl Rng) -> DiceRoll
+	{
+		let mut parts = vec![];
+		let mut total = 0i64;
+		for term in &self.terms
+		{
+			let (value, part) = match term.kind
+			{
+				TermKind::Constant(value) => (value as i64, value.to_string()),
+				TermKind::Dice {
+					count,
+					sides,
+					explode,
+					keep
+				} =>
+				{
+					let dice = (0..count)
+						.map(|_| Die::roll(rng, sides, explode))
+						.collect::<Vec<_>>();
+					let kept = kept_dice(&dice, keep);
+					let value = dice
+						.iter()
+						.zip(&kept)
+						.filter(|(_, kept)| **kept)
+						.map(|(die, _)| die.value() as i64)
+						.sum::<i64>();
+					let faces = dice
+						.iter()
+						.zip(&kept)
+						.map(|(die, kept)| match kept
+						{
+							true => die.to_string(),
+							false => format!("dropped {die}")
+						})
+						.collect::<Vec<_>>()
+						.join(", ");
+					(value, format!("[{faces}]"))
+				}
+			};
+			match term.negative
+			{
+				true =>
+				{
+					total -= value;
+					parts.push(format!("- {part}"));
+				},
+				false =>
+				{
+					total += value;
+					parts.push(format!("+ {part}"));
+				}
+			}
+		}
+		// The leading sign is implicit unless it is negative.
+		let mut parts = parts.join(" ");
+		if let Some(rest) = parts.strip_prefix("+ ")
+		{
+			parts = rest.to_string();
+		}
+		DiceRoll {
+			expression: self.source.clone(),
+			parts,
+			total
+		}
+	}
+}
+
+/// The outcome of rolling a [dice expression](DiceExpression). It displays as
+/// the expression, the faces of every die, and the total, e.g.,
+/// `3d6+2: [4, 2, 6] + 2 = 14`. The faces of dropped dice are marked as such,
+/// and exploded dice show each of their rolls, e.g., `6!+3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceRoll
+{
+	/// The normalized text of the expression.
+	pub expression: String,
+
+	/// The faces of the dice and the constants, in order.
+	pub parts: String,
+
+	/// The total.
+	pub total: i64
+}
+
+impl fmt::Display for DiceRoll
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	{
+		write!(f, "{}: {} = {}", self.expression, self.parts, self.total)
+	}
+}
+
+/// A single rolled die, including any extra rolls from exploding.
+struct Die
+{
+	/// The faces rolled, in order.
+	rolls: Vec<u32>,
+
+	/// Whether the last roll exploded, which only happens when the limit on
+	/// extra rolls is reached.
+	exploded: bool
+}
+
+impl Die
+{
+	/// Roll a die.
+	///
+	/// # Arguments
+	///
+	/// - `rng`: The source of randomness.
+	/// - `sides`: The number of faces on the die.
+	/// - `explode`: Whether the die rolls again when it shows its highest face.
+	fn roll(rng: &mut impl Rng, sides: u32, explode: bool) -> Self
+	{
+		let mut rolls = vec![rng.gen_range(1..=sides)];
+		while explode && rolls.last() == Some(&sides)
+		{
+			if rolls.len() > MAX_EXPLOSIONS
+			{
+				return Self {
+					rolls,
+					exploded: true
+				}
+			}
+			rolls.push(rng.gen_range(1..=sides));
+		}
+		Self {
+			rolls,
+			exploded: false
+		}
+	}
+
+	/// The value of the die, i.e., the sum of its rolls.
+	fn value(&self) -> u32 { self.rolls.iter().sum() }
+}
+
+impl fmt::Display for Die
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	{
+		// Every roll but the last exploded.
+		let last = self.rolls.len() - 1;
+		for (index, roll) in self.rolls.iter().enumerate()
+		{
+			if index > 0
+			{
+				write!(f, "+")?;
+			}
+			write!(f, "{roll}")?;
+			if index < last || self.exploded
+			{
+				write!(f, "!")?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Decide which of the specified dice count toward the total.
+///
+/// # Arguments
+///
+/// - `dice`: The dice.
+/// - `keep`: Which dice to keep, if not all of them.
+///
+/// # Returns
+///
+/// Whether each die is kept, in order.
+fn kept_dice(dice: &[Die], keep: Option<Keep>) -> Vec<bool>
+{
+	let mut order = (0..dice.len()).collect::<Vec<_>>();
+	// Ties go to the earlier die, so the choice is deterministic.
+	let count = match keep
+	{
+		None => return vec![true; dice.len()],
+		Some(Keep::Highest(count)) =>
+		{
+			order.sort_by_key(|&index| std::cmp::Reverse(dice[index].value()));
+			count
+		},
+		Some(Keep::Lowest(count)) =>
+		{
+			order.sort_by_key(|&index| dice[index].value());
+			count
+		}
+	};
+	let mut kept = vec![false; dice.len()];
+	for index in order.into_iter().take(count as usize)
+	{
+		kept[index] = true;
+	}
+	kept
+}
+
+/// A parser of [dice expressions](DiceExpression), which have already been
+/// normalized.
+struct Parser<I: Iterator<Item = char>>
+{
+	/// The remaining characters.
+	chars: std::iter::Peekable<I>,
+
+	/// The number of dice so far.
+	dice: u32
+}
+
+impl<I: Iterator<Item = char>> Parser<I>
+{
+	/// Consume the specified character, if it comes next.
+	///
+	/// # Returns
+	///
+	/// Whether the character was consumed.
+	fn eat(&mut self, c: char) -> bool { self.chars.next_if_eq(&c).is_some() }
+
+	/// Parse a number, if one comes next. Numbers too big to be sensible are
+	/// saturated, so that the limits catch them.
+	fn number(&mut self) -> Option<u32>
+	{
+		let mut number = None;
+		while let Some(digit) = self.chars.peek().and_then(|c| c.to_digit(10))
+		{
+			self.chars.next();
+			number = Some(
+				number
+					.unwrap_or(0u32)
+					.saturating_mul(10)
+					.saturating_add(digit)
+			);
+		}
+		number
+	}
+
+	/// Parse a term, i.e., a constant or a number of like dice.
+	fn term(&mut self) -> Result<TermKind, DiceError>
+	{
+		let number = self.number();
+		if !self.eat('d')
+		{
+			return number.map(TermKind::Constant).ok_or_else(|| {
+				match self.chars.peek()
+				{
+					Some(&c) => DiceError::Unexpected(c),
+					None => DiceError::MissingNumber
+				}
+			})
+		}
+		let count = number.unwrap_or(1);
+		self.dice = self.dice.saturating_add(count);
+		if count == 0 || self.dice > MAX_DICE
+		{
+			return Err(DiceError::TooManyDice)
+		}
+		let sides = match self.eat('%')
+		{
+			true => 100,
+			false => self.number().ok_or(DiceError::MissingNumber)?
+		};
+		if sides == 0 || sides > MAX_SIDES
+		{
+			return Err(DiceError::InvalidSides)
+		}
+		let explode = self.eat('!');
+		if explode && sides == 1
+		{
+			return Err(DiceError::InvalidExplode)
+		}
+		let keep = match self.eat('k')
+		{
+			false => None,
+			true =>
+			{
+				let keep = match self.chars.next()
+				{
+					Some('h') => Keep::Highest,
+					Some('l') => Keep::Lowest,
+					Some(c) => return Err(DiceError::Unexpected(c)),
+					None => return Err(DiceError::MissingNumber)
+				};
+				let kept = self.number().unwrap_or(1);
+				if kept == 0 || kept > count
+				{
+					return Err(DiceError::InvalidKeep(kept, count))
+				}
+				Some(keep(kept))
+			}
+		};
+		Ok(TermKind::Dice {
+			count,
+			sides,
+			explode,
+			keep
+		})
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                               Random tables.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A weighted random table, e.g., of wandering monsters or of weather, loaded
+/// from a `.table` file in the prompt directory. The file is TOML:
+///
+/// ```text
+/// name = "Wilderness encounters"
+/// description = "What the party meets on the road."
+///
+/// [[entries]]
+/// weight = 3
+/// result = "Nothing but the wind."
+///
+/// [[entries]]
+/// result = "A pack of hungry wolves."
+/// ```
+///
+/// The table identifier is the stem of the file name. Entries without a weight
+/// have weight 1.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RandomTable
+{
+	/// The identifier of the table, i.e., the stem of its file name.
+	#[serde(skip)]
+	pub id: String,
+
+	/// The human-readable name of the table.
+	pub name: Option<String>,
+
+	/// A short description of the table.
+	pub description: Option<String>,
+
+	/// The entries of the table.
+	pub entries: Vec<TableEntry>
+}
+
+/// An entry in a [random table](RandomTable).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TableEntry
+{
+	/// The relative likelihood of the entry.
+	#[serde(default = "TableEntry::default_weight")]
+	pub weight: u32,
+
+	/// The text of the entry.
+	pub result: String
+}
+
+impl TableEntry
+{
+	/// The weight of an entry that does not specify one.
+	fn default_weight() -> u32 { 1 }
+}
+
+impl RandomTable
+{
+	/// Check that the table can be rolled on, i.e., that it has at least one
+	/// entry with a positive weight.
+	///
+	/// # Returns
+	///
+	/// A description of the problem, if any.
+	pub fn validate(&self) -> Result<(), String>
+	{
+		match self.entries.iter().any(|entry| entry.weight > 0)
+		{
+			true => Ok(()),
+			false => Err("The table has no entries with positive weight".into())
+		}
+	}
+
+	/// Roll on the table.
+	///
+	/// # Arguments
+	///
+	/// - `rng`: The source of randomness. A seeded generator makes the roll
+	///   reproducible.
+	///
+	/// # Returns
+	///
+	/// The chosen entry, or `None` if the table is [invalid](Self::validate).
+	pub fn roll(&self, rng: &mut impl Rng) -> Option<&TableEntry>
+	{
+		let index =
+			WeightedIndex::new(self.entries.iter().map(|entry| entry.weight))
+				.ok()?;
+		self.entries.get(index.sample(rng))
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The maximum number of dice in a single expression.
+const MAX_DICE: u32 = 100;
+
+/// The maximum number of faces on a die.
+const MAX_SIDES: u32 = 1000;
+
+/// The maximum number of extra rolls for a single exploding die.
+const MAX_EXPLOSIONS: usize = 20;
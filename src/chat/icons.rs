@@ -289,3 +289,27 @@ pub fn DeleteImage() -> impl IntoView
 		</svg>
 	}
 }
+
+/// The stop image. This indicates that the chat completion in progress can be
+/// cancelled. This is the solid "stop" from the
+/// [`heroicons`](https://heroicons.com/solid) set.
+#[component]
+pub fn StopImage() -> impl IntoView
+{
+	view! {
+		<svg
+			xmlns="http://www.w3.org/2000/svg"
+			viewBox="0 0 24 24"
+			fill="currentColor"
+			class="w-6 h-6 text-error"
+		>
+			<title>Stop response</title>
+			<path
+				fill-rule="evenodd"
+				d="M4.5 7.5a3 3 0 0 1 3-3h9a3 3 0 0 1 3 3v9a3 3 0 0 1-3 3h-9a3 3
+					0 0 1-3-3v-9Z"
+				clip-rule="evenodd"
+			/>
+		</svg>
+	}
+}
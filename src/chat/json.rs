@@ -0,0 +1,193 @@
+use leptos::prelude::*;
+use serde_json::Value;
+#[cfg(feature = "ssr")]
+use thiserror::Error;
+
+////////////////////////////////////////////////////////////////////////////////
+//                             Structured output.                             //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Parse the specified output of the assistant as JSON. Models often wrap JSON
+/// in a Markdown code fence despite instructions to the contrary, so a single
+/// fence around the whole output is ignored.
+///
+/// # Arguments
+///
+/// - `output`: The output of the assistant.
+///
+/// # Returns
+///
+/// The parsed output.
+pub fn parse_output(output: &str) -> serde_json::Result<Value>
+{
+	serde_json::from_str(strip_fence(output))
+}
+
+/// Strip a Markdown code fence, including its info string, from around the
+/// specified output. Answer the output unchanged if it is not fenced.
+fn strip_fence(output: &str) -> &str
+{
+	let trimmed = output.trim();
+	trimmed
+		.strip_prefix(FENCE)
+		.and_then(|rest| rest.strip_suffix(FENCE))
+		.and_then(|rest| rest.split_once('\n'))
+		.map(|(_, body)| body)
+		.unwrap_or(trimmed)
+}
+
+/// A JSON Schema that constrains the output of the assistant.
+#[cfg(feature = "ssr")]
+pub struct OutputSchema
+{
+	/// The schema itself.
+	schema: Value,
+
+	/// The compiled schema.
+	validator: jsonschema::Validator
+}
+
+/// The reason that the output of the assistant failed
+/// [validation](OutputSchema::validate).
+#[cfg(feature = "ssr")]
+#[derive(Debug, Error)]
+pub enum OutputError
+{
+	#[error("Output is not JSON: {0}")]
+	Syntax(#[from] serde_json::Error),
+
+	#[error("Output does not match the schema: {}", .0.join("; "))]
+	Schema(Vec<String>)
+}
+
+#[cfg(feature = "ssr")]
+impl OutputSchema
+{
+	/// Compile the specified JSON Schema.
+	///
+	/// # Arguments
+	///
+	/// - `schema`: The schema.
+	///
+	/// # Returns
+	///
+	/// The compiled schema, or a description of the problem.
+	pub fn new(schema: Value) -> Result<Self, String>
+	{
+		let validator =
+			jsonschema::validator_for(&schema).map_err(|e| e.to_string())?;
+		Ok(Self { schema, validator })
+	}
+
+	/// Whether the schema only admits JSON objects. OpenAI's JSON mode
+	/// guarantees an object, so it cannot help with any other schema.
+	pub fn expects_object(&self) -> bool
+	{
+		self.schema.get("type").and_then(Value::as_str) == Some("object")
+	}
+
+	/// Parse the specified output of the assistant and validate it against the
+	/// schema.
+	///
+	/// # Arguments
+	///
+	/// - `output`: The output of the assistant.
+	///
+	/// # Returns
+	///
+	/// The parsed output.
+	pub fn validate(&self, output: &str) -> Result<Value, OutputError>
+	{
+		let value = parse_output(output)?;
+		let errors = self
+			.validator
+			.iter_errors(&value)
+			.take(MAX_REPORTED_ERRORS)
+			.map(|e| match e.instance_path.to_string()
+			{
+				path if path.is_empty() => e.to_string(),
+				path => format!("{path}: {e}")
+			})
+			.collect::<Vec<_>>();
+		match errors.is_empty()
+		{
+			true => Ok(value),
+			false => Err(OutputError::Schema(errors))
+		}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                Components.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Represents a parsed JSON value as nested lists: arrays become numbered
+/// lists, and objects become lists of named fields.
+///
+/// # Arguments
+///
+/// * `value` - Specifies the value to present.
+#[component]
+pub fn JsonView(value: Value) -> AnyView
+{
+	view! {
+		<div class="json-view">{json_value(value)}</div>
+	}
+	.into_any()
+}
+
+/// Present the specified JSON value, recursively.
+fn json_value(value: Value) -> AnyView
+{
+	match value
+	{
+		Value::Array(items) if items.is_empty() =>
+		{
+			view! { <span class="json-empty">"none"</span> }.into_any()
+		},
+		Value::Array(items) => view! {
+			<ol>
+				{items
+					.into_iter()
+					.map(|item| view! { <li>{json_value(item)}</li> })
+					.collect_view()}
+			</ol>
+		}
+		.into_any(),
+		Value::Object(fields) if fields.is_empty() =>
+		{
+			view! { <span class="json-empty">"empty"</span> }.into_any()
+		},
+		Value::Object(fields) => view! {
+			<dl>
+				{fields
+					.into_iter()
+					.map(|(name, value)| view! {
+						<dt>{name}</dt>
+						<dd>{json_value(value)}</dd>
+					})
+					.collect_view()}
+			</dl>
+		}
+		.into_any(),
+		Value::String(text) =>
+		{
+			view! { <span class="json-string">{text}</span> }.into_any()
+		},
+		scalar => view! {
+			<span class="json-scalar">{scalar.to_string()}</span>
+		}
+		.into_any()
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The delimiter of a Markdown code fence.
+const FENCE: &str = "```";
+
+/// The maximum number of schema violations to report for a single output.
+#[cfg(feature = "ssr")]
+const MAX_REPORTED_ERRORS: usize = 8;
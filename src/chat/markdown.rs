@@ -0,0 +1,525 @@
+use leptos::prelude::*;
+use leptos_use::{UseClipboardReturn, use_clipboard};
+use pulldown_cmark::{
+	CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd, html::push_html
+};
+use std::{borrow::Cow, sync::LazyLock};
+use syntect::{
+	highlighting::ThemeSet, html::highlighted_html_for_string,
+	parsing::SyntaxSet
+};
+
+use super::icons::{CopiedImage, CopyImage};
+
+////////////////////////////////////////////////////////////////////////////////
+//                            Markdown rendering.                             //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A top-level block of rendered Markdown. Fenced code blocks are kept apart
+/// from the surrounding content, so that each can have its own copy button.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block
+{
+	/// Rendered HTML, excluding top-level code blocks. Raw HTML from the
+	/// source is escaped, so this is safe to inject into the page.
+	Html(String),
+
+	/// A code block.
+	Code
+	{
+		/// The language given by the fence's info string, if any.
+		language: Option<String>,
+
+		/// The code itself, for copying.
+		source: String,
+
+		/// The syntax-highlighted code, as HTML.
+		html: String
+	}
+}
+
+/// Render the specified Markdown source as a sequence of [blocks](Block).
+/// Supports the CommonMark syntax plus tables, strikethrough, and task lists.
+/// Raw HTML is rendered as text, and links with scriptable URLs are neutered,
+/// so the result is safe to inject into the page even if the source is not.
+///
+/// # Arguments
+///
+/// - `source`: The Markdown source.
+///
+/// # Returns
+///
+/// The rendered blocks, in order.
+pub fn render_markdown(source: &str) -> Vec<Block>
+{
+	let mut blocks = vec![];
+	// The pending events, i.e., those since the last top-level code block.
+	let mut events = vec![];
+	// The code block in progress, if any: its language, its source, and
+	// whether it is top-level.
+	let mut code = None::<(Option<String>, String, bool)>;
+	// The nesting depth of the current event.
+	let mut depth = 0usize;
+	for event in Parser::new_ext(source, markdown_options())
+	{
+		match event
+		{
+			Event::Start(Tag::CodeBlock(kind)) =>
+			{
+				let language = match kind
+				{
+					CodeBlockKind::Fenced(info) =>
+					{
+						info.split_whitespace().next().map(str::to_string)
+					},
+					CodeBlockKind::Indented => None
+				};
+				code = Some((language, String::new(), depth == 0));
+				depth += 1;
+			},
+			Event::End(TagEnd::CodeBlock) =>
+			{
+				depth -= 1;
+				if let Some((language, source, top_level)) = code.take()
+				{
+					let html = highlight(&source, language.as_deref());
+					if top_level
+					{
+						flush(&mut events, &mut blocks);
+						blocks.push(Block::Code {
+							language,
+							source,
+							html
+						});
+					}
+					else
+					{
+						// Nested code blocks, e.g., inside list items, must
+						// stay inline to keep the surrounding HTML balanced.
+						events.push(Event::Html(html.into()));
+					}
+				}
+			},
+			Event::Text(text) => match &mut code
+			{
+				Some((_, source, _)) => source.push_str(&text),
+				None => events.push(Event::Text(text))
+			},
+			// Show raw HTML as text, rather than injecting it.
+			Event::Html(html) | Event::InlineHtml(html) =>
+			{
+				events.push(Event::Text(html))
+			},
+			Event::Start(Tag::HtmlBlock) =>
+			{
+				depth += 1;
+				events.push(Event::Start(Tag::Paragraph));
+			},
+			Event::End(TagEnd::HtmlBlock) =>
+			{
+				depth -= 1;
+				events.push(Event::End(TagEnd::Paragraph));
+			},
+			Event::Start(tag) =>
+			{
+				depth += 1;
+				events.push(Event::Start(neuter_link(tag)));
+			},
+			Event::End(tag) =>
+			{
+				depth -= 1;
+				events.push(Event::End(tag));
+			},
+			event => events.push(event)
+		}
+	}
+	flush(&mut events, &mut blocks);
+	blocks
+}
+
+/// Syntax highlight the specified code as HTML, using inline styles. Unknown
+/// languages are rendered as plain text.
+///
+/// # Arguments
+///
+/// - `source`: The code to highlight.
+/// - `language`: The language of the code, as a name or file extension.
+///
+/// # Returns
+///
+/// The highlighted code, as a `<pre>` element.
+pub fn highlight(source: &str, language: Option<&str>) -> String
+{
+	static SYNTAXES: LazyLock<SyntaxSet> =
+		LazyLock::new(SyntaxSet::load_defaults_newlines);
+	static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+	let syntax = language
+		.and_then(|language| SYNTAXES.find_syntax_by_token(language))
+		.unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+	highlighted_html_for_string(
+		source,
+		&SYNTAXES,
+		syntax,
+		&THEMES.themes[THEME]
+	)
+	.unwrap_or_else(|_| {
+		// Fall back to unhighlighted, but still escaped, code.
+		let mut html = String::new();
+		push_html(
+			&mut html,
+			[
+				Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)),
+				Event::Text(source.into()),
+				Event::End(TagEnd::CodeBlock)
+			]
+			.into_iter()
+		);
+		html
+	})
+}
+
+/// Render the pending events as an HTML [block](Block), if there are any.
+fn flush(events: &mut Vec<Event>, blocks: &mut Vec<Block>)
+{
+	if !events.is_empty()
+	{
+		let mut html = String::new();
+		push_html(&mut html, events.drain(..));
+		blocks.push(Block::Html(html));
+	}
+}
+
+/// Replace the destination of a link or image whose URL could run script,
+/// e.g., `javascript:`. Other tags are returned unchanged.
+fn neuter_link(tag: Tag) -> Tag
+{
+	let is_unsafe = |url: &CowStr| {
+		let url = url.trim_start().to_ascii_lowercase();
+		UNSAFE_SCHEMES.iter().any(|scheme| url.starts_with(scheme))
+	};
+	match tag
+	{
+		Tag::Link {
+			link_type,
+			dest_url,
+			title,
+			id
+		} if is_unsafe(&dest_url) => Tag::Link {
+			link_type,
+			dest_url: "#".into(),
+			title,
+			id
+		},
+		Tag::Image {
+			link_type,
+			dest_url,
+			title,
+			id
+		} if is_unsafe(&dest_url) => Tag::Image {
+			link_type,
+			dest_url: "".into(),
+			title,
+			id
+		},
+		tag => tag
+	}
+}
+
+/// The Markdown extensions to enable.
+fn markdown_options() -> Options
+{
+	Options::ENABLE_TABLES
+		| Options::ENABLE_STRIKETHROUGH
+		| Options::ENABLE_TASKLISTS
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                            Streaming Markdown.                             //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Renders Markdown that arrives incrementally, e.g., as fragments of an
+/// assistant message. Top-level blocks that can no longer change are rendered
+/// once and committed; only the trailing open blocks are re-rendered as the
+/// source grows. The last block is patched up to look like its completed form,
+/// e.g., a table whose delimiter row has not arrived yet still renders as a
+/// table.
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownStream
+{
+	/// The length of the source that has been committed.
+	offset: usize,
+
+	/// The committed blocks, in order.
+	committed: Vec<Block>
+}
+
+impl MarkdownStream
+{
+	/// Get the committed blocks, in order. These never change, though more may
+	/// be committed by subsequent [updates](Self::update).
+	pub fn committed(&self) -> &[Block] { &self.committed }
+
+	/// Update the rendering with the complete source so far, which must extend
+	/// the source of the previous update. Starts over if it does not.
+	///
+	/// # Arguments
+	///
+	/// - `source`: The complete Markdown source so far.
+	///
+	/// # Returns
+	///
+	/// The blocks of the trailing open blocks, which supersede those returned
+	/// by the previous update.
+	pub fn update(&mut self, source: &str) -> Vec<Block>
+	{
+		if source.len() < self.offset || !source.is_char_boundary(self.offset)
+		{
+			*self = Self::default();
+		}
+		let tail = &source[self.offset..];
+		let (open, last) = open_blocks(tail);
+		if open > 0
+		{
+			self.committed.extend(render_markdown(&tail[..open]));
+			self.offset += open;
+		}
+		let open =
+			format!("{}{}", &tail[open..last], close_block(&tail[last..]));
+		render_markdown(&open)
+	}
+}
+
+/// Find the open blocks of the specified Markdown source, i.e., the last two
+/// top-level blocks that follow blank lines. Everything before them is
+/// complete, no matter what comes next. Without a blank line, more source could
+/// still extend the previous block, e.g., with another table row; and even
+/// after a blank line, the last block could turn out to continue the one before
+/// it, e.g., as another item of a loose list.
+///
+/// # Returns
+///
+/// The offsets of the first and last open blocks, respectively.
+fn open_blocks(source: &str) -> (usize, usize)
+{
+	let follows_blank_line = |start: usize| {
+		source[..start]
+			.trim_end_matches([' ', '\t'])
+			.ends_with("\n\n")
+	};
+	let mut previous = 0;
+	let mut start = 0;
+	let mut depth = 0usize;
+	for (event, range) in
+		Parser::new_ext(source, markdown_options()).into_offset_iter()
+	{
+		// Ends are never top-level, because they follow their starts.
+		if depth == 0 && follows_blank_line(range.start)
+		{
+			previous = start;
+			start = range.start;
+		}
+		match event
+		{
+			Event::Start(_) => depth += 1,
+			Event::End(_) => depth -= 1,
+			_ =>
+			{}
+		}
+	}
+	(previous, start)
+}
+
+/// Patch up the last block of a streamed Markdown source so that it renders
+/// like its completed form. Drops a closing fence or a table row that is still
+/// arriving, and supplies the delimiter row of a table that has only its header
+/// so far.
+fn close_block(block: &str) -> Cow<'_, str>
+{
+	let mut lines = block.lines().collect::<Vec<_>>();
+	let partial = !block.is_empty() && !block.ends_with('\n');
+	let is_fence = |line: &str| {
+		let line = line.trim_start();
+		line.starts_with("```") || line.starts_with("~~~")
+	};
+	let is_row = |line: &&str| line.trim_start().starts_with('|');
+	if let [first, .., last] = lines[..]
+		&& is_fence(first)
+		&& partial
+		&& last.trim().chars().all(|c| c == '`' || c == '~')
+	{
+		// The closing fence is still arriving. Hiding it early is harmless:
+		// an unclosed fence extends to the end of the source anyway.
+		lines.pop();
+		return Cow::Owned(lines.join("\n"))
+	}
+	if lines.is_empty() || !lines.iter().all(is_row)
+	{
+		return Cow::Borrowed(block)
+	}
+	if partial && lines.len() > 1
+	{
+		lines.pop();
+	}
+	let mut table = lines.join("\n");
+	if lines.len() == 1
+	{
+		let columns = lines[0].trim().trim_matches('|').split('|').count();
+		table.push('\n');
+		table.push_str(&"|---".repeat(columns));
+		table.push('|');
+	}
+	Cow::Owned(table)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                            Markdown components.                            //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Represents Markdown content, rendered as HTML. Fenced code blocks are syntax
+/// highlighted, and each has its own copy button.
+///
+/// # Arguments
+///
+/// * `content` - Obtains the Markdown source.
+#[component]
+pub fn Markdown<C>(content: C) -> impl IntoView
+where
+	C: Fn() -> String + Send + Sync + 'static
+{
+	move || {
+		render_markdown(&content())
+			.into_iter()
+			.map(|block| view! { <MarkdownBlock block=block/> })
+			.collect_view()
+	}
+}
+
+/// Represents Markdown content that is still arriving, rendered as HTML. Only
+/// the trailing open blocks are re-rendered as the content grows, so the result
+/// looks like the final [`Markdown`] throughout.
+///
+/// # Arguments
+///
+/// * `content` - Obtains the Markdown source so far. Each new value must extend
+///   the previous one.
+#[component]
+pub fn StreamingMarkdown(content: ReadSignal<String>) -> impl IntoView
+{
+	// The committed blocks, which are never re-rendered.
+	let (committed, set_committed) = signal(Vec::<Block>::new());
+	// The blocks of the trailing open blocks.
+	let (open, set_open) = signal(Vec::<Block>::new());
+	Effect::new(move |stream: Option<MarkdownStream>| {
+		let mut stream = stream.unwrap_or_default();
+		let before = stream.committed().len();
+		let blocks = content.with(|content| stream.update(content));
+		if stream.committed().len() != before
+		{
+			set_committed(stream.committed().to_vec());
+		}
+		set_open(blocks);
+		stream
+	});
+	view! {
+		<For
+			each=move || committed().into_iter().enumerate()
+			key=|(index, _)| *index
+			children=move |(_, block)| view! { <MarkdownBlock block=block/> }
+		/>
+		{move || {
+			open()
+				.into_iter()
+				.map(|block| view! { <MarkdownBlock block=block/> })
+				.collect_view()
+		}}
+	}
+}
+
+/// Represents a single [block](Block) of rendered Markdown.
+///
+/// # Arguments
+///
+/// * `block` - Specifies the block.
+#[component]
+pub fn MarkdownBlock(block: Block) -> impl IntoView
+{
+	match block
+	{
+		Block::Html(html) => view! {
+			<div class="markdown" inner_html=html></div>
+		}
+		.into_any(),
+		Block::Code {
+			language,
+			source,
+			html
+		} => view! {
+			<CodeBlock language=language source=source html=html/>
+		}
+		.into_any()
+	}
+}
+
+/// Represents a syntax-highlighted code block with a copy button.
+///
+/// # Arguments
+///
+/// * `language` - Specifies the language of the code, if known.
+/// * `source` - Specifies the code, for copying.
+/// * `html` - Specifies the highlighted code, as HTML.
+#[component]
+pub fn CodeBlock(
+	language: Option<String>,
+	source: String,
+	html: String
+) -> impl IntoView
+{
+	let UseClipboardReturn {
+		is_supported: can_copy,
+		text: _,
+		copied,
+		copy
+	} = use_clipboard();
+	view! {
+		<div class="relative my-2 text-left text-sm">
+			<div class="absolute top-1 right-1 flex items-center gap-1">
+				{language.map(|language| view! {
+					<span class="badge badge-ghost badge-xs">{language}</span>
+				})}
+				<Show when=can_copy>
+					<div class="tooltip tooltip-left" data-tip="Copy code">
+						<button
+							class="btn btn-circle btn-ghost btn-xs"
+							on:click={
+								let copy = copy.clone();
+								let source = source.clone();
+								move |_| copy(&source)
+							}
+						>
+							<Show
+								when=copied
+								fallback=move || view! { <CopyImage /> }
+							>
+								<CopiedImage />
+							</Show>
+						</button>
+					</div>
+				</Show>
+			</div>
+			<div
+				class="overflow-x-auto [&>pre]:p-2 [&>pre]:rounded"
+				inner_html=html
+			></div>
+		</div>
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The syntax highlighting theme, one of `syntect`'s defaults.
+const THEME: &str = "base16-ocean.dark";
+
+/// The URL schemes that could run script if followed.
+const UNSAFE_SCHEMES: &[&str] = &["javascript:", "vbscript:", "data:"];
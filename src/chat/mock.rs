@@ -0,0 +1,328 @@
+use async_trait::async_trait;
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::{
+	sync::{
+		Mutex,
+		atomic::{AtomicUsize, Ordering}
+	},
+	time::Duration
+};
+use tracing::warn;
+
+use super::{
+	BackendError, ChatBackend, CompletionChunk, CompletionRequest,
+	CompletionStream, FinishReason, Role
+};
+
+////////////////////////////////////////////////////////////////////////////////
+//                               Mock backend.                                //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [`ChatBackend`] that needs no model server, for tests and demos. It
+/// streams the responses of its [script](MockScript) in turn, or, without a
+/// script, echoes the latest user message. Every request is recorded, so that
+/// tests can inspect what the backend was asked.
+#[derive(Debug, Default)]
+pub struct MockBackend
+{
+	/// The script.
+	script: MockScript,
+
+	/// The index of the next scripted response.
+	next: AtomicUsize,
+
+	/// The requests received so far, in order.
+	requests: Mutex<Vec<CompletionRequest>>
+}
+
+/// The script of a [`MockBackend`], as read from a TOML file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MockScript
+{
+	/// The number of characters in each fragment of content, or `0` to send
+	/// the content whole.
+	#[serde(default)]
+	pub chunk_size: usize,
+
+	/// The delay before each fragment, in milliseconds.
+	#[serde(default)]
+	pub delay_ms: u64,
+
+	/// The models that the backend claims to serve, or empty for just
+	/// `mock`.
+	#[serde(default)]
+	pub models: Vec<String>,
+
+	/// The responses, in order. After the last response, the script starts
+	/// over. Without any responses, the backend echoes.
+	#[serde(default)]
+	pub responses: Vec<MockResponse>
+}
+
+/// A scripted response of a [`MockBackend`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MockResponse
+{
+	/// The content of the response.
+	#[serde(default)]
+	pub content: String,
+
+	/// Whether to echo the latest user message in place of the content.
+	#[serde(default)]
+	pub echo: bool,
+
+	/// The tools to call, after the content.
+	#[serde(default)]
+	pub tool_calls: Vec<MockToolCall>,
+
+	/// Why the backend stops generating, if it says.
+	#[serde(default)]
+	pub finish_reason: Option<FinishReason>,
+
+	/// The error to fail with, if any.
+	#[serde(default)]
+	pub error: Option<MockError>
+}
+
+/// A scripted tool call of a [`MockResponse`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MockToolCall
+{
+	/// The name of the tool.
+	pub name: String,
+
+	/// The arguments of the call, as JSON text.
+	#[serde(default)]
+	pub arguments: String
+}
+
+/// A scripted failure of a [`MockResponse`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MockError
+{
+	/// The kind of error.
+	#[serde(default)]
+	pub kind: MockErrorKind,
+
+	/// The number of content fragments to send before failing. At `0`, the
+	/// response fails to start at all. A failed response has no tool calls or
+	/// finish reason.
+	#[serde(default)]
+	pub after: usize,
+
+	/// The error message.
+	#[serde(default)]
+	pub message: String
+}
+
+/// The kind of a [`MockError`], after the variants of [`BackendError`].
+#[derive(
+	Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MockErrorKind
+{
+	/// The backend cannot be reached, so another may stand in.
+	Unavailable,
+
+	/// The backend failed.
+	#[default]
+	Backend,
+
+	/// The backend's response is garbled.
+	Response
+}
+
+impl MockError
+{
+	/// Get the [`BackendError`] that this scripted failure stands for.
+	fn to_backend_error(&self) -> BackendError
+	{
+		let message = self.message.clone();
+		match self.kind
+		{
+			MockErrorKind::Unavailable => BackendError::Unavailable(message),
+			MockErrorKind::Backend => BackendError::Backend(message),
+			MockErrorKind::Response => BackendError::Response(message)
+		}
+	}
+}
+
+impl MockBackend
+{
+	/// Create a backend that follows the specified script.
+	///
+	/// # Arguments
+	///
+	/// - `script`: The script.
+	pub fn new(script: MockScript) -> Self
+	{
+		Self {
+			script,
+			..Default::default()
+		}
+	}
+
+	/// Create a backend that echoes the latest user message.
+	pub fn echo() -> Self { Self::default() }
+
+	/// Create a backend that follows the script in the specified TOML file.
+	///
+	/// # Arguments
+	///
+	/// - `path`: The path to the script.
+	pub fn read(path: &str) -> Result<Self, String>
+	{
+		let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+		let script =
+			toml::from_str::<MockScript>(&text).map_err(|e| e.to_string())?;
+		Ok(Self::new(script))
+	}
+
+	/// Create a backend that follows the specified script, or, if there is
+	/// none or it is unusable, echoes.
+	///
+	/// # Arguments
+	///
+	/// - `path`: The path to the script, if any.
+	pub fn read_or_echo(path: Option<&str>) -> Self
+	{
+		match path
+		{
+			Some(path) => Self::read(path).unwrap_or_else(|e| {
+				warn!("Failed to read mock script, echoing: {path}: {e}");
+				Self::echo()
+			}),
+			None => Self::echo()
+		}
+	}
+
+	/// Create a backend for the configured script. `MOCK_SCRIPT` specifies the
+	/// path to the script, if any.
+	pub fn configured() -> Self
+	{
+		Self::read_or_echo(std::env::var("MOCK_SCRIPT").ok().as_deref())
+	}
+
+	/// Get the requests that the backend has received so far, in order.
+	pub fn requests(&self) -> Vec<CompletionRequest>
+	{
+		self.requests.lock().unwrap().clone()
+	}
+
+	/// Choose the response to the specified request: the next scripted
+	/// response, or an echo.
+	fn respond(&self, request: &CompletionRequest) -> MockResponse
+	{
+		let mut response = match self.script.responses.len()
+		{
+			0 => MockResponse {
+				echo: true,
+				..Default::default()
+			},
+			count =>
+			{
+				let next = self.next.fetch_add(1, Ordering::Relaxed);
+				self.script.responses[next % count].clone()
+			}
+		};
+		if response.echo
+		{
+			response.content = request
+				.messages
+				.iter()
+				.rev()
+				.find(|message| message.role == Role::User)
+				.map(|message| message.content.clone())
+				.unwrap_or_default();
+		}
+		response
+	}
+}
+
+#[async_trait]
+impl ChatBackend for MockBackend
+{
+	fn name(&self) -> &str { "mock" }
+
+	async fn list_models(&self) -> Result<Vec<String>, BackendError>
+	{
+		let mut models = match self.script.models.is_empty()
+		{
+			true => vec![MOCK_MODEL.to_string()],
+			false => self.script.models.clone()
+		};
+		models.sort();
+		Ok(models)
+	}
+
+	async fn stream_completion(
+		&self,
+		request: CompletionRequest
+	) -> Result<CompletionStream, BackendError>
+	{
+		let response = self.respond(&request);
+		self.requests.lock().unwrap().push(request);
+		if let Some(error) = &response.error
+			&& error.after == 0
+		{
+			return Err(error.to_backend_error())
+		}
+		let characters = response.content.chars().collect::<Vec<_>>();
+		let chunk_size = match self.script.chunk_size
+		{
+			0 => characters.len().max(1),
+			chunk_size => chunk_size
+		};
+		let mut chunks = characters
+			.chunks(chunk_size)
+			.map(|chunk| Ok(CompletionChunk::Content(chunk.iter().collect())))
+			.collect::<Vec<_>>();
+		// A failure strikes among the content fragments, and ends the response
+		// before any tool calls or finish reason.
+		match &response.error
+		{
+			Some(error) =>
+			{
+				chunks.truncate(error.after);
+				chunks.push(Err(error.to_backend_error()));
+			},
+			None =>
+			{
+				chunks.extend(response.tool_calls.iter().enumerate().map(
+					|(index, call)| {
+						Ok(CompletionChunk::ToolCall {
+							index,
+							id: Some(format!("call_{index}")),
+							name: Some(call.name.clone()),
+							arguments: call.arguments.clone()
+						})
+					}
+				));
+				if let Some(reason) = response.finish_reason
+				{
+					chunks.push(Ok(CompletionChunk::Finish(reason)));
+				}
+			}
+		}
+		let delay = Duration::from_millis(self.script.delay_ms);
+		Ok(stream::iter(chunks)
+			.then(move |chunk| async move {
+				if !delay.is_zero()
+				{
+					tokio::time::sleep(delay).await;
+				}
+				chunk
+			})
+			.boxed())
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The model that a [`MockBackend`] claims to serve, unless its script names
+/// others.
+const MOCK_MODEL: &str = "mock";
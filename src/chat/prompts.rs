@@ -0,0 +1,648 @@
+use minijinja::{Environment, ErrorKind as TemplateErrorKind};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fs,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	sync::{LazyLock, Once}
+};
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tracing::{trace, warn};
+
+use super::{OutputSchema, PromptInfo, PromptVariable, RandomTable};
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Prompt library.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The library of system prompts: every `.system` file in a single directory.
+/// A prompt file may begin with TOML front matter, delimited by `+++` lines,
+/// that names and describes the prompt, and declares its variables:
+///
+/// ```text
+/// +++
+/// name = "Game master"
+/// description = "Narrates a tabletop roleplaying game."
+///
+/// [[variables]]
+/// name = "setting"
+/// description = "Where the game takes place."
+/// default = "a generic fantasy realm"
+/// +++
+/// You are an AI dungeon master for a game set in {{ setting }}…
+/// ```
+///
+/// The prompt identifier is the stem of the file name. The front matter is
+/// optional; without it, the prompt is named by its identifier.
+///
+/// The body of a prompt is a [MiniJinja](minijinja) template, so it may use
+/// variables (`{{ setting }}`), conditionals (`{% if setting %}…{% endif %}`),
+/// and includes of other prompts by identifier (`{% include "rules" %}`).
+/// Undefined variables render as empty text.
+///
+/// A prompt that asks for structured output may name a JSON Schema file in the
+/// same directory, e.g., `schema = "crpg.schema.json"`, in its front matter.
+/// The assistant's responses must then match the schema. A prompt whose output
+/// comprises CRPG action steps may also set `world = true`, so that the steps
+/// drive the [model world](super::World). Finally, `tools = ["…"]` names the
+/// [tools](super::Tool) that the assistant may call.
+///
+/// The directory may also hold [random tables](RandomTable) as `.table` files,
+/// for the built-in `table` tool.
+#[derive(Debug, Clone)]
+pub struct PromptLibrary
+{
+	/// The directory that contains the prompt files.
+	dir: PathBuf,
+
+	/// The identifier of the default prompt, if any.
+	default: Option<String>
+}
+
+/// A system prompt from the [library](PromptLibrary).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt
+{
+	/// The description of the prompt.
+	pub info: PromptInfo,
+
+	/// The text of the prompt, excluding any front matter.
+	pub body: String
+}
+
+/// The front matter of a prompt file.
+#[derive(Debug, Default, Deserialize)]
+struct FrontMatter
+{
+	/// The human-readable name of the prompt.
+	name: Option<String>,
+
+	/// A short description of the prompt.
+	description: Option<String>,
+
+	/// The variables that the prompt template accepts.
+	#[serde(default)]
+	variables: Vec<PromptVariable>,
+
+	/// The file name of the JSON Schema that constrains the assistant's
+	/// output, relative to the prompt directory.
+	schema: Option<String>,
+
+	/// Whether the assistant's output comprises action steps that drive the
+	/// [model world](super::World).
+	#[serde(default)]
+	world: bool,
+
+	/// The names of the server-side tools that the assistant may call.
+	#[serde(default)]
+	tools: Vec<String>
+}
+
+/// An error that occurred while accessing the [`PromptLibrary`].
+#[derive(Debug, Error)]
+pub enum PromptError
+{
+	#[error("No such system prompt: {0}")]
+	NotFound(String),
+
+	#[error("I/O error: {0}")]
+	Io(#[from] std::io::Error),
+
+	#[error("Invalid front matter in {0}: {1}")]
+	FrontMatter(String, toml::de::Error),
+
+	#[error("Invalid template in {0}: {1:#}")]
+	Template(String, minijinja::Error),
+
+	#[error("Invalid schema in {0}: {1}")]
+	Schema(String, String),
+
+	#[error("Invalid random table {0}: {1}")]
+	Table(String, String)
+}
+
+impl PromptLibrary
+{
+	/// Create a library of the prompt files in the specified directory.
+	///
+	/// # Arguments
+	///
+	/// - `dir`: The directory that contains the prompt files.
+	/// - `default`: The identifier of the default prompt, if any.
+	pub fn new(dir: impl Into<PathBuf>, default: Option<String>) -> Self
+	{
+		Self {
+			dir: dir.into(),
+			default
+		}
+	}
+
+	/// Get the configured library. `PROMPT_DIR` specifies the directory of
+	/// prompt files, and `SYSTEM_PROMPT` optionally names the default prompt,
+	/// either by identifier or by file name. Only the stem of the file name
+	/// counts, so the default prompt always comes from the prompt directory,
+	/// whatever directory `SYSTEM_PROMPT` names.
+	pub fn configured() -> Self
+	{
+		let dir =
+			std::env::var("PROMPT_DIR").unwrap_or_else(|_| PROMPT_DIR.into());
+		let default = std::env::var("SYSTEM_PROMPT").ok().and_then(|prompt| {
+			let path = Path::new(&prompt);
+			if let Some(parent) = path.parent()
+				&& !parent.as_os_str().is_empty()
+				&& fs::canonicalize(parent).ok() != fs::canonicalize(&dir).ok()
+			{
+				// The library is configured afresh for every request, so only
+				// warn once.
+				static WARNED: Once = Once::new();
+				WARNED.call_once(|| {
+					warn!(
+						"SYSTEM_PROMPT is outside the prompt directory; using \
+						the prompt of the same name in {dir}: {prompt}"
+					)
+				});
+			}
+			path.file_stem()
+				.map(|stem| stem.to_string_lossy().to_string())
+		});
+		Self::new(dir, default)
+	}
+
+	/// List every prompt in the library, sorted by name. Unreadable prompt
+	/// files are skipped.
+	pub fn list(&self) -> Result<Vec<PromptInfo>, PromptError>
+	{
+		let entries = match fs::read_dir(&self.dir)
+		{
+			Ok(entries) => entries,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => return Err(e.into())
+		};
+		let mut prompts = vec![];
+		for entry in entries
+		{
+			let path = entry?.path();
+			let Some(id) = Self::id(&path)
+			else
+			{
+				continue
+			};
+			match self.read(&id)
+			{
+				Ok(prompt) => prompts.push(prompt.info),
+				Err(e) =>
+				{
+					warn!("Skipping system prompt {}: {e}", path.display())
+				}
+			}
+		}
+		prompts.sort_by(|a, b| a.name.cmp(&b.name));
+		Ok(prompts)
+	}
+
+	/// Load the specified prompt, or the default prompt if none is specified.
+	///
+	/// # Returns
+	///
+	/// The prompt, or `None` if no prompt is specified and there is no
+	/// default.
+	pub fn load(&self, id: Option<&str>)
+	-> Result<Option<Prompt>, PromptError>
+	{
+		match id.or(self.default.as_deref())
+		{
+			Some(id) => self.read(id).map(Some),
+			None => Ok(None)
+		}
+	}
+
+	/// Load the specified prompt, or the default prompt if none is specified,
+	/// and render its template. Variables without values take their declared
+	/// defaults.
+	///
+	/// # Arguments
+	///
+	/// - `id`: The identifier of the prompt, or `None` for the default.
+	/// - `values`: The values of the prompt's variables, keyed by name. Empty
+	///   values count as absent.
+	///
+	/// # Returns
+	///
+	/// The rendered prompt, or `None` if no prompt is specified and there is no
+	/// default.
+	pub fn render(
+		&self,
+		id: Option<&str>,
+		values: &BTreeMap<String, String>
+	) -> Result<Option<Prompt>, PromptError>
+	{
+		let Some(prompt) = self.load(id)?
+		else
+		{
+			return Ok(None)
+		};
+		let mut context = values
+			.iter()
+			.filter(|(_, value)| !value.trim().is_empty())
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect::<BTreeMap<_, _>>();
+		for variable in &prompt.info.variables
+		{
+			if let Some(default) = &variable.default
+			{
+				context
+					.entry(variable.name.clone())
+					.or_insert_with(|| default.clone());
+			}
+		}
+		let body = self
+			.environment()
+			.render_str(&prompt.body, context)
+			.map_err(|e| PromptError::Template(prompt.info.id.clone(), e))?;
+		Ok(Some(Prompt { body, ..prompt }))
+	}
+
+	/// Load the schema of the specified prompt.
+	///
+	/// # Arguments
+	///
+	/// - `info`: The description of the prompt.
+	///
+	/// # Returns
+	///
+	/// The compiled schema, or `None` if the prompt does not ask for structured
+	/// output.
+	pub fn schema(
+		&self,
+		info: &PromptInfo
+	) -> Result<Option<OutputSchema>, PromptError>
+	{
+		let Some(name) = info.schema.clone()
+		else
+		{
+			return Ok(None)
+		};
+		// As with prompts, a schema must reside in the prompt directory.
+		if name.contains(['/', '\\']) || name.starts_with('.')
+		{
+			return Err(PromptError::NotFound(name))
+		}
+		let text =
+			fs::read_to_string(self.dir.join(&name)).map_err(|e| {
+				match e.kind()
+				{
+					ErrorKind::NotFound => PromptError::NotFound(name.clone()),
+					_ => e.into()
+				}
+			})?;
+		let schema = serde_json::from_str(&text)
+			.map_err(|e| PromptError::Schema(name.clone(), e.to_string()))?;
+		OutputSchema::new(schema)
+			.map(Some)
+			.map_err(|e| PromptError::Schema(name, e))
+	}
+
+	/// Load every [random table](RandomTable) in the prompt directory, sorted
+	/// by identifier. Invalid table files are skipped.
+	pub fn tables(&self) -> Result<Vec<RandomTable>, PromptError>
+	{
+		let entries = match fs::read_dir(&self.dir)
+		{
+			Ok(entries) => entries,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => return Err(e.into())
+		};
+		let mut tables = vec![];
+		for entry in entries
+		{
+			let path = entry?.path();
+			if path
+				.extension()
+				.is_none_or(|extension| extension != TABLE_EXTENSION)
+			{
+				continue
+			}
+			match Self::read_table(&path)
+			{
+				Ok(table) => tables.push(table),
+				Err(e) =>
+				{
+					warn!("Skipping random table {}: {e}", path.display())
+				}
+			}
+		}
+		tables.sort_by(|a, b| a.id.cmp(&b.id));
+		Ok(tables)
+	}
+
+	/// Create a template environment whose includes resolve to other prompts
+	/// in the library.
+	fn environment(&self) -> Environment<'static>
+	{
+		let mut environment = Environment::new();
+		environment.set_keep_trailing_newline(true);
+		environment.set_trim_blocks(true);
+		environment.set_lstrip_blocks(true);
+		let library = self.clone();
+		environment.set_loader(move |id| match library.read(id)
+		{
+			Ok(prompt) => Ok(Some(prompt.body)),
+			Err(PromptError::NotFound(_)) => Ok(None),
+			Err(e) => Err(minijinja::Error::new(
+				TemplateErrorKind::InvalidOperation,
+				e.to_string()
+			))
+		});
+		environment
+	}
+
+	/// Get the identifiers of the prompts that depend on the specified file in
+	/// the prompt directory: the prompt stored in the file, if any, every
+	/// prompt whose schema it holds, and every prompt that includes one of
+	/// those, directly or indirectly. Unreadable prompt files are skipped.
+	///
+	/// # Arguments
+	///
+	/// - `path`: The path to the file, which may no longer exist.
+	///
+	/// # Returns
+	///
+	/// The identifiers of the dependent prompts, sorted.
+	fn dependents(&self, path: &Path) -> BTreeSet<String>
+	{
+		let name = path
+			.file_name()
+			.map(|name| name.to_string_lossy().to_string());
+		let mut dependents =
+			Self::id(path).into_iter().collect::<BTreeSet<_>>();
+		let prompts = fs::read_dir(&self.dir)
+			.into_iter()
+			.flatten()
+			.filter_map(|entry| Self::id(&entry.ok()?.path()))
+			.filter_map(|id| self.read(&id).ok())
+			.collect::<Vec<_>>();
+		dependents.extend(
+			prompts
+				.iter()
+				.filter(|prompt| name.is_some() && prompt.info.schema == name)
+				.map(|prompt| prompt.info.id.clone())
+		);
+		// Follow the includes until no more prompts turn up.
+		let includes = prompts
+			.iter()
+			.map(|prompt| (&prompt.info.id, included_prompts(&prompt.body)))
+			.collect::<Vec<_>>();
+		loop
+		{
+			let count = dependents.len();
+			for (id, included) in &includes
+			{
+				if included.iter().any(|id| dependents.contains(id))
+				{
+					dependents.insert(id.to_string());
+				}
+			}
+			if dependents.len() == count
+			{
+				return dependents
+			}
+		}
+	}
+
+	/// Get the identifier of the prompt stored in the specified file, or
+	/// `None` if the file is not a prompt file.
+	fn id(path: &Path) -> Option<String>
+	{
+		if path
+			.extension()
+			.is_none_or(|extension| extension != EXTENSION)
+		{
+			return None
+		}
+		path.file_stem()
+			.map(|stem| stem.to_string_lossy().to_string())
+	}
+
+	/// Read the random table stored in the specified file.
+	fn read_table(path: &Path) -> Result<RandomTable, PromptError>
+	{
+		let id = path
+			.file_stem()
+			.map(|stem| stem.to_string_lossy().to_string())
+			.unwrap_or_default();
+		let text = fs::read_to_string(path)?;
+		let table = toml::from_str::<RandomTable>(&text)
+			.map_err(|e| PromptError::Table(id.clone(), e.to_string()))?;
+		table
+			.validate()
+			.map_err(|e| PromptError::Table(id.clone(), e))?;
+		Ok(RandomTable { id, ..table })
+	}
+
+	/// Read the specified prompt from its file.
+	fn read(&self, id: &str) -> Result<Prompt, PromptError>
+	{
+		// Only plain file stems name prompts, so that a request cannot reach
+		// outside the prompt directory.
+		if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.')
+		{
+			return Err(PromptError::NotFound(id.to_string()))
+		}
+		let path = self.dir.join(format!("{id}.{EXTENSION}"));
+		let bytes = fs::read(&path).map_err(|e| match e.kind()
+		{
+			ErrorKind::NotFound => PromptError::NotFound(id.to_string()),
+			_ => e.into()
+		})?;
+		let text = String::from_utf8_lossy(&bytes);
+		let (front_matter, body) = split_front_matter(&text);
+		let front_matter = match front_matter
+		{
+			Some(front_matter) => toml::from_str(front_matter)
+				.map_err(|e| PromptError::FrontMatter(id.to_string(), e))?,
+			None => FrontMatter::default()
+		};
+		Ok(Prompt {
+			info: PromptInfo {
+				id: id.to_string(),
+				name: front_matter.name.unwrap_or_else(|| id.to_string()),
+				description: front_matter.description,
+				is_default: self.default.as_deref() == Some(id),
+				variables: front_matter.variables,
+				schema: front_matter.schema,
+				world: front_matter.world,
+				tools: front_matter.tools
+			},
+			body: body.to_string()
+		})
+	}
+}
+
+/// Get the identifiers of the prompts that the specified prompt body
+/// includes, i.e., every string literal in its `include` tags, like
+/// `{% include "rules" %}` or `{% include ["house", "rules"] %}`.
+fn included_prompts(body: &str) -> Vec<String>
+{
+	let mut included = vec![];
+	let mut rest = body;
+	while let Some(start) = rest.find("{%")
+	{
+		let tag = &rest[start + 2..];
+		let end = tag.find("%}").unwrap_or(tag.len());
+		rest = &tag[end..];
+		let tag = tag[..end].trim_matches(['-', '+', '~']).trim_start();
+		let Some(arguments) = tag.strip_prefix("include")
+		else
+		{
+			continue
+		};
+		// Every other piece between quotes is a literal.
+		for quote in ['"', '\'']
+		{
+			included.extend(
+				arguments
+					.split(quote)
+					.skip(1)
+					.step_by(2)
+					.map(str::to_string)
+			);
+		}
+	}
+	included
+}
+
+/// Split the specified prompt file into its front matter, if any, and its
+/// body.
+fn split_front_matter(text: &str) -> (Option<&str>, &str)
+{
+	let Some(rest) = text
+		.strip_prefix(FRONT_MATTER_DELIMITER)
+		.and_then(|rest| rest.strip_prefix('\n'))
+	else
+	{
+		return (None, text)
+	};
+	let mut offset = 0;
+	for line in rest.split_inclusive('\n')
+	{
+		if line.trim_end() == FRONT_MATTER_DELIMITER
+		{
+			return (Some(&rest[..offset]), &rest[offset + line.len()..])
+		}
+		offset += line.len();
+	}
+	// The front matter is unterminated, so treat the whole file as the body.
+	(None, text)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Prompt watcher.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Subscribe to changes in the [configured](PromptLibrary::configured) prompt
+/// library. The first subscription starts watching the prompt directory, and
+/// every subscriber then receives the identifier of each prompt whose file is
+/// created, modified, or removed, or that depends on such a file, because it
+/// includes the prompt or uses the file as its schema. Editors often touch a
+/// file several times per save, so the same identifier may arrive repeatedly.
+pub fn subscribe_prompt_changes() -> broadcast::Receiver<String>
+{
+	static WATCHER: LazyLock<PromptWatcher> =
+		LazyLock::new(|| PromptWatcher::new(&PromptLibrary::configured()));
+	WATCHER.changes.subscribe()
+}
+
+/// Watches the directory of a [`PromptLibrary`] and broadcasts the identifiers
+/// of changed prompts.
+struct PromptWatcher
+{
+	/// The broadcaster of changed prompt identifiers.
+	changes: broadcast::Sender<String>,
+
+	/// The file watcher, or `None` if the directory could not be watched.
+	/// Dropping the file watcher stops watching.
+	_watcher: Option<RecommendedWatcher>
+}
+
+impl PromptWatcher
+{
+	/// Start watching the specified library. If its directory cannot be
+	/// watched, e.g., because it does not exist, then no changes are ever
+	/// broadcast.
+	///
+	/// # Arguments
+	///
+	/// - `library`: The prompt library to watch.
+	fn new(library: &PromptLibrary) -> Self
+	{
+		let (changes, _) = broadcast::channel(WATCHER_CAPACITY);
+		let handler = {
+			let changes = changes.clone();
+			let library = library.clone();
+			move |event: notify::Result<Event>| match event
+			{
+				Ok(event) if !event.kind.is_access() =>
+				{
+					let ids = event
+						.paths
+						.iter()
+						.flat_map(|path| library.dependents(path))
+						.collect::<BTreeSet<_>>();
+					for id in ids
+					{
+						trace!("System prompt changed: {id}");
+						// Nobody may be listening, and that is fine.
+						let _ = changes.send(id);
+					}
+				},
+				Ok(_) =>
+				{},
+				Err(e) => warn!("Error watching system prompts: {e}")
+			}
+		};
+		let watcher = notify::recommended_watcher(handler).and_then(|mut w| {
+			w.watch(&library.dir, RecursiveMode::NonRecursive)?;
+			Ok(w)
+		});
+		let watcher = match watcher
+		{
+			Ok(watcher) => Some(watcher),
+			Err(e) =>
+			{
+				warn!(
+					"Not watching system prompts in {}: {e}",
+					library.dir.display()
+				);
+				None
+			}
+		};
+		Self {
+			changes,
+			_watcher: watcher
+		}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The default directory of prompt files.
+const PROMPT_DIR: &str = "data";
+
+/// The extension of prompt files.
+const EXTENSION: &str = "system";
+
+/// The extension of [random table](RandomTable) files.
+const TABLE_EXTENSION: &str = "table";
+
+/// The line that opens and closes the front matter of a prompt file.
+const FRONT_MATTER_DELIMITER: &str = "+++";
+
+/// The number of prompt changes that the [watcher](PromptWatcher) buffers for
+/// each subscriber. A subscriber that falls further behind skips the oldest
+/// changes.
+const WATCHER_CAPACITY: usize = 16;
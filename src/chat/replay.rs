@@ -0,0 +1,418 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, stream};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+	path::{Path, PathBuf},
+	sync::atomic::{AtomicUsize, Ordering},
+	time::{Duration, Instant}
+};
+use thiserror::Error;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{
+	BackendError, BackendKind, ChatBackend, CompletionRequest,
+	CompletionStream, ResponseLines, ollama_response, openai_response
+};
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Fixtures.                                  //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A chat completion as it passed over the wire between the server and a
+/// model server: the request, and the status and raw body of the response.
+/// A [`Recorder`] writes each exchange of an [`OpenAiBackend`] or
+/// [`OllamaBackend`] to a JSON fixture file, and a [`ReplayBackend`] plays the
+/// fixtures back through the same parsing as those backends.
+///
+/// [`OpenAiBackend`]: super::OpenAiBackend
+/// [`OllamaBackend`]: super::OllamaBackend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange
+{
+	/// The kind of the backend that answered, i.e., the API of the response.
+	pub kind: BackendKind,
+
+	/// When the request was sent.
+	pub recorded: DateTime<Utc>,
+
+	/// The request.
+	pub request: CompletionRequest,
+
+	/// The HTTP status of the response.
+	pub status: u16,
+
+	/// The lines of the body of the response, in order.
+	#[serde(default)]
+	pub lines: Vec<ExchangeLine>
+}
+
+/// A line of the body of the response of an [`Exchange`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExchangeLine
+{
+	/// The number of milliseconds between the request and the line.
+	pub elapsed_ms: u64,
+
+	/// The line, with its line feed, if any.
+	pub text: String
+}
+
+/// An error that occurred while reading or writing a fixture.
+#[derive(Debug, Error)]
+pub enum FixtureError
+{
+	#[error("I/O error: {0}: {1}")]
+	Io(PathBuf, std::io::Error),
+
+	#[error("Invalid fixture: {0}: {1}")]
+	Json(PathBuf, serde_json::Error)
+}
+
+impl Exchange
+{
+	/// Read the exchange in the specified fixture file.
+	///
+	/// # Arguments
+	///
+	/// - `path`: The path to the fixture.
+	pub fn read(path: &Path) -> Result<Self, FixtureError>
+	{
+		let text = std::fs::read_to_string(path)
+			.map_err(|e| FixtureError::Io(path.into(), e))?;
+		serde_json::from_str(&text)
+			.map_err(|e| FixtureError::Json(path.into(), e))
+	}
+
+	/// Read the exchanges at the specified path: either a single fixture file,
+	/// or a directory of `.json` fixture files, in order of their names.
+	///
+	/// # Arguments
+	///
+	/// - `path`: The path to the fixture or directory.
+	pub fn read_all(path: &Path) -> Result<Vec<Self>, FixtureError>
+	{
+		if !path.is_dir()
+		{
+			return Ok(vec![Self::read(path)?])
+		}
+		let mut paths = std::fs::read_dir(path)
+			.map_err(|e| FixtureError::Io(path.into(), e))?
+			.filter_map(Result::ok)
+			.map(|entry| entry.path())
+			.filter(|path| {
+				path.extension().and_then(|e| e.to_str())
+					== Some(FIXTURE_EXTENSION)
+			})
+			.collect::<Vec<_>>();
+		paths.sort();
+		paths.iter().map(|path| Self::read(path)).collect()
+	}
+
+	/// Write the exchange to a new fixture file in the specified directory,
+	/// creating the directory if necessary. The file is named for the time of
+	/// the request, so that the fixtures sort in order.
+	///
+	/// # Arguments
+	///
+	/// - `dir`: The directory of fixtures.
+	///
+	/// # Returns
+	///
+	/// The path to the fixture.
+	pub fn write(&self, dir: &Path) -> Result<PathBuf, FixtureError>
+	{
+		std::fs::create_dir_all(dir)
+			.map_err(|e| FixtureError::Io(dir.into(), e))?;
+		let path = dir.join(format!(
+			"{}-{}.{FIXTURE_EXTENSION}",
+			self.recorded.format("%Y%m%dT%H%M%S%.3f"),
+			&Uuid::new_v4().simple().to_string()[..8]
+		));
+		let text = serde_json::to_string_pretty(self)
+			.map_err(|e| FixtureError::Json(path.clone(), e))?;
+		std::fs::write(&path, text)
+			.map_err(|e| FixtureError::Io(path.clone(), e))?;
+		Ok(path)
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Recording.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Records the [exchanges](Exchange) of a backend with its model server to
+/// fixture files. `RECORD_DIR` enables recording for every configured backend
+/// that talks to a model server.
+#[derive(Debug, Clone)]
+pub struct Recorder
+{
+	/// The directory of fixtures.
+	dir: PathBuf
+}
+
+impl Recorder
+{
+	/// Create a recorder that writes fixtures to the specified directory.
+	///
+	/// # Arguments
+	///
+	/// - `dir`: The directory of fixtures.
+	pub fn new(dir: impl Into<PathBuf>) -> Self { Self { dir: dir.into() } }
+
+	/// Start recording an exchange, as its request is sent.
+	///
+	/// # Arguments
+	///
+	/// - `kind`: The kind of the backend.
+	/// - `request`: The request.
+	pub(super) fn start(
+		&self,
+		kind: BackendKind,
+		request: &CompletionRequest
+	) -> Recording
+	{
+		Recording {
+			exchange: Exchange {
+				kind,
+				recorded: Utc::now(),
+				request: request.clone(),
+				status: 0,
+				lines: vec![]
+			},
+			started: Instant::now(),
+			dir: self.dir.clone(),
+			responded: false
+		}
+	}
+}
+
+/// Records an [`Exchange`] as its response streams, and writes it once the
+/// body ends or is dropped, e.g., because the chat was cancelled.
+pub(super) struct Recording
+{
+	/// The exchange so far.
+	exchange: Exchange,
+
+	/// When the request was sent.
+	started: Instant,
+
+	/// The directory of fixtures.
+	dir: PathBuf,
+
+	/// Whether the server responded. A request that never reached the server
+	/// has nothing to replay, so it is not written.
+	responded: bool
+}
+
+impl Recording
+{
+	/// Record the specified response as its body streams.
+	///
+	/// # Arguments
+	///
+	/// - `status`: The HTTP status of the response.
+	/// - `lines`: The lines of the body of the response.
+	///
+	/// # Returns
+	///
+	/// The same lines.
+	pub(super) fn record(
+		mut self,
+		status: StatusCode,
+		lines: ResponseLines
+	) -> ResponseLines
+	{
+		self.exchange.status = status.as_u16();
+		self.responded = true;
+		lines
+			.map(move |line| {
+				if let Ok(line) = &line
+				{
+					self.exchange.lines.push(ExchangeLine {
+						elapsed_ms: self.started.elapsed().as_millis() as u64,
+						text: String::from_utf8_lossy(line).into_owned()
+					});
+				}
+				line
+			})
+			.boxed()
+	}
+}
+
+impl Drop for Recording
+{
+	fn drop(&mut self)
+	{
+		if !self.responded
+		{
+			return
+		}
+		match self.exchange.write(&self.dir)
+		{
+			Ok(path) => debug!("Recorded exchange: {}", path.display()),
+			Err(e) => warn!("Failed to record exchange: {e}")
+		}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                  Replay.                                   //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [`ChatBackend`] that plays back recorded [exchanges](Exchange) in turn,
+/// whatever the request, so that the server's handling of real responses
+/// can be tested without a model server. The recorded bodies go through the
+/// same parsing as the live responses of their kind of backend. After the last
+/// exchange, the playback starts over.
+#[derive(Debug, Default)]
+pub struct ReplayBackend
+{
+	/// The exchanges, in order.
+	exchanges: Vec<Exchange>,
+
+	/// The index of the next exchange.
+	next: AtomicUsize,
+
+	/// Whether to reproduce the timing of the recorded responses.
+	timed: bool
+}
+
+impl ReplayBackend
+{
+	/// Create a backend that plays back the specified exchanges, as fast as
+	/// possible.
+	///
+	/// # Arguments
+	///
+	/// - `exchanges`: The exchanges, in order.
+	pub fn new(exchanges: Vec<Exchange>) -> Self
+	{
+		Self {
+			exchanges,
+			..Default::default()
+		}
+	}
+
+	/// Create a backend that plays back the exchanges at the specified path:
+	/// either a single fixture file, or a directory of fixture files.
+	///
+	/// # Arguments
+	///
+	/// - `path`: The path to the fixture or directory.
+	pub fn read(path: impl AsRef<Path>) -> Result<Self, FixtureError>
+	{
+		Ok(Self::new(Exchange::read_all(path.as_ref())?))
+	}
+
+	/// Create a backend for the specified fixtures, or, if there are none or
+	/// they are unusable, a backend that fails every request.
+	///
+	/// # Arguments
+	///
+	/// - `path`: The path to the fixture or directory, if any.
+	pub fn read_or_empty(path: Option<&str>) -> Self
+	{
+		match path
+		{
+			Some(path) => Self::read(path).unwrap_or_else(|e| {
+				warn!("Failed to read fixtures: {path}: {e}");
+				Self::default()
+			}),
+			None =>
+			{
+				warn!("No fixtures to replay");
+				Self::default()
+			}
+		}
+	}
+
+	/// Create a backend for the configured fixtures. `REPLAY_PATH` specifies
+	/// the fixture file or directory. The playback reproduces the recorded
+	/// timing.
+	pub fn configured() -> Self
+	{
+		Self::read_or_empty(std::env::var("REPLAY_PATH").ok().as_deref())
+			.with_timing(true)
+	}
+
+	/// Set whether to reproduce the timing of the recorded responses.
+	///
+	/// # Arguments
+	///
+	/// - `timed`: Whether to wait between pieces as long as the backend did.
+	pub fn with_timing(mut self, timed: bool) -> Self
+	{
+		self.timed = timed;
+		self
+	}
+}
+
+#[async_trait]
+impl ChatBackend for ReplayBackend
+{
+	fn name(&self) -> &str { "replay" }
+
+	async fn list_models(&self) -> Result<Vec<String>, BackendError>
+	{
+		let mut models = self
+			.exchanges
+			.iter()
+			.filter_map(|exchange| exchange.request.params.model.clone())
+			.collect::<Vec<_>>();
+		models.sort();
+		models.dedup();
+		Ok(models)
+	}
+
+	async fn stream_completion(
+		&self,
+		request: CompletionRequest
+	) -> Result<CompletionStream, BackendError>
+	{
+		if self.exchanges.is_empty()
+		{
+			return Err(BackendError::Backend("No exchanges to replay".into()))
+		}
+		let next = self.next.fetch_add(1, Ordering::Relaxed);
+		let exchange = &self.exchanges[next % self.exchanges.len()];
+		if request.messages != exchange.request.messages
+		{
+			debug!("Replaying an exchange for a different request");
+		}
+		let status = StatusCode::from_u16(exchange.status)
+			.map_err(|e| BackendError::Response(e.to_string()))?;
+		let timed = self.timed;
+		let mut elapsed = 0;
+		let lines = stream::iter(exchange.lines.clone())
+			.then(move |line| {
+				let delay = line.elapsed_ms.saturating_sub(elapsed);
+				elapsed = elapsed.max(line.elapsed_ms);
+				async move {
+					if timed && delay > 0
+					{
+						tokio::time::sleep(Duration::from_millis(delay)).await;
+					}
+					Ok(line.text.into_bytes())
+				}
+			})
+			.boxed();
+		match exchange.kind
+		{
+			BackendKind::OpenAi => openai_response(status, lines).await,
+			BackendKind::Ollama => ollama_response(status, lines).await,
+			kind => Err(BackendError::Backend(format!(
+				"Cannot replay an exchange of a {kind:?} backend"
+			)))
+		}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The extension of fixture files.
+const FIXTURE_EXTENSION: &str = "json";
@@ -0,0 +1,151 @@
+use chrono::Local;
+use leptos::{prelude::*, server::LocalResource, task::spawn_local};
+use leptos_router::{components::A, hooks::use_navigate};
+use log::debug;
+use uuid::Uuid;
+
+use super::{ConversationSummary, duplicate_conversation, list_conversations};
+
+////////////////////////////////////////////////////////////////////////////////
+//                           Conversation sidebar.                            //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Notifies the [sidebar](ConversationSidebar) that the saved conversations
+/// have changed, so that it refreshes its list. Provided as context by the
+/// page that hosts both the sidebar and the [chat](super::Chat).
+#[derive(Debug, Copy, Clone)]
+pub struct ConversationsChanged(pub Trigger);
+
+/// Represents the sidebar that lists the saved conversations, most recently
+/// updated first, and offers actions to start a new conversation or duplicate
+/// the current one.
+///
+/// # Arguments
+///
+/// * `current` - Specifies the current conversation, if any.
+#[component]
+pub fn ConversationSidebar(current: Signal<Option<Uuid>>) -> impl IntoView
+{
+	let changed = use_context::<ConversationsChanged>();
+	// The saved conversations. We need to use a local resource in order to
+	// read this signal in a closure. Refreshes whenever a conversation is
+	// saved.
+	let conversations = LocalResource::new(move || {
+		if let Some(ConversationsChanged(changed)) = changed
+		{
+			changed.track();
+		}
+		async move { list_conversations().await }
+	});
+	let conversations = move || {
+		conversations
+			.get()
+			.and_then(|conversations| conversations.as_ref().ok().cloned())
+			.unwrap_or_default()
+	};
+	// Whether the current conversation has been saved, and can therefore be
+	// duplicated.
+	let current_saved = move || {
+		current().is_some_and(|id| conversations().iter().any(|c| c.id == id))
+	};
+	let navigate = use_navigate();
+	// How to start a new conversation.
+	let new_chat = {
+		let navigate = navigate.clone();
+		move |_| navigate(&chat_path(Uuid::new_v4()), Default::default())
+	};
+	// How to duplicate the current conversation, and then switch to the copy.
+	let duplicate_chat = move |_| {
+		let Some(id) = current.get_untracked()
+		else
+		{
+			return
+		};
+		let navigate = navigate.clone();
+		spawn_local(async move {
+			match duplicate_conversation(id).await
+			{
+				Ok(copy) =>
+				{
+					if let Some(ConversationsChanged(changed)) = changed
+					{
+						changed.notify();
+					}
+					navigate(&chat_path(copy.id), Default::default());
+				},
+				Err(e) => debug!("Failed to duplicate conversation: {e}")
+			}
+		});
+	};
+	view! {
+		<div class="bg-base-200 min-h-full w-72 p-4 text-left flex flex-col">
+			<div class="flex gap-2 mb-4">
+				<button
+					type="button"
+					class="btn btn-primary btn-sm flex-1"
+					on:click=new_chat
+				>
+					"New chat"
+				</button>
+				<button
+					type="button"
+					class="btn btn-outline btn-sm flex-1"
+					disabled=move || !current_saved()
+					on:click=duplicate_chat
+				>
+					"Duplicate chat"
+				</button>
+			</div>
+			<ul class="menu p-0 w-full">
+				<For
+					each=conversations
+					key=|summary| (summary.id, summary.title.clone(), summary.updated)
+					children=move |summary| view! {
+						<ConversationItem
+							summary=summary
+							current=current
+						/>
+					}
+				/>
+			</ul>
+		</div>
+	}
+}
+
+/// Represents a saved conversation in the [sidebar](ConversationSidebar).
+///
+/// # Arguments
+///
+/// * `summary` - Specifies the conversation.
+/// * `current` - Specifies the current conversation, if any.
+#[component]
+pub fn ConversationItem(
+	summary: ConversationSummary,
+	current: Signal<Option<Uuid>>
+) -> impl IntoView
+{
+	let id = summary.id;
+	let updated = summary
+		.updated
+		.with_timezone(&Local)
+		.format("%b %e, %H:%M")
+		.to_string();
+	view! {
+		<li>
+			<A
+				href=chat_path(id)
+				attr:class=move || {
+					if current() == Some(id) { "menu-active" } else { "" }
+				}
+			>
+				<div class="flex flex-col min-w-0">
+					<span class="truncate">{summary.title}</span>
+					<span class="text-xs opacity-60">{updated}</span>
+				</div>
+			</A>
+		</li>
+	}
+}
+
+/// Get the path of the page that hosts the specified conversation.
+pub fn chat_path(id: Uuid) -> String { format!("/c/{id}") }
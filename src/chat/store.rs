@@ -0,0 +1,459 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::{
+	cmp::Reverse,
+	fs,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	sync::{LazyLock, Mutex}
+};
+use thiserror::Error;
+use tracing::{debug, info, trace};
+use uuid::Uuid;
+
+use super::{
+	Conversation, ConversationSettings, ConversationSummary, MessageTree, Role
+};
+
+////////////////////////////////////////////////////////////////////////////////
+//                            Conversation store.                             //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A persistent store of [conversations](Conversation). Implementations are
+/// synchronous, so callers in an asynchronous context should use
+/// [`tokio::task::spawn_blocking`].
+pub trait ConversationStore: Send + Sync
+{
+	/// List the summaries of every saved conversation, most recently updated
+	/// first.
+	fn list(&self) -> Result<Vec<ConversationSummary>, StoreError>;
+
+	/// Load the specified conversation. Answer `None` if no such conversation
+	/// has been saved.
+	fn load(&self, id: Uuid) -> Result<Option<Conversation>, StoreError>;
+
+	/// Save the specified conversation, replacing its message tree and
+	/// settings. The conversation is created if necessary, in which case its
+	/// title is derived from the active branch.
+	fn save(
+		&self,
+		id: Uuid,
+		tree: MessageTree,
+		settings: ConversationSettings
+	) -> Result<ConversationSummary, StoreError>;
+
+	/// Rename the specified conversation.
+	fn rename(&self, id: Uuid, title: String) -> Result<(), StoreError>;
+
+	/// Delete the specified conversation. Deleting a conversation that does not
+	/// exist is not an error.
+	fn delete(&self, id: Uuid) -> Result<(), StoreError>;
+}
+
+/// An error that occurred while accessing a [`ConversationStore`].
+#[derive(Debug, Error)]
+pub enum StoreError
+{
+	#[error("No such conversation: {0}")]
+	NotFound(Uuid),
+
+	#[error("I/O error: {0}")]
+	Io(#[from] std::io::Error),
+
+	#[error("JSON error: {0}")]
+	Json(#[from] serde_json::Error),
+
+	#[error("SQLite error: {0}")]
+	Sqlite(#[from] rusqlite::Error)
+}
+
+/// Get the configured [`ConversationStore`]. Set `CONVERSATION_STORE` to
+/// `sqlite` to use an embedded SQLite database; otherwise conversations are
+/// saved as JSON files. `CONVERSATION_PATH` specifies the directory of JSON
+/// files or the SQLite database file, as appropriate.
+pub fn conversation_store() -> &'static dyn ConversationStore
+{
+	static STORE: LazyLock<Box<dyn ConversationStore>> = LazyLock::new(|| {
+		let kind = std::env::var("CONVERSATION_STORE").unwrap_or_default();
+		let path = std::env::var("CONVERSATION_PATH").ok();
+		let store: Box<dyn ConversationStore> = if kind
+			.eq_ignore_ascii_case("sqlite")
+		{
+			let path = path.unwrap_or_else(|| SQLITE_PATH.to_string());
+			info!("Conversations are saved in SQLite database: {path}");
+			Box::new(
+				SqliteConversationStore::open(path)
+					.expect("Failed to open conversation database")
+			)
+		}
+		else
+		{
+			let path = path.unwrap_or_else(|| JSON_PATH.to_string());
+			info!("Conversations are saved as JSON files in: {path}");
+			Box::new(JsonConversationStore::new(path))
+		};
+		store
+	});
+	STORE.as_ref()
+}
+
+/// Derive a title for a new conversation from the first user message along its
+/// active branch.
+fn derive_title(tree: &MessageTree) -> String
+{
+	tree.active_branch()
+		.iter()
+		.find(|(_, message)| message.role == Role::User)
+		.and_then(|(_, message)| {
+			message.content.lines().find(|line| !line.trim().is_empty())
+		})
+		.map(|line| {
+			let line = line.trim();
+			match line.char_indices().nth(TITLE_LENGTH)
+			{
+				Some((end, _)) => format!("{}…", &line[..end]),
+				None => line.to_string()
+			}
+		})
+		.unwrap_or_else(|| UNTITLED.to_string())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                             JSON file storage.                             //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [`ConversationStore`] that saves each conversation as a JSON file in a
+/// single directory.
+#[derive(Debug)]
+pub struct JsonConversationStore
+{
+	/// The directory that contains the conversation files.
+	dir: PathBuf,
+
+	/// Serializes access to the directory, so that concurrent saves cannot
+	/// clobber each other.
+	lock: Mutex<()>
+}
+
+impl JsonConversationStore
+{
+	/// Create a store that keeps its conversation files in the specified
+	/// directory. The directory is created on first save.
+	pub fn new(dir: impl Into<PathBuf>) -> Self
+	{
+		Self {
+			dir: dir.into(),
+			lock: Mutex::new(())
+		}
+	}
+
+	/// Get the path of the file that holds the specified conversation.
+	fn path(&self, id: Uuid) -> PathBuf { self.dir.join(format!("{id}.json")) }
+
+	/// Read the conversation from the specified file.
+	fn read(path: &Path) -> Result<Option<Conversation>, StoreError>
+	{
+		match fs::read(path)
+		{
+			Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e.into())
+		}
+	}
+
+	/// Write the conversation to its file. The file is replaced atomically, so
+	/// that a crash cannot leave a truncated conversation behind.
+	fn write(&self, conversation: &Conversation) -> Result<(), StoreError>
+	{
+		fs::create_dir_all(&self.dir)?;
+		let path = self.path(conversation.id);
+		let temp = path.with_extension("json.tmp");
+		fs::write(&temp, serde_json::to_vec_pretty(conversation)?)?;
+		fs::rename(&temp, &path)?;
+		trace!("Wrote conversation: {}", path.display());
+		Ok(())
+	}
+}
+
+impl ConversationStore for JsonConversationStore
+{
+	fn list(&self) -> Result<Vec<ConversationSummary>, StoreError>
+	{
+		let _guard = self.lock.lock().unwrap();
+		let entries = match fs::read_dir(&self.dir)
+		{
+			Ok(entries) => entries,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => return Err(e.into())
+		};
+		let mut summaries = vec![];
+		for entry in entries
+		{
+			let path = entry?.path();
+			if path.extension().is_none_or(|extension| extension != "json")
+			{
+				continue
+			}
+			match Self::read(&path)
+			{
+				Ok(conversation) =>
+				{
+					summaries.extend(conversation.map(|c| c.summary()))
+				},
+				// Skip unreadable files rather than hiding every conversation.
+				Err(e) => debug!("Skipping {}: {e}", path.display())
+			}
+		}
+		summaries.sort_by_key(|summary| Reverse(summary.updated));
+		Ok(summaries)
+	}
+
+	fn load(&self, id: Uuid) -> Result<Option<Conversation>, StoreError>
+	{
+		let _guard = self.lock.lock().unwrap();
+		Self::read(&self.path(id))
+	}
+
+	fn save(
+		&self,
+		id: Uuid,
+		tree: MessageTree,
+		settings: ConversationSettings
+	) -> Result<ConversationSummary, StoreError>
+	{
+		let _guard = self.lock.lock().unwrap();
+		let now = Utc::now();
+		let conversation = match Self::read(&self.path(id))?
+		{
+			Some(conversation) => Conversation {
+				updated: now,
+				tree,
+				settings,
+				..conversation
+			},
+			None => Conversation {
+				id,
+				title: derive_title(&tree),
+				created: now,
+				updated: now,
+				tree,
+				settings
+			}
+		};
+		self.write(&conversation)?;
+		Ok(conversation.summary())
+	}
+
+	fn rename(&self, id: Uuid, title: String) -> Result<(), StoreError>
+	{
+		let _guard = self.lock.lock().unwrap();
+		let conversation =
+			Self::read(&self.path(id))?.ok_or(StoreError::NotFound(id))?;
+		self.write(&Conversation {
+			title,
+			updated: Utc::now(),
+			..conversation
+		})
+	}
+
+	fn delete(&self, id: Uuid) -> Result<(), StoreError>
+	{
+		let _guard = self.lock.lock().unwrap();
+		match fs::remove_file(self.path(id))
+		{
+			Ok(()) => Ok(()),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e.into())
+		}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              SQLite storage.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [`ConversationStore`] backed by an embedded SQLite database. The message
+/// tree and settings of each conversation are kept as JSON documents.
+#[derive(Debug)]
+pub struct SqliteConversationStore
+{
+	/// The database connection.
+	connection: Mutex<Connection>
+}
+
+impl SqliteConversationStore
+{
+	/// Open the store in the specified database file, creating the file and
+	/// its schema as necessary.
+	pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError>
+	{
+		Self::with_connection(Connection::open(path)?)
+	}
+
+	/// Create the store atop the specified connection, creating the schema as
+	/// necessary.
+	fn with_connection(connection: Connection) -> Result<Self, StoreError>
+	{
+		connection.execute_batch(
+			"CREATE TABLE IF NOT EXISTS conversations (
+				id TEXT PRIMARY KEY NOT NULL,
+				title TEXT NOT NULL,
+				created TEXT NOT NULL,
+				updated TEXT NOT NULL,
+				messages TEXT NOT NULL,
+				settings TEXT NOT NULL DEFAULT '{}'
+			);"
+		)?;
+		// Databases created before conversations had settings lack the column.
+		if connection
+			.prepare("SELECT settings FROM conversations LIMIT 0")
+			.is_err()
+		{
+			connection.execute_batch(
+				"ALTER TABLE conversations
+				ADD COLUMN settings TEXT NOT NULL DEFAULT '{}';"
+			)?;
+		}
+		Ok(Self {
+			connection: Mutex::new(connection)
+		})
+	}
+}
+
+impl ConversationStore for SqliteConversationStore
+{
+	fn list(&self) -> Result<Vec<ConversationSummary>, StoreError>
+	{
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare(
+			"SELECT id, title, created, updated FROM conversations
+			ORDER BY updated DESC"
+		)?;
+		let summaries = statement
+			.query_map([], |row| {
+				Ok(ConversationSummary {
+					id: row.get(0)?,
+					title: row.get(1)?,
+					created: row.get(2)?,
+					updated: row.get(3)?
+				})
+			})?
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(summaries)
+	}
+
+	fn load(&self, id: Uuid) -> Result<Option<Conversation>, StoreError>
+	{
+		let connection = self.connection.lock().unwrap();
+		let row = connection
+			.query_row(
+				"SELECT title, created, updated, messages, settings
+				FROM conversations WHERE id = ?1",
+				params![id],
+				|row| {
+					Ok((
+						row.get::<_, String>(0)?,
+						row.get::<_, DateTime<Utc>>(1)?,
+						row.get::<_, DateTime<Utc>>(2)?,
+						row.get::<_, String>(3)?,
+						row.get::<_, String>(4)?
+					))
+				}
+			)
+			.optional()?;
+		match row
+		{
+			Some((title, created, updated, messages, settings)) =>
+			{
+				Ok(Some(Conversation {
+					id,
+					title,
+					created,
+					updated,
+					tree: serde_json::from_str(&messages)?,
+					settings: serde_json::from_str(&settings)?
+				}))
+			},
+			None => Ok(None)
+		}
+	}
+
+	fn save(
+		&self,
+		id: Uuid,
+		tree: MessageTree,
+		settings: ConversationSettings
+	) -> Result<ConversationSummary, StoreError>
+	{
+		let connection = self.connection.lock().unwrap();
+		let now = Utc::now();
+		// The title and creation time only apply to a new conversation.
+		connection.execute(
+			"INSERT INTO conversations
+				(id, title, created, updated, messages, settings)
+			VALUES (?1, ?2, ?3, ?3, ?4, ?5)
+			ON CONFLICT (id) DO UPDATE SET
+				updated = excluded.updated,
+				messages = excluded.messages,
+				settings = excluded.settings",
+			params![
+				id,
+				derive_title(&tree),
+				now,
+				serde_json::to_string(&tree)?,
+				serde_json::to_string(&settings)?
+			]
+		)?;
+		let summary = connection.query_row(
+			"SELECT title, created, updated FROM conversations WHERE id = ?1",
+			params![id],
+			|row| {
+				Ok(ConversationSummary {
+					id,
+					title: row.get(0)?,
+					created: row.get(1)?,
+					updated: row.get(2)?
+				})
+			}
+		)?;
+		Ok(summary)
+	}
+
+	fn rename(&self, id: Uuid, title: String) -> Result<(), StoreError>
+	{
+		let connection = self.connection.lock().unwrap();
+		let changed = connection.execute(
+			"UPDATE conversations SET title = ?2, updated = ?3 WHERE id = ?1",
+			params![id, title, Utc::now()]
+		)?;
+		match changed
+		{
+			0 => Err(StoreError::NotFound(id)),
+			_ => Ok(())
+		}
+	}
+
+	fn delete(&self, id: Uuid) -> Result<(), StoreError>
+	{
+		let connection = self.connection.lock().unwrap();
+		connection
+			.execute("DELETE FROM conversations WHERE id = ?1", params![id])?;
+		Ok(())
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The default directory for conversations saved as JSON files.
+const JSON_PATH: &str = "conversations";
+
+/// The default database file for conversations saved in SQLite.
+const SQLITE_PATH: &str = "conversations.sqlite";
+
+/// The maximum number of characters in a derived conversation title.
+const TITLE_LENGTH: usize = 60;
+
+/// The title of a conversation that has no user messages.
+const UNTITLED: &str = "New conversation";
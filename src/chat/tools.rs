@@ -0,0 +1,375 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::BTreeMap, sync::Arc};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use super::{
+	DiceExpression, Message, PromptLibrary, RandomTable, Role, ToolCall
+};
+
+////////////////////////////////////////////////////////////////////////////////
+//                                   Tools.                                   //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A server-side tool that the assistant may call, i.e., a function in the
+/// sense of OpenAI's function calling. The assistant sees the tool's name,
+/// description, and parameter schema, and decides when to call it.
+pub trait Tool: Send + Sync
+{
+	/// The name of the tool, by which the assistant calls it and system prompts
+	/// enable it.
+	fn name(&self) -> &str;
+
+	/// A description of the tool, telling the assistant when to call it.
+	fn description(&self) -> &str;
+
+	/// The JSON Schema of the tool's arguments, which must describe an object.
+	fn parameters(&self) -> Value;
+
+	/// Call the tool.
+	///
+	/// # Arguments
+	///
+	/// - `arguments`: The arguments, as chosen by the assistant.
+	///
+	/// # Returns
+	///
+	/// The result, as text for the assistant.
+	fn call(&self, arguments: Value) -> Result<String, ToolError>;
+}
+
+/// An error that occurred while calling a [`Tool`]. The error is reported to
+/// the assistant as the result of the call, so that it can try again.
+#[derive(Debug, Error)]
+pub enum ToolError
+{
+	#[error("No such tool: {0}")]
+	NotFound(String),
+
+	#[error("Invalid arguments: {0}")]
+	InvalidArguments(String),
+
+	#[error("{0}")]
+	Failed(String)
+}
+
+/// The definition of a [`Tool`], as offered to the assistant.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition
+{
+	/// The name of the tool.
+	pub name: String,
+
+	/// The description of the tool.
+	pub description: String,
+
+	/// The JSON Schema of the tool's arguments.
+	pub parameters: Value
+}
+
+/// The registry of server-side [tools](Tool), keyed by name.
+#[derive(Clone, Default)]
+pub struct ToolRegistry
+{
+	/// The registered tools, keyed by name.
+	tools: BTreeMap<String, Arc<dyn Tool>>
+}
+
+impl ToolRegistry
+{
+	/// Get the registry of built-in tools: [`RollTool`], and [`TableTool`] if
+	/// the prompt directory holds any random tables.
+	pub fn configured() -> Self
+	{
+		let mut registry = Self::default();
+		registry.register(RollTool);
+		match PromptLibrary::configured().tables()
+		{
+			Ok(tables) if tables.is_empty() =>
+			{},
+			Ok(tables) => registry.register(TableTool::new(tables)),
+			Err(e) => warn!("Failed to load random tables: {e}")
+		}
+		registry
+	}
+
+	/// Register the specified tool, replacing any tool of the same name.
+	///
+	/// # Arguments
+	///
+	/// - `tool`: The tool.
+	pub fn register(&mut self, tool: impl Tool + 'static)
+	{
+		self.tools.insert(tool.name().to_string(), Arc::new(tool));
+	}
+
+	/// Whether the registry has no tools.
+	pub fn is_empty(&self) -> bool { self.tools.is_empty() }
+
+	/// Select the specified tools, e.g., those that a system prompt enables.
+	/// Unknown names are skipped with a warning.
+	///
+	/// # Arguments
+	///
+	/// - `names`: The names of the tools.
+	///
+	/// # Returns
+	///
+	/// A registry of just the specified tools.
+	pub fn select(&self, names: &[String]) -> Self
+	{
+		let tools = names
+			.iter()
+			.filter_map(|name| match self.tools.get(name)
+			{
+				Some(tool) => Some((name.clone(), Arc::clone(tool))),
+				None =>
+				{
+					warn!("Skipping unknown tool: {name}");
+					None
+				}
+			})
+			.collect();
+		Self { tools }
+	}
+
+	/// Get the definitions of the registered tools, for a chat completion
+	/// request.
+	pub fn definitions(&self) -> Vec<ToolDefinition>
+	{
+		self.tools
+			.values()
+			.map(|tool| ToolDefinition {
+				name: tool.name().to_string(),
+				description: tool.description().to_string(),
+				parameters: tool.parameters()
+			})
+			.collect()
+	}
+
+	/// Execute the specified tool call. Errors become the result of the call,
+	/// so that the assistant learns what went wrong.
+	///
+	/// # Arguments
+	///
+	/// - `call`: The tool call.
+	///
+	/// # Returns
+	///
+	/// The [tool message](Role::Tool) that answers the call.
+	pub fn call(&self, call: &ToolCall) -> Message
+	{
+		let result =
+			self.tools
+				.get(&call.name)
+				.ok_or_else(|| ToolError::NotFound(call.name.clone()))
+				.and_then(|tool| {
+					// Some models send no arguments at all for a tool without
+					// parameters.
+					let arguments =
+						match call.arguments.trim()
+						{
+							"" => Value::Object(Default::default()),
+							arguments => serde_json::from_str(arguments)
+								.map_err(|e| {
+									ToolError::InvalidArguments(e.to_string())
+								})?
+						};
+					tool.call(arguments)
+				});
+		let content = match result
+		{
+			Ok(result) => result,
+			Err(e) =>
+			{
+				debug!("Tool call failed: {}: {e}", call.name);
+				format!("Error: {e}")
+			}
+		};
+		Message {
+			tool_call: Some(call.clone()),
+			..Role::Tool.message(content)
+		}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                              Built-in tools.                               //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The `roll` tool, which rolls a [dice expression](DiceExpression), so that
+/// the assistant need not invent the results. Every result records its seed,
+/// and rolling the same expression with the same seed gives the same result,
+/// so rolls are auditable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RollTool;
+
+/// The arguments of the [`RollTool`].
+#[derive(Debug, Deserialize)]
+struct RollArguments
+{
+	/// The dice expression.
+	expression: String,
+
+	/// The seed, if reproducing an earlier roll.
+	seed: Option<u64>
+}
+
+impl Tool for RollTool
+{
+	fn name(&self) -> &str { "roll" }
+
+	fn description(&self) -> &str
+	{
+		"Roll dice. Use this whenever the game calls for chance, and never \
+		invent the results. Supports tabletop notation: 3d6+2, 1d8+1d6-1, \
+		2d20kh1 (advantage), 2d20kl1 (disadvantage), 4d6! (exploding dice), \
+		and d% (percentile)."
+	}
+
+	fn parameters(&self) -> Value
+	{
+		json!({
+			"type": "object",
+			"properties": {
+				"expression": {
+					"type": "string",
+					"description": "The dice expression, e.g., 3d6+2."
+				},
+				"seed": {
+					"type": "integer",
+					"description":
+						"The seed of an earlier roll to reproduce. Omit it \
+						otherwise."
+				}
+			},
+			"required": ["expression"]
+		})
+	}
+
+	fn call(&self, arguments: Value) -> Result<String, ToolError>
+	{
+		let arguments = serde_json::from_value::<RollArguments>(arguments)
+			.map_err(|e| ToolError::InvalidArguments(e.to_string()))?;
+		let expression = arguments
+			.expression
+			.parse::<DiceExpression>()
+			.map_err(|e| ToolError::InvalidArguments(e.to_string()))?;
+		let seed = arguments.seed.unwrap_or_else(new_seed);
+		let roll = expression.roll(&mut ChaCha8Rng::seed_from_u64(seed));
+		Ok(format!("{roll} (seed {seed})"))
+	}
+}
+
+/// The `table` tool, which rolls on one of the [random tables](RandomTable) in
+/// the prompt directory. As with the [`RollTool`], every result records its
+/// seed.
+#[derive(Debug, Clone)]
+pub struct TableTool
+{
+	/// The tables, keyed by identifier.
+	tables: BTreeMap<String, RandomTable>,
+
+	/// The description of the tool, which lists the tables.
+	description: String
+}
+
+/// The arguments of the [`TableTool`].
+#[derive(Debug, Deserialize)]
+struct TableArguments
+{
+	/// The identifier of the table.
+	table: String,
+
+	/// The seed, if reproducing an earlier roll.
+	seed: Option<u64>
+}
+
+impl TableTool
+{
+	/// Create a tool that rolls on the specified tables.
+	///
+	/// # Arguments
+	///
+	/// - `tables`: The tables.
+	pub fn new(tables: impl IntoIterator<Item = RandomTable>) -> Self
+	{
+		let tables = tables
+			.into_iter()
+			.map(|table| (table.id.clone(), table))
+			.collect::<BTreeMap<_, _>>();
+		let mut description = "Roll on a random table, and use the result in \
+			the story. The tables are:"
+			.to_string();
+		for (id, table) in &tables
+		{
+			description.push_str(&format!("\n- {id}"));
+			if let Some(name) = &table.name
+			{
+				description.push_str(&format!(": {name}"));
+			}
+			if let Some(details) = &table.description
+			{
+				description.push_str(&format!(" ({details})"));
+			}
+		}
+		Self {
+			tables,
+			description
+		}
+	}
+}
+
+impl Tool for TableTool
+{
+	fn name(&self) -> &str { "table" }
+
+	fn description(&self) -> &str { &self.description }
+
+	fn parameters(&self) -> Value
+	{
+		json!({
+			"type": "object",
+			"properties": {
+				"table": {
+					"type": "string",
+					"enum": self.tables.keys().collect::<Vec<_>>(),
+					"description": "The table to roll on."
+				},
+				"seed": {
+					"type": "integer",
+					"description":
+						"The seed of an earlier roll to reproduce. Omit it \
+						otherwise."
+				}
+			},
+			"required": ["table"]
+		})
+	}
+
+	fn call(&self, arguments: Value) -> Result<String, ToolError>
+	{
+		let arguments = serde_json::from_value::<TableArguments>(arguments)
+			.map_err(|e| ToolError::InvalidArguments(e.to_string()))?;
+		let table = self.tables.get(&arguments.table).ok_or_else(|| {
+			ToolError::InvalidArguments(format!(
+				"No such table: {}",
+				arguments.table
+			))
+		})?;
+		let seed = arguments.seed.unwrap_or_else(new_seed);
+		let entry = table
+			.roll(&mut ChaCha8Rng::seed_from_u64(seed))
+			.ok_or_else(|| ToolError::Failed("The table is empty".into()))?;
+		let name = table.name.as_deref().unwrap_or(&table.id);
+		Ok(format!("{name}: {} (seed {seed})", entry.result))
+	}
+}
+
+/// Choose a fresh seed for a roll. Seeds fit in 32 bits, so that they survive
+/// the round trip through JSON numbers intact, whatever the model's JSON
+/// parser.
+fn new_seed() -> u64 { rand::random::<u32>() as u64 }
@@ -1,5 +1,6 @@
+use std::collections::{BTreeMap, HashMap};
 #[cfg(feature = "ssr")]
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::Arc};
 
 #[cfg(feature = "ssr")]
 use async_openai::{
@@ -7,14 +8,18 @@ use async_openai::{
 	config::OpenAIConfig,
 	error::OpenAIError,
 	types::{
+		ChatCompletionMessageToolCall,
 		ChatCompletionRequestAssistantMessageArgs,
 		ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
-		ChatCompletionRequestUserMessageArgs, CreateChatCompletionResponse
+		ChatCompletionRequestToolMessageArgs,
+		ChatCompletionRequestUserMessageArgs, ChatCompletionToolType,
+		CreateChatCompletionResponse, FunctionCall
 	}
 };
 
 #[cfg(feature = "ssr")]
 use axum::extract::ws::{Message as WebSocketMessage, WebSocket};
+use chrono::{DateTime, Utc};
 #[cfg(feature = "ssr")]
 use futures::{SinkExt, lock::Mutex, stream::SplitSink};
 #[cfg(feature = "ssr")]
@@ -23,35 +28,115 @@ use leptos::prelude::ServerFnError;
 use leptos::server_fn::error::NoCustomError;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "ssr")]
+use tokio::task::AbortHandle;
+#[cfg(feature = "ssr")]
 use tracing::{debug, trace};
+use uuid::Uuid;
 
+#[cfg(feature = "ssr")]
+use super::{BackendRouter, SummaryCache};
 use crate::error_template::AppError;
 
 ////////////////////////////////////////////////////////////////////////////////
 //                       Application message protocol.                        //
 ////////////////////////////////////////////////////////////////////////////////
 
-/// The application messages.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The application messages. Every chat message carries the identifier of the
+/// chat request to which it pertains, as chosen by the client when it sent
+/// [`StartChat`](Self::StartChat). This allows the client to discard stale
+/// replies, e.g., late fragments from a cancelled request, and allows the
+/// server to track several chats per session. Notifications, like
+/// [`SystemPromptChanged`](Self::SystemPromptChanged), pertain to no request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AppMessage
 {
 	/// A chat completion request, sent by the client.
-	StartChat(Vec<Message>),
+	StartChat(ChatRequest),
+
+	/// A chat cancellation request, sent by the client. Aborts the specified
+	/// chat completion, if it is still in progress.
+	CancelChat(Uuid),
+
+	/// A chat acknowledgement, sent by the server in response to a
+	/// [`StartChat`](Self::StartChat) message that could not start immediately
+	/// because the chat assistant was busy. The chat will start once the
+	/// assistant becomes available.
+	ChatQueued(Uuid),
 
 	/// A chat fragment reply, sent by the server in response to a
 	/// [`StartChat`](Self::StartChat) message.
-	NextChatFragment(String),
+	NextChatFragment(Uuid, String),
+
+	/// A chat retry notice, sent by the server when the assistant's response
+	/// to a [`StartChat`](Self::StartChat) message does not match the prompt's
+	/// schema. The server discards the response and asks the assistant again,
+	/// so the client should discard the fragments received so far.
+	ChatRetried(Uuid),
+
+	/// A tool call notice, sent by the server when the assistant's response to
+	/// a [`StartChat`](Self::StartChat) message ends by calling tools. The
+	/// fragments received so far, together with the calls, comprise a complete
+	/// assistant message. The results of the calls follow, as
+	/// [`ToolResult`](Self::ToolResult) messages, and then the fragments of the
+	/// assistant's follow-up response.
+	ToolCalls(Uuid, Vec<ToolCall>),
+
+	/// A tool result notice, sent by the server after it executes a tool call.
+	/// Carries the [tool message](Role::Tool) that answers the call.
+	ToolResult(Uuid, Message),
+
+	/// A context notice, sent by the server when the messages of a
+	/// [`StartChat`](Self::StartChat) message exceed the context budget.
+	/// Carries the indices of the messages that the server left out, and
+	/// whether it replaced them with a summary.
+	ContextTrimmed(Uuid, Vec<usize>, bool),
+
+	/// A usage report, sent by the server once the assistant finishes
+	/// responding to a [`StartChat`](Self::StartChat) message, just before
+	/// [`ChatCompleted`](Self::ChatCompleted).
+	ChatUsage(Uuid, Usage),
 
 	/// A chat conclusion reply, sent by the server in response to a
 	/// [`StartChat`](Self::StartChat) message.
-	ChatCompleted,
+	ChatCompleted(Uuid),
+
+	/// A chat cancellation reply, sent by the server in response to a
+	/// [`CancelChat`](Self::CancelChat) message. Concludes the chat in lieu of
+	/// [`ChatCompleted`](Self::ChatCompleted).
+	ChatCancelled(Uuid),
 
 	/// An error reply, sent by the server.
-	Error(AppError)
+	Error(Uuid, AppError),
+
+	/// A notification, sent by the server whenever a file in the prompt
+	/// library changes on disk. Carries the identifier of the changed prompt.
+	SystemPromptChanged(String)
 }
 
 impl AppMessage
 {
+	/// Get the identifier of the chat request to which the message pertains,
+	/// or `None` if the message is a notification.
+	pub fn request_id(&self) -> Option<Uuid>
+	{
+		match self
+		{
+			AppMessage::StartChat(ChatRequest { id, .. })
+			| AppMessage::CancelChat(id)
+			| AppMessage::ChatQueued(id)
+			| AppMessage::NextChatFragment(id, _)
+			| AppMessage::ChatRetried(id)
+			| AppMessage::ToolCalls(id, _)
+			| AppMessage::ToolResult(id, _)
+			| AppMessage::ContextTrimmed(id, ..)
+			| AppMessage::ChatUsage(id, _)
+			| AppMessage::ChatCompleted(id)
+			| AppMessage::ChatCancelled(id)
+			| AppMessage::Error(id, _) => Some(*id),
+			AppMessage::SystemPromptChanged(_) => None
+		}
+	}
+
 	/// Send the message to the client. Handles serialization, framing, sending,
 	/// and logging.
 	#[cfg(feature = "ssr")]
@@ -85,36 +170,315 @@ impl AppMessage
 	}
 }
 
+/// A chat completion request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatRequest
+{
+	/// The request identifier, chosen by the client and echoed on every reply.
+	pub id: Uuid,
+
+	/// The messages to send to the chat assistant.
+	pub messages: Vec<Message>,
+
+	/// The identifier of the system prompt, or `None` for the server's
+	/// default. If the prompt has a schema, then the response must match it.
+	pub prompt: Option<String>,
+
+	/// The parameters that control how the chat assistant generates its
+	/// response.
+	pub params: GenerationParams,
+
+	/// The name of the backend that should generate the response, or `None`
+	/// for the server's default. Another backend may stand in if the chosen
+	/// one is unreachable.
+	#[serde(default)]
+	pub backend: Option<String>
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                           Generation parameters.                           //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The parameters that control how the chat assistant generates a response.
+/// Any absent parameter takes the server's default, and every parameter is
+/// clamped to the server's limits; see [`GenerationConfig`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams
+{
+	/// The model that should generate the response.
+	pub model: Option<String>,
+
+	/// The maximum number of tokens to generate.
+	pub max_tokens: Option<u16>,
+
+	/// The sampling temperature, between `0` and `2`. Higher values make the
+	/// response more random.
+	pub temperature: Option<f32>,
+
+	/// The nucleus sampling threshold, between `0` and `1`. Only the tokens
+	/// comprising the top `top_p` probability mass are considered.
+	pub top_p: Option<f32>,
+
+	/// The sequences that stop generation. At most four are honored.
+	pub stop: Vec<String>,
+
+	/// The seed for deterministic sampling, if the model supports it.
+	pub seed: Option<i64>,
+
+	/// The presence penalty, between `-2` and `2`. Positive values encourage
+	/// the model to talk about new topics.
+	pub presence_penalty: Option<f32>,
+
+	/// The frequency penalty, between `-2` and `2`. Positive values discourage
+	/// the model from repeating itself verbatim.
+	pub frequency_penalty: Option<f32>
+}
+
+/// The server's defaults and limits for [generation
+/// parameters](GenerationParams), read from the environment.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub(super) struct GenerationConfig
+{
+	/// The default parameters, used in lieu of any absent from a request.
+	pub defaults: GenerationParams,
+
+	/// The maximum number of tokens that any request may generate.
+	pub max_tokens_limit: u16
+}
+
+#[cfg(feature = "ssr")]
+impl GenerationConfig
+{
+	/// Resolve the requested parameters against the receiver: supply defaults
+	/// for absent parameters, discard non-finite values, and clamp everything
+	/// else to the permissible ranges.
+	pub fn resolve(&self, params: &GenerationParams) -> GenerationParams
+	{
+		let defaults = &self.defaults;
+		let clamp = |value: Option<f32>, default: Option<f32>, min, max| {
+			value
+				.filter(|value| value.is_finite())
+				.or(default)
+				.map(|value| value.clamp(min, max))
+		};
+		let stop = match params.stop.is_empty()
+		{
+			true => &defaults.stop,
+			false => &params.stop
+		};
+		GenerationParams {
+			model: params
+				.model
+				.clone()
+				.filter(|model| !model.trim().is_empty())
+				.or_else(|| defaults.model.clone()),
+			max_tokens: params.max_tokens.or(defaults.max_tokens).map(
+				|max_tokens| max_tokens.clamp(1, self.max_tokens_limit.max(1))
+			),
+			temperature: clamp(
+				params.temperature,
+				defaults.temperature,
+				0.0,
+				2.0
+			),
+			top_p: clamp(params.top_p, defaults.top_p, 0.0, 1.0),
+			stop: stop
+				.iter()
+				.filter(|stop| !stop.is_empty())
+				.take(MAX_STOP_SEQUENCES)
+				.cloned()
+				.collect(),
+			seed: params.seed.or(defaults.seed),
+			presence_penalty: clamp(
+				params.presence_penalty,
+				defaults.presence_penalty,
+				-2.0,
+				2.0
+			),
+			frequency_penalty: clamp(
+				params.frequency_penalty,
+				defaults.frequency_penalty,
+				-2.0,
+				2.0
+			)
+		}
+	}
+}
+
+#[cfg(feature = "ssr")]
+impl Default for GenerationConfig
+{
+	fn default() -> Self
+	{
+		Self {
+			defaults: GenerationParams {
+				model: Some(get_model()),
+				max_tokens: Some(get_env_or("MAX_TOKENS", MAX_TOKENS)),
+				temperature: Some(get_env_or("TEMPERATURE", TEMPERATURE)),
+				top_p: Some(get_env_or("TOP_P", TOP_P)),
+				..Default::default()
+			},
+			max_tokens_limit: get_env_or("MAX_TOKENS_LIMIT", MAX_TOKENS_LIMIT)
+				.max(1)
+		}
+	}
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 //                              Session support.                              //
 ////////////////////////////////////////////////////////////////////////////////
 
+/// The configuration of websocket sessions, shared by every connection: the
+/// chat backends, and the limits and defaults for chats.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub struct SessionConfig
+{
+	/// The chat backends.
+	pub(super) backends: Arc<BackendRouter>,
+
+	/// The defaults and limits for generation parameters.
+	pub(super) generation: GenerationConfig,
+
+	/// The maximum number of chats that may be in progress at once.
+	pub(super) max_chats: usize,
+
+	/// What to do with a chat that arrives while the chat assistant is busy.
+	pub(super) busy_policy: BusyPolicy,
+
+	/// Whether the chat assistant supports OpenAI's JSON mode.
+	pub(super) json_mode: bool,
+
+	/// How many times to retry a response that does not match the prompt's
+	/// schema.
+	pub(super) schema_retries: usize,
+
+	/// The size of the model's context window, in tokens. The messages of a
+	/// chat must fit in the window along with the response.
+	pub(super) context_window: usize,
+
+	/// Whether to summarize the messages that do not fit in the context
+	/// window, rather than just leaving them out.
+	pub(super) summarize_context: bool,
+
+	/// The summaries of messages that did not fit in the context window,
+	/// shared by every session so that a reconnected client still finds them.
+	pub(super) summaries: Arc<SummaryCache>
+}
+
+#[cfg(feature = "ssr")]
+impl SessionConfig
+{
+	/// Create a configuration for the specified backends. The environment
+	/// configures everything else.
+	///
+	/// # Arguments
+	///
+	/// - `backends`: The chat backends.
+	pub fn new(backends: Arc<BackendRouter>) -> Self
+	{
+		Self {
+			backends,
+			generation: GenerationConfig::default(),
+			max_chats: get_max_chats(),
+			busy_policy: get_busy_policy(),
+			json_mode: get_env_or("JSON_MODE", false),
+			schema_retries: get_env_or("SCHEMA_RETRIES", SCHEMA_RETRIES),
+			context_window: get_context_window(),
+			summarize_context: get_env_or("SUMMARIZE_CONTEXT", false),
+			summaries: Arc::new(SummaryCache::new())
+		}
+	}
+
+	/// Get the configuration for the [configured](BackendRouter::configured)
+	/// backends.
+	pub fn configured() -> Self { Self::new(BackendRouter::configured()) }
+
+	/// Set the maximum number of chats that may be in progress at once.
+	///
+	/// # Arguments
+	///
+	/// - `max_chats`: The maximum number of chats, at least `1`.
+	pub fn with_max_chats(mut self, max_chats: usize) -> Self
+	{
+		self.max_chats = max_chats.max(1);
+		self
+	}
+
+	/// Set what to do with a chat that arrives while the chat assistant is
+	/// busy.
+	///
+	/// # Arguments
+	///
+	/// - `busy_policy`: The busy policy.
+	pub fn with_busy_policy(mut self, busy_policy: BusyPolicy) -> Self
+	{
+		self.busy_policy = busy_policy;
+		self
+	}
+}
+
 /// Each websocket connection has a context that holds session data.
 #[cfg(feature = "ssr")]
 #[derive(Debug)]
 pub(super) struct SessionState
 {
-	/// The chat client.
-	pub chat_client: Client<OpenAIConfig>,
+	/// The configuration of the session.
+	pub config: SessionConfig,
+
+	/// The tasks generating the chat completions in progress, keyed by request
+	/// identifier. Aborting a task also drops its upstream completion stream.
+	pub chats: HashMap<Uuid, AbortHandle>,
 
-	/// Whether the chat assistant is currently busy.
-	pub chat_busy: bool
+	/// The chats waiting for the chat assistant to become available, in
+	/// arrival order.
+	pub queue: VecDeque<ChatRequest>
 }
 
 #[cfg(feature = "ssr")]
-impl Default for SessionState
+impl SessionState
 {
-	fn default() -> Self
+	/// Create the state of a new session.
+	///
+	/// # Arguments
+	///
+	/// - `config`: The configuration of the session.
+	pub fn new(config: SessionConfig) -> Self
 	{
 		Self {
-			chat_client: Client::with_config(
-				OpenAIConfig::new()
-					.with_api_base(get_base_url())
-					.with_api_key(get_key())
-			),
-			chat_busy: false
+			config,
+			chats: HashMap::new(),
+			queue: VecDeque::new()
 		}
 	}
+
+	/// Whether the chat assistant is currently busy, i.e., whether the session
+	/// has no capacity for another chat.
+	pub fn chat_busy(&self) -> bool
+	{
+		self.chats.len() >= self.config.max_chats
+	}
+
+	/// Whether the specified request identifier belongs to a chat that is
+	/// either in progress or queued.
+	pub fn chat_known(&self, id: Uuid) -> bool
+	{
+		self.chats.contains_key(&id) || self.queue.iter().any(|r| r.id == id)
+	}
+}
+
+/// What to do with a chat that arrives while the chat assistant is busy.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusyPolicy
+{
+	/// Reject the chat with an [`AppError::ChatBusy`] error.
+	Reject,
+
+	/// Queue the chat until the chat assistant becomes available,
+	/// acknowledging it with [`AppMessage::ChatQueued`].
+	Queue
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -122,7 +486,7 @@ impl Default for SessionState
 ////////////////////////////////////////////////////////////////////////////////
 
 /// The role of a message in the chat.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Role
 {
 	/// The assistant's role. This corresponds to the assistant that generates
@@ -135,7 +499,11 @@ pub enum Role
 
 	/// The user's role. This corresponds to user who is interacting with the
 	/// chat.
-	User
+	User,
+
+	/// The tool's role. This corresponds to the result of a
+	/// [tool call](ToolCall) that the assistant made.
+	Tool
 }
 
 impl Role
@@ -148,7 +516,9 @@ impl Role
 	{
 		Message {
 			role: self,
-			content
+			content,
+			tool_calls: vec![],
+			tool_call: None
 		}
 	}
 }
@@ -163,13 +533,14 @@ impl From<async_openai::types::Role> for Role
 			async_openai::types::Role::Assistant => Role::Assistant,
 			async_openai::types::Role::System => Role::System,
 			async_openai::types::Role::User => Role::User,
+			async_openai::types::Role::Tool => Role::Tool,
 			_ => unreachable!()
 		}
 	}
 }
 
-/// A message in the chat, either a system prompt or a message that is sent
-/// between the user and the assistant.
+/// A message in the chat, either a system prompt, a message that is sent
+/// between the user and the assistant, or the result of a tool call.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message
 {
@@ -177,7 +548,31 @@ pub struct Message
 	pub role: Role,
 
 	/// The content of the message.
-	pub content: String
+	pub content: String,
+
+	/// The tools that the assistant called at the end of this message, if
+	/// any. Only assistant messages call tools.
+	#[serde(default)]
+	pub tool_calls: Vec<ToolCall>,
+
+	/// The tool call that this message answers. Only tool messages answer tool
+	/// calls.
+	#[serde(default)]
+	pub tool_call: Option<ToolCall>
+}
+
+/// A call of a server-side tool, made by the assistant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCall
+{
+	/// The identifier of the call, chosen by the assistant.
+	pub id: String,
+
+	/// The name of the tool.
+	pub name: String,
+
+	/// The arguments of the call, as JSON text.
+	pub arguments: String
 }
 
 impl Message
@@ -210,9 +605,9 @@ impl TryFrom<&CreateChatCompletionResponse> for Message
 			})?
 			.message
 			.clone();
-		let role = message.role.into();
+		let role: Role = message.role.into();
 		let content = message.content.unwrap_or_else(String::new).to_string();
-		Ok(Self { role, content })
+		Ok(role.message(content))
 	}
 }
 
@@ -227,10 +622,31 @@ impl TryFrom<&Message> for ChatCompletionRequestMessage
 		{
 			Role::Assistant =>
 			{
-				Ok(ChatCompletionRequestAssistantMessageArgs::default()
-					.content(message.content.clone())
-					.build()?
-					.into())
+				let mut args =
+					ChatCompletionRequestAssistantMessageArgs::default();
+				// A message that only calls tools has no content.
+				if !message.content.is_empty() || message.tool_calls.is_empty()
+				{
+					args.content(message.content.clone());
+				}
+				if !message.tool_calls.is_empty()
+				{
+					args.tool_calls(
+						message
+							.tool_calls
+							.iter()
+							.map(|call| ChatCompletionMessageToolCall {
+								id: call.id.clone(),
+								r#type: ChatCompletionToolType::Function,
+								function: FunctionCall {
+									name: call.name.clone(),
+									arguments: call.arguments.clone()
+								}
+							})
+							.collect::<Vec<_>>()
+					);
+				}
+				Ok(args.build()?.into())
 			},
 			Role::System =>
 			{
@@ -242,18 +658,644 @@ impl TryFrom<&Message> for ChatCompletionRequestMessage
 			Role::User => Ok(ChatCompletionRequestUserMessageArgs::default()
 				.content(message.content.clone())
 				.build()?
+				.into()),
+			Role::Tool => Ok(ChatCompletionRequestToolMessageArgs::default()
+				.content(message.content.clone())
+				.tool_call_id(
+					message
+						.tool_call
+						.as_ref()
+						.map(|call| call.id.clone())
+						.unwrap_or_default()
+				)
+				.build()?
 				.into())
 		}
 	}
 }
 
+////////////////////////////////////////////////////////////////////////////////
+//                               Message tree.                                //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The message history of a conversation, modelled as a tree of messages keyed
+/// by identifier. Editing or regenerating a message adds a sibling branch
+/// rather than overwriting the message, so every alternative is retained. Each
+/// node remembers which of its children is active; following the active
+/// children from the root traces the active branch, which is what the user
+/// sees and what is sent to the chat assistant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageTree
+{
+	/// Every message in the tree, keyed by identifier.
+	nodes: HashMap<Uuid, MessageNode>,
+
+	/// The alternatives for the first message.
+	roots: Branches
+}
+
+/// A message in a [`MessageTree`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct MessageNode
+{
+	/// The message.
+	message: Message,
+
+	/// The identifier of the parent message, or `None` for a root.
+	parent: Option<Uuid>,
+
+	/// The alternatives for the next message.
+	children: Branches
+}
+
+/// The alternative continuations of a [`MessageNode`], or the alternative
+/// roots of a [`MessageTree`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Branches
+{
+	/// The identifiers of the alternatives, in creation order.
+	ids: Vec<Uuid>,
+
+	/// The index of the selected alternative. Meaningless if there are no
+	/// alternatives.
+	selected: usize,
+
+	/// Whether the active branch ends here, regardless of the selection. Set
+	/// by rewinding, so that the next message starts a new alternative without
+	/// losing the old ones.
+	collapsed: bool
+}
+
+impl Branches
+{
+	/// Get the identifier of the active alternative, if any.
+	fn active(&self) -> Option<Uuid>
+	{
+		match self.collapsed
+		{
+			true => None,
+			false => self.ids.get(self.selected).copied()
+		}
+	}
+
+	/// Get the index of the specified alternative.
+	fn index_of(&self, id: Uuid) -> Option<usize>
+	{
+		self.ids.iter().position(|i| *i == id)
+	}
+}
+
+impl MessageTree
+{
+	/// Create a tree that contains only the specified message.
+	pub fn with_root(message: Message) -> Self
+	{
+		let mut tree = Self::default();
+		tree.append(message);
+		tree
+	}
+
+	/// Get the specified message.
+	pub fn get(&self, id: Uuid) -> Option<&Message>
+	{
+		self.nodes.get(&id).map(|node| &node.message)
+	}
+
+	/// Get the messages along the active branch, in chronological order, with
+	/// each message keyed by its identifier.
+	pub fn active_branch(&self) -> Vec<(Uuid, Message)>
+	{
+		let mut branch = vec![];
+		let mut next = self.roots.active();
+		// A dangling identifier, e.g., in a damaged stored tree, ends the
+		// branch.
+		while let Some(id) = next
+			&& let Some(node) = self.nodes.get(&id)
+		{
+			branch.push((id, node.message.clone()));
+			next = node.children.active();
+		}
+		branch
+	}
+
+	/// Get the identifier of the last message along the active branch, if any.
+	pub fn tip(&self) -> Option<Uuid>
+	{
+		let mut tip = None;
+		let mut next = self.roots.active();
+		while let Some(id) = next
+			&& let Some(node) = self.nodes.get(&id)
+		{
+			tip = Some(id);
+			next = node.children.active();
+		}
+		tip
+	}
+
+	/// Append the specified message to the active branch.
+	///
+	/// # Returns
+	///
+	/// The identifier of the new message.
+	pub fn append(&mut self, message: Message) -> Uuid
+	{
+		let tip = self.tip();
+		self.attach(tip, message)
+	}
+
+	/// Add the specified message as an alternative to an existing message, and
+	/// make it active. The alternative has no continuation.
+	///
+	/// # Returns
+	///
+	/// The identifier of the new message, or `None` if the existing message
+	/// or its parent does not exist.
+	pub fn add_sibling(&mut self, id: Uuid, message: Message) -> Option<Uuid>
+	{
+		let parent = self.nodes.get(&id)?.parent;
+		if parent.is_some_and(|parent| !self.nodes.contains_key(&parent))
+		{
+			return None
+		}
+		Some(self.attach(parent, message))
+	}
+
+	/// End the active branch at the specified message. Its continuation is
+	/// retained, and can be restored with [`resume`](Self::resume) or reached
+	/// by [switching](Self::switch) once a new continuation is appended.
+	pub fn rewind(&mut self, id: Uuid)
+	{
+		if let Some(node) = self.nodes.get_mut(&id)
+		{
+			node.children.collapsed = true;
+		}
+	}
+
+	/// End the active branch just before the specified message, so that the
+	/// next message appended becomes an alternative to it.
+	pub fn rewind_before(&mut self, id: Uuid)
+	{
+		if let Some(parent) = self.nodes.get(&id).map(|node| node.parent)
+			&& let Some(branches) = self.branches_mut(parent)
+		{
+			branches.collapsed = true;
+		}
+	}
+
+	/// Whether the active branch was [rewound](Self::rewind) and can be
+	/// [resumed](Self::resume).
+	pub fn can_resume(&self) -> bool
+	{
+		let branches = self.branches(self.tip());
+		branches.collapsed && !branches.ids.is_empty()
+	}
+
+	/// Restore the continuation of the active branch that was most recently
+	/// [rewound](Self::rewind).
+	pub fn resume(&mut self)
+	{
+		let tip = self.tip();
+		if let Some(branches) = self.branches_mut(tip)
+		{
+			branches.collapsed = false;
+		}
+	}
+
+	/// Get the position of the specified message among its alternatives.
+	///
+	/// # Returns
+	///
+	/// The zero-based index of the message and the number of alternatives,
+	/// including the message itself.
+	pub fn siblings(&self, id: Uuid) -> (usize, usize)
+	{
+		match self.nodes.get(&id)
+		{
+			Some(node) =>
+			{
+				let branches = self.branches(node.parent);
+				match branches.index_of(id)
+				{
+					Some(index) => (index, branches.ids.len()),
+					None => (0, 1)
+				}
+			},
+			None => (0, 1)
+		}
+	}
+
+	/// Activate an alternative to the specified message, offset from it by the
+	/// specified amount, e.g., `-1` for the previous alternative and `1` for
+	/// the next. The offset is clamped to the available alternatives.
+	pub fn switch(&mut self, id: Uuid, offset: isize)
+	{
+		let Some(parent) = self.nodes.get(&id).map(|node| node.parent)
+		else
+		{
+			return
+		};
+		let Some(branches) = self.branches_mut(parent)
+		else
+		{
+			return
+		};
+		if let Some(index) = branches.index_of(id)
+		{
+			let last = branches.ids.len() as isize - 1;
+			branches.selected =
+				(index as isize + offset).clamp(0, last) as usize;
+			branches.collapsed = false;
+		}
+	}
+
+	/// Remove the specified message. Its alternative continuations take its
+	/// place among its own alternatives, so the messages that followed it
+	/// remain in the active branch.
+	pub fn remove(&mut self, id: Uuid)
+	{
+		let Some(node) = self.nodes.remove(&id)
+		else
+		{
+			return
+		};
+		for child in &node.children.ids
+		{
+			if let Some(child) = self.nodes.get_mut(child)
+			{
+				child.parent = node.parent;
+			}
+		}
+		let Some(branches) = self.branches_mut(node.parent)
+		else
+		{
+			return
+		};
+		let Some(index) = branches.index_of(id)
+		else
+		{
+			return
+		};
+		let count = node.children.ids.len();
+		branches.ids.splice(index..=index, node.children.ids);
+		if branches.selected == index
+		{
+			if count > 0
+			{
+				// Continue along the removed message's active continuation.
+				branches.selected = index + node.children.selected;
+				branches.collapsed |= node.children.collapsed;
+			}
+			else
+			{
+				// Fall back to a neighboring alternative, if any.
+				branches.selected =
+					index.min(branches.ids.len().saturating_sub(1));
+			}
+		}
+		else if branches.selected > index
+		{
+			branches.selected = branches.selected + count - 1;
+		}
+	}
+
+	/// Add the specified message as a child of the specified parent, or as a
+	/// root, and make it active.
+	fn attach(&mut self, parent: Option<Uuid>, message: Message) -> Uuid
+	{
+		let id = Uuid::new_v4();
+		self.nodes.insert(
+			id,
+			MessageNode {
+				message,
+				parent,
+				children: Branches::default()
+			}
+		);
+		if let Some(branches) = self.branches_mut(parent)
+		{
+			branches.ids.push(id);
+			branches.selected = branches.ids.len() - 1;
+			branches.collapsed = false;
+		}
+		id
+	}
+
+	/// Replace the specified message, keeping its place among its alternatives
+	/// and its continuations. The replacement gets a fresh identifier, so that
+	/// views keyed by identifier notice the change.
+	///
+	/// # Returns
+	///
+	/// The identifier of the replacement, or `None` if the specified message
+	/// does not exist.
+	pub fn replace(&mut self, id: Uuid, message: Message) -> Option<Uuid>
+	{
+		let node = self.nodes.remove(&id)?;
+		let replacement = Uuid::new_v4();
+		for child in &node.children.ids
+		{
+			if let Some(child) = self.nodes.get_mut(child)
+			{
+				child.parent = Some(replacement);
+			}
+		}
+		if let Some(branches) = self.branches_mut(node.parent)
+			&& let Some(index) = branches.index_of(id)
+		{
+			branches.ids[index] = replacement;
+		}
+		self.nodes
+			.insert(replacement, MessageNode { message, ..node });
+		Some(replacement)
+	}
+
+	/// Set the system message, i.e., the first message of the active branch if
+	/// it is a [system](Role::System) message. If there is no system message,
+	/// then the message becomes the parent of every root.
+	///
+	/// # Returns
+	///
+	/// The identifier of the system message.
+	pub fn set_system(&mut self, message: Message) -> Uuid
+	{
+		let system = self.roots.active().filter(|id| {
+			self.nodes
+				.get(id)
+				.is_some_and(|node| node.message.role == Role::System)
+		});
+		match system
+		{
+			Some(id) =>
+			{
+				self.replace(id, message).expect("system message exists")
+			},
+			None =>
+			{
+				let id = Uuid::new_v4();
+				let children = std::mem::take(&mut self.roots);
+				for child in &children.ids
+				{
+					if let Some(child) = self.nodes.get_mut(child)
+					{
+						child.parent = Some(id);
+					}
+				}
+				self.nodes.insert(
+					id,
+					MessageNode {
+						message,
+						parent: None,
+						children
+					}
+				);
+				self.roots = Branches {
+					ids: vec![id],
+					..Branches::default()
+				};
+				id
+			}
+		}
+	}
+
+	/// Get the alternatives that follow the specified parent, or the roots. A
+	/// parent that does not exist has no alternatives.
+	fn branches(&self, parent: Option<Uuid>) -> &Branches
+	{
+		static NONE: Branches = Branches {
+			ids: vec![],
+			selected: 0,
+			collapsed: false
+		};
+		match parent
+		{
+			Some(parent) =>
+			{
+				self.nodes.get(&parent).map_or(&NONE, |node| &node.children)
+			},
+			None => &self.roots
+		}
+	}
+
+	/// Get the alternatives that follow the specified parent, or the roots, or
+	/// `None` if the parent does not exist.
+	fn branches_mut(&mut self, parent: Option<Uuid>) -> Option<&mut Branches>
+	{
+		match parent
+		{
+			Some(parent) =>
+			{
+				self.nodes.get_mut(&parent).map(|node| &mut node.children)
+			},
+			None => Some(&mut self.roots)
+		}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                Token types.                                //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The token counts of a conversation, relative to the model's context window.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenCounts
+{
+	/// The number of tokens in each message, in order.
+	pub counts: Vec<usize>,
+
+	/// The size of the model's context window, in tokens.
+	pub window: usize,
+
+	/// Whether the counts come from a tokenizer, rather than estimation.
+	pub exact: bool
+}
+
+impl TokenCounts
+{
+	/// The total number of tokens in the conversation.
+	pub fn total(&self) -> usize { self.counts.iter().sum() }
+}
+
+/// The number of tokens that a chat used, summed over every completion that the
+/// chat took, e.g., the follow-ups to tool calls. Each completion uses the
+/// usage that its backend reports, if any, and otherwise the server counts the
+/// tokens itself.
+#[derive(
+	Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct Usage
+{
+	/// The number of tokens in the prompts.
+	pub prompt_tokens: usize,
+
+	/// The number of tokens in the completions.
+	pub completion_tokens: usize,
+
+	/// Whether the counts come from a tokenizer, rather than estimation.
+	pub exact: bool
+}
+
+impl std::ops::AddAssign for Usage
+{
+	fn add_assign(&mut self, other: Self)
+	{
+		self.prompt_tokens += other.prompt_tokens;
+		self.completion_tokens += other.completion_tokens;
+		self.exact &= other.exact;
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                            System prompt types.                            //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Describes a system prompt from the server's prompt library.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptInfo
+{
+	/// The prompt identifier, i.e., the stem of its file name.
+	pub id: String,
+
+	/// The human-readable name of the prompt.
+	pub name: String,
+
+	/// A short description of the prompt, if any.
+	pub description: Option<String>,
+
+	/// Whether this is the server's default prompt.
+	pub is_default: bool,
+
+	/// The variables that the prompt template accepts, in declaration order.
+	pub variables: Vec<PromptVariable>,
+
+	/// The file name of the JSON Schema that constrains the assistant's
+	/// output, if the prompt asks for structured output.
+	#[serde(default)]
+	pub schema: Option<String>,
+
+	/// Whether the assistant's output comprises action steps that drive the
+	/// [model world](super::World).
+	#[serde(default)]
+	pub world: bool,
+
+	/// The names of the server-side tools that the assistant may call.
+	#[serde(default)]
+	pub tools: Vec<String>
+}
+
+/// Describes a variable of a templated system prompt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptVariable
+{
+	/// The name of the variable, as used in the template.
+	pub name: String,
+
+	/// A short description of the variable, if any.
+	#[serde(default)]
+	pub description: Option<String>,
+
+	/// The value of the variable when none is given, if any.
+	#[serde(default)]
+	pub default: Option<String>
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                            Conversation types.                             //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A saved conversation, comprising its complete message history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conversation
+{
+	/// The conversation identifier.
+	pub id: Uuid,
+
+	/// The human-readable title of the conversation.
+	pub title: String,
+
+	/// When the conversation was first saved.
+	pub created: DateTime<Utc>,
+
+	/// When the conversation was last saved.
+	pub updated: DateTime<Utc>,
+
+	/// The message history, including every alternative branch.
+	pub tree: MessageTree,
+
+	/// The settings that shape the conversation.
+	#[serde(default)]
+	pub settings: ConversationSettings
+}
+
+/// The settings that shape a [conversation](Conversation), saved alongside its
+/// message history.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationSettings
+{
+	/// The identifier of the chosen [system prompt](PromptInfo), or `None` for
+	/// the server's default.
+	#[serde(default)]
+	pub prompt: Option<String>,
+
+	/// The values of the [variables](PromptVariable) of the system prompt,
+	/// keyed by name.
+	#[serde(default)]
+	pub variables: BTreeMap<String, String>,
+
+	/// The name of the chosen backend, or `None` for the server's default.
+	#[serde(default)]
+	pub backend: Option<String>
+}
+
+impl Conversation
+{
+	/// Summarize the conversation, omitting its message history.
+	pub fn summary(&self) -> ConversationSummary
+	{
+		ConversationSummary {
+			id: self.id,
+			title: self.title.clone(),
+			created: self.created,
+			updated: self.updated
+		}
+	}
+}
+
+/// The summary of a saved [conversation](Conversation), sufficient for listing
+/// it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationSummary
+{
+	/// The conversation identifier.
+	pub id: Uuid,
+
+	/// The human-readable title of the conversation.
+	pub title: String,
+
+	/// When the conversation was first saved.
+	pub created: DateTime<Utc>,
+
+	/// When the conversation was last saved.
+	pub updated: DateTime<Utc>
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 //                               Configuration.                               //
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Create a chat client for the configured OpenAI API.
+#[cfg(feature = "ssr")]
+pub(super) fn chat_client() -> Client<OpenAIConfig>
+{
+	Client::with_config(
+		OpenAIConfig::new()
+			.with_api_base(get_base_url())
+			.with_api_key(get_key())
+	)
+}
+
 /// Get the base URL for the OpenAI API. This is where the API is hosted.
 #[cfg(feature = "ssr")]
-fn get_base_url() -> String
+pub(super) fn get_base_url() -> String
 {
 	std::env::var("OPENAI_API_URL").unwrap_or_else(|_| URL.to_string())
 }
@@ -261,11 +1303,59 @@ fn get_base_url() -> String
 /// Get the API key for the OpenAI API. This is used to authenticate the user
 /// with the API.
 #[cfg(feature = "ssr")]
-fn get_key() -> String
+pub(super) fn get_key() -> String
 {
 	std::env::var("OPENAI_TOKEN").unwrap_or_else(|_| KEY.to_string())
 }
 
+/// Get the default model, i.e., the model that generates the chat responses
+/// unless a request specifies otherwise.
+#[cfg(feature = "ssr")]
+fn get_model() -> String
+{
+	std::env::var("CHAT_MODEL").unwrap_or_else(|_| MODEL.to_string())
+}
+
+/// Get the value of the specified environment variable, or the given default
+/// if the variable is unset or unparseable.
+#[cfg(feature = "ssr")]
+fn get_env_or<T: std::str::FromStr>(name: &str, default: T) -> T
+{
+	std::env::var(name)
+		.ok()
+		.and_then(|value| value.parse().ok())
+		.unwrap_or(default)
+}
+
+/// Get the maximum number of chats that a single session may have in progress
+/// at once. Local model servers usually generate one completion at a time, so
+/// the default is `1`.
+#[cfg(feature = "ssr")]
+fn get_max_chats() -> usize
+{
+	get_env_or("MAX_CONCURRENT_CHATS", MAX_CHATS).max(1)
+}
+
+/// Get the size of the model's context window, in tokens.
+#[cfg(feature = "ssr")]
+pub(super) fn get_context_window() -> usize
+{
+	get_env_or("CONTEXT_WINDOW", CONTEXT_WINDOW)
+}
+
+/// Get the [policy](BusyPolicy) for chats that arrive while the chat assistant
+/// is busy. Set `BUSY_POLICY` to `queue` to queue such chats; otherwise they
+/// are rejected.
+#[cfg(feature = "ssr")]
+fn get_busy_policy() -> BusyPolicy
+{
+	match std::env::var("BUSY_POLICY")
+	{
+		Ok(policy) if policy.eq_ignore_ascii_case("queue") => BusyPolicy::Queue,
+		_ => BusyPolicy::Reject
+	}
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 //                                 Constants.                                 //
 ////////////////////////////////////////////////////////////////////////////////
@@ -279,3 +1369,44 @@ const URL: &str = "http://localhost:5115/v1";
 /// actually needed.
 #[cfg(feature = "ssr")]
 const KEY: &str = "not-needed";
+
+/// The default maximum number of chats that a single session may have in
+/// progress at once.
+#[cfg(feature = "ssr")]
+const MAX_CHATS: usize = 1;
+
+/// The default model to use for the chat completion. This is the model that
+/// will be used to generate the chat responses unless a request specifies
+/// otherwise.
+#[cfg(feature = "ssr")]
+const MODEL: &str = "mistralai_mixtral-8x7b-instruct-v0.1";
+
+/// The default maximum number of tokens to generate.
+#[cfg(feature = "ssr")]
+const MAX_TOKENS: u16 = 1024;
+
+/// The default limit on the maximum number of tokens that any request may
+/// generate.
+#[cfg(feature = "ssr")]
+const MAX_TOKENS_LIMIT: u16 = 4096;
+
+/// The default sampling temperature.
+#[cfg(feature = "ssr")]
+const TEMPERATURE: f32 = 0.8;
+
+/// The default nucleus sampling threshold.
+#[cfg(feature = "ssr")]
+const TOP_P: f32 = 0.95;
+
+/// The maximum number of stop sequences supported by the OpenAI API.
+#[cfg(feature = "ssr")]
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// The default number of times to retry a response that does not match the
+/// prompt's schema.
+#[cfg(feature = "ssr")]
+const SCHEMA_RETRIES: usize = 2;
+
+/// The default size of the model's context window, in tokens.
+#[cfg(feature = "ssr")]
+const CONTEXT_WINDOW: usize = 8192;
@@ -0,0 +1,513 @@
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ssr")]
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(feature = "ssr")]
+use super::{Message, Role, parse_output};
+
+////////////////////////////////////////////////////////////////////////////////
+//                                World model.                                //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The model world of a roguelike CRPG, as shaped by the action steps that the
+/// game master emits. Every actor, agent, and target that a step mentions
+/// becomes an [entity](Entity), so the world knows about exactly those things
+/// that have come up in play.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct World
+{
+	/// The entities, keyed by name.
+	pub entities: BTreeMap<String, Entity>,
+
+	/// The steps applied so far, described in order of application.
+	pub events: Vec<String>
+}
+
+/// A thing in the model [world](World): a character, a creature, an item, or a
+/// place.
+#[derive(
+	Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+pub struct Entity
+{
+	/// Where the entity is, i.e., the name of a place or of its holder.
+	pub location: Option<String>,
+
+	/// The names of the entities that the entity holds.
+	pub inventory: BTreeSet<String>,
+
+	/// The name of the entity that the entity has ready to hand, e.g., a
+	/// drawn weapon.
+	pub readied: Option<String>,
+
+	/// The posture or condition of the entity, e.g., `sitting` or `asleep`.
+	pub posture: Option<String>,
+
+	/// The number of times that the entity has been attacked.
+	pub wounds: u32,
+
+	/// Whether the entity has been used up, e.g., a quaffed potion.
+	pub consumed: bool
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                             Action execution.                              //
+////////////////////////////////////////////////////////////////////////////////
+
+/// A single step of a player's action, as emitted by the game master. See
+/// `data/crpg.system` for the format.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ActionStep
+{
+	/// The entities that initiated the action.
+	pub actors: Vec<String>,
+
+	/// The action itself.
+	pub action: String,
+
+	/// Any entities involved in the execution of the action, excluding the
+	/// actors and targets.
+	#[serde(default)]
+	pub agents: Vec<String>,
+
+	/// The recipients of the action.
+	#[serde(default)]
+	pub targets: Vec<String>,
+
+	/// Any additional parameters of the action.
+	#[serde(default)]
+	pub params: Vec<Map<String, Value>>
+}
+
+/// The kinds of action that the [world](World) understands. Any other action
+/// is recorded as an event but has no side effects.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ActionKind
+{
+	/// The actors pick up the targets and agents.
+	Take,
+
+	/// The actors ready an agent or target, picking it up if necessary.
+	Ready,
+
+	/// The actors put down the targets.
+	Drop,
+
+	/// The actors hand the agents over to the first target.
+	Give,
+
+	/// The actors move to the first target.
+	Go,
+
+	/// The actors assume the specified posture.
+	Posture(&'static str),
+
+	/// The actors attack the targets.
+	Attack,
+
+	/// The actors use up the agents and targets.
+	Consume
+}
+
+#[cfg(feature = "ssr")]
+impl ActionKind
+{
+	/// Classify the specified action, or answer `None` if the world does not
+	/// understand it.
+	fn of(action: &str) -> Option<Self>
+	{
+		let kind = match action.trim().to_lowercase().as_str()
+		{
+			"take" | "get" | "grab" | "pick up" | "pickup" | "carry" =>
+			{
+				Self::Take
+			},
+			"ready" | "equip" | "draw" | "wield" | "brandish" => Self::Ready,
+			"drop" | "put down" | "discard" => Self::Drop,
+			"give" | "hand" | "offer" => Self::Give,
+			"go" | "move" | "walk" | "run" | "enter" | "travel" => Self::Go,
+			"sit" => Self::Posture("sitting"),
+			"stand" => Self::Posture("standing"),
+			"crouch" | "kneel" => Self::Posture("crouching"),
+			"lie" | "lie down" => Self::Posture("lying down"),
+			"sleep" | "rest" => Self::Posture("asleep"),
+			"attack" | "strike" | "hit" | "shoot" | "stab" => Self::Attack,
+			"use" | "quaff" | "drink" | "eat" | "consume" => Self::Consume,
+			_ => return None
+		};
+		Some(kind)
+	}
+}
+
+#[cfg(feature = "ssr")]
+impl World
+{
+	/// Build the world by replaying every action step that the game master
+	/// emitted in the specified conversation. Assistant messages that are not
+	/// valid action steps are ignored, so the world survives the occasional
+	/// malformed response.
+	///
+	/// # Arguments
+	///
+	/// - `messages`: The messages of the conversation, in order.
+	pub fn replay(messages: &[Message]) -> Self
+	{
+		let mut world = Self::default();
+		for message in messages.iter().filter(|m| m.role == Role::Assistant)
+		{
+			let steps = parse_output(&message.content)
+				.and_then(serde_json::from_value::<Vec<ActionStep>>);
+			for step in steps.iter().flatten()
+			{
+				world.apply(step);
+			}
+		}
+		world
+	}
+
+	/// Apply the specified action step to the world.
+	///
+	/// # Arguments
+	///
+	/// - `step`: The action step.
+	pub fn apply(&mut self, step: &ActionStep)
+	{
+		for name in step.actors.iter().chain(&step.agents).chain(&step.targets)
+		{
+			self.entity(name);
+		}
+		match ActionKind::of(&step.action)
+		{
+			Some(ActionKind::Take) =>
+			{
+				for actor in &step.actors
+				{
+					for item in step.targets.iter().chain(&step.agents)
+					{
+						self.hold(actor, item);
+					}
+				}
+			},
+			Some(ActionKind::Ready) =>
+			{
+				let item = step.agents.first().or(step.targets.first());
+				for actor in &step.actors
+				{
+					if let Some(item) = item
+					{
+						self.hold(actor, item);
+					}
+					self.entity(actor).readied = item.cloned();
+				}
+			},
+			Some(ActionKind::Drop) =>
+			{
+				for actor in &step.actors
+				{
+					let location = self.entity(actor).location.clone();
+					for item in &step.targets
+					{
+						self.release(actor, item);
+						self.entity(item).location = location.clone();
+					}
+				}
+			},
+			Some(ActionKind::Give) =>
+			{
+				if let Some(recipient) = step.targets.first()
+				{
+					for actor in &step.actors
+					{
+						for item in &step.agents
+						{
+							self.release(actor, item);
+							self.hold(recipient, item);
+						}
+					}
+				}
+			},
+			Some(ActionKind::Go) =>
+			{
+				if let Some(place) = step.targets.first()
+				{
+					for actor in &step.actors
+					{
+						self.entity(actor).location = Some(place.clone());
+						self.entity(actor).posture = None;
+					}
+				}
+			},
+			Some(ActionKind::Posture(posture)) =>
+			{
+				for actor in &step.actors
+				{
+					self.entity(actor).posture = Some(posture.to_string());
+				}
+			},
+			Some(ActionKind::Attack) =>
+			{
+				for target in &step.targets
+				{
+					self.entity(target).wounds += 1;
+				}
+			},
+			Some(ActionKind::Consume) =>
+			{
+				for actor in &step.actors
+				{
+					for item in step.agents.iter().chain(&step.targets)
+					{
+						self.release(actor, item);
+						let item = self.entity(item);
+						item.location = None;
+						item.consumed = true;
+					}
+				}
+			},
+			None =>
+			{}
+		}
+		self.events.push(describe_step(step));
+	}
+
+	/// Describe the world for the game master, so that the next turn can take
+	/// it into account.
+	pub fn describe(&self) -> String
+	{
+		let mut description = String::new();
+		for (name, entity) in &self.entities
+		{
+			let mut facts = vec![];
+			if let Some(location) = &entity.location
+			{
+				facts.push(format!("in {location}"));
+			}
+			if !entity.inventory.is_empty()
+			{
+				let items = entity
+					.inventory
+					.iter()
+					.map(|item| match entity.readied.as_ref() == Some(item)
+					{
+						true => format!("{item} (readied)"),
+						false => item.clone()
+					})
+					.collect::<Vec<_>>();
+				facts.push(format!("holding {}", items.join(", ")));
+			}
+			if let Some(posture) = &entity.posture
+			{
+				facts.push(posture.clone());
+			}
+			if entity.wounds > 0
+			{
+				facts.push(describe_wounds(entity.wounds));
+			}
+			if entity.consumed
+			{
+				facts.push("used up".to_string());
+			}
+			if !facts.is_empty()
+			{
+				description
+					.push_str(&format!("* {name}: {}\n", facts.join("; ")));
+			}
+		}
+		let recent = self.events.len().saturating_sub(RECENT_EVENTS);
+		if recent < self.events.len()
+		{
+			description.push_str("\nRecent events:\n");
+			for event in &self.events[recent..]
+			{
+				description.push_str(&format!("* {event}\n"));
+			}
+		}
+		description
+	}
+
+	/// Get the specified entity, creating it if necessary.
+	fn entity(&mut self, name: &str) -> &mut Entity
+	{
+		self.entities.entry(name.to_string()).or_default()
+	}
+
+	/// Put the specified item into the holder's inventory, taking it from any
+	/// previous holder.
+	fn hold(&mut self, holder: &str, item: &str)
+	{
+		if let Some(previous) = self.entity(item).location.clone()
+		{
+			self.release(&previous, item);
+		}
+		self.entity(holder).inventory.insert(item.to_string());
+		self.entity(item).location = Some(holder.to_string());
+	}
+
+	/// Remove the specified item from the holder's inventory, if it is there.
+	fn release(&mut self, holder: &str, item: &str)
+	{
+		if let Some(holder) = self.entities.get_mut(holder)
+			&& holder.inventory.remove(item)
+			&& holder.readied.as_deref() == Some(item)
+		{
+			holder.readied = None;
+		}
+	}
+}
+
+/// Describe the specified action step in a single line, e.g., `player attack
+/// goblin with flaming greatsword`.
+#[cfg(feature = "ssr")]
+fn describe_step(step: &ActionStep) -> String
+{
+	let mut event = format!("{} {}", step.actors.join(" and "), step.action);
+	if !step.targets.is_empty()
+	{
+		event.push_str(&format!(" {}", step.targets.join(" and ")));
+	}
+	if !step.agents.is_empty()
+	{
+		event.push_str(&format!(" with {}", step.agents.join(" and ")));
+	}
+	let params = step
+		.params
+		.iter()
+		.flatten()
+		.map(|(key, value)| match value
+		{
+			Value::String(value) => format!("{key}: {value}"),
+			value => format!("{key}: {value}")
+		})
+		.collect::<Vec<_>>();
+	if !params.is_empty()
+	{
+		event.push_str(&format!(" ({})", params.join(", ")));
+	}
+	event
+}
+
+/// Describe the number of times that an entity has been attacked.
+fn describe_wounds(wounds: u32) -> String
+{
+	match wounds
+	{
+		1 => "attacked once".to_string(),
+		wounds => format!("attacked {wounds} times")
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                Components.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// Represents the state of the model world: every entity with what is known
+/// about it, followed by the most recent events.
+///
+/// # Arguments
+///
+/// * `world` - Specifies the world.
+#[component]
+pub fn WorldPanel(world: Signal<World>) -> impl IntoView
+{
+	let entities = move || world.with(|world| world.entities.clone());
+	let events = move || {
+		world.with(|world| {
+			world
+				.events
+				.iter()
+				.rev()
+				.take(RECENT_EVENTS)
+				.cloned()
+				.collect::<Vec<_>>()
+		})
+	};
+	view! {
+		<div class="card bg-base-200 text-sm">
+			<div class="card-body p-4">
+				<h2 class="card-title text-base">"World"</h2>
+				<Show
+					when=move || world.with(|world| !world.entities.is_empty())
+					fallback=|| view! {
+						<p class="opacity-50">"Nothing has happened yet."</p>
+					}
+				>
+					<ul class="space-y-1">
+						<For
+							each=entities
+							key=|(name, entity)| (name.clone(), entity.clone())
+							children=|(name, entity)| view! {
+								<li><WorldEntity name=name entity=entity/></li>
+							}
+						/>
+					</ul>
+					<h3 class="font-bold mt-2">"Recent events"</h3>
+					<ul class="list-disc pl-4 opacity-75">
+						{move || {
+							events()
+								.into_iter()
+								.map(|event| view! { <li>{event}</li> })
+								.collect_view()
+						}}
+					</ul>
+				</Show>
+			</div>
+		</div>
+	}
+}
+
+/// Represents an entity of the model world as a name followed by badges for
+/// its state.
+///
+/// # Arguments
+///
+/// * `name` - Specifies the name of the entity.
+/// * `entity` - Specifies the entity.
+#[component]
+pub fn WorldEntity(name: String, entity: Entity) -> impl IntoView
+{
+	let mut badges = vec![];
+	if let Some(location) = entity.location
+	{
+		badges.push(format!("in {location}"));
+	}
+	for item in &entity.inventory
+	{
+		match entity.readied.as_ref() == Some(item)
+		{
+			true => badges.push(format!("⚔ {item}")),
+			false => badges.push(format!("🎒 {item}"))
+		}
+	}
+	if let Some(posture) = entity.posture
+	{
+		badges.push(posture);
+	}
+	if entity.wounds > 0
+	{
+		badges.push(describe_wounds(entity.wounds));
+	}
+	if entity.consumed
+	{
+		badges.push("used up".to_string());
+	}
+	view! {
+		<span class="font-semibold">{name}</span>
+		{badges
+			.into_iter()
+			.map(|badge| view! {
+				<span class="badge badge-ghost badge-sm ml-1">{badge}</span>
+			})
+			.collect_view()}
+	}
+}
+
+////////////////////////////////////////////////////////////////////////////////
+//                                 Constants.                                 //
+////////////////////////////////////////////////////////////////////////////////
+
+/// The number of recent events to describe.
+const RECENT_EVENTS: usize = 8;
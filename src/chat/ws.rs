@@ -1,16 +1,27 @@
-use async_openai::types::{
-	ChatCompletionRequestMessage, CreateChatCompletionRequestArgs
-};
 use axum::{
+	Extension,
 	extract::ws::{Message as WebSocketMessage, WebSocket, WebSocketUpgrade},
 	response::IntoResponse
 };
-use futures::{StreamExt, lock::Mutex, stream::SplitSink};
+use futures::{
+	StreamExt,
+	lock::Mutex,
+	stream::{self, SplitSink}
+};
 use std::sync::Arc;
-use tracing::{debug, trace};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, trace, warn};
+use uuid::Uuid;
 
-use super::SessionState;
-use super::{AppMessage, Message};
+use super::{
+	AppMessage, BackendError, BackendRouter, ChatBackend, ChatRequest,
+	Completion, CompletionAssembler, CompletionRequest, CompletionStream,
+	FinishReason, GenerationParams, Message, OutputSchema, PromptLibrary, Role,
+	RoutedBackend, SummaryCache, ToolRegistry, Usage, World, fit_context
+};
+use super::{
+	BusyPolicy, SessionConfig, SessionState, subscribe_prompt_changes
+};
 use crate::error_template::AppError;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -20,116 +31,312 @@ use crate::error_template::AppError;
 /// The handler for the HTTP request, called when the HTTP GET arrives
 /// at the start of Websocket negotiation. After this completes, the actual
 /// Websocket protocol upgrade occurs. This is the last point
-/// where we can extract TCP/IP metadata, HTTP headers, etc.
-pub async fn chat_handler(ws: WebSocketUpgrade) -> impl IntoResponse
+/// where we can extract TCP/IP metadata, HTTP headers, etc. Every session
+/// starts from the [configuration](SessionConfig) in the router's extensions.
+pub async fn chat_handler(
+	ws: WebSocketUpgrade,
+	Extension(config): Extension<SessionConfig>
+) -> impl IntoResponse
 {
-	ws.on_upgrade(move |ws| handle_ws(ws, SessionState::default()))
+	ws.on_upgrade(move |ws| handle_ws(ws, SessionState::new(config)))
 }
 
 /// The handler for the Websocket connection. This is where we handle the
 /// actual Websocket protocol. We receive messages from the client and send
-/// messages to the client.
+/// messages to the client. Between messages, we also relay changes to the
+/// prompt library, as [`AppMessage::SystemPromptChanged`] notifications.
 async fn handle_ws(ws: WebSocket, state: SessionState)
 {
 	let (send, mut recv) = ws.split();
 	let send = Arc::new(Mutex::new(send));
 	let state = Arc::new(Mutex::new(state));
-	while let Some(message) = recv.next().await
+	let mut prompt_changes = subscribe_prompt_changes();
+	// Whether the prompt library is still being watched.
+	let mut watching = true;
+	loop
 	{
+		let message = tokio::select! {
+			message = recv.next() => match message
+			{
+				Some(message) => message,
+				None => break
+			},
+			changed = prompt_changes.recv(), if watching =>
+			{
+				match changed
+				{
+					Ok(id) =>
+					{
+						let message = AppMessage::SystemPromptChanged(id);
+						let _ = message.send_to_client(&send).await;
+					},
+					Err(RecvError::Lagged(skipped)) =>
+					{
+						debug!("Skipped {skipped} system prompt changes")
+					},
+					Err(RecvError::Closed) => watching = false
+				}
+				continue
+			}
+		};
 		let message = decode_message(message);
 		// We have a legitimate message, so process it.
 		match message
 		{
 			None => continue,
-			Some(AppMessage::StartChat(messages)) =>
+			Some(AppMessage::StartChat(request)) =>
+			{
+				start_chat(request, &send, &state).await
+			},
+			Some(AppMessage::CancelChat(id)) =>
 			{
-				let send = Arc::clone(&send);
-				let state = Arc::clone(&state);
-				tokio::spawn(async move {
-					let send = Arc::clone(&send);
-					chat(messages, &send, &state).await;
-				});
+				cancel_chat(id, &send, &state).await
 			},
-			Some(AppMessage::NextChatFragment(_)) =>
+			Some(AppMessage::ChatQueued(_)) =>
+			{
+				debug!("Received unexpected ChatQueued message")
+			},
+			Some(AppMessage::NextChatFragment(..)) =>
 			{
 				debug!("Received unexpected NextChatFragment message")
 			},
-			Some(AppMessage::ChatCompleted) =>
+			Some(AppMessage::ChatRetried(_)) =>
+			{
+				debug!("Received unexpected ChatRetried message")
+			},
+			Some(AppMessage::ToolCalls(..)) =>
+			{
+				debug!("Received unexpected ToolCalls message")
+			},
+			Some(AppMessage::ToolResult(..)) =>
+			{
+				debug!("Received unexpected ToolResult message")
+			},
+			Some(AppMessage::ContextTrimmed(..)) =>
+			{
+				debug!("Received unexpected ContextTrimmed message")
+			},
+			Some(AppMessage::ChatUsage(..)) =>
+			{
+				debug!("Received unexpected ChatUsage message")
+			},
+			Some(AppMessage::ChatCompleted(_)) =>
 			{
 				debug!("Received unexpected ChatCompleted message")
 			},
-			Some(AppMessage::Error(_)) =>
+			Some(AppMessage::ChatCancelled(_)) =>
+			{
+				debug!("Received unexpected ChatCancelled message")
+			},
+			Some(AppMessage::Error(..)) =>
 			{
 				debug!("Received unexpected Error message")
+			},
+			Some(AppMessage::SystemPromptChanged(_)) =>
+			{
+				debug!("Received unexpected SystemPromptChanged message")
 			}
 		}
 	}
+	// The client is gone, so abandon any chats in progress or queued.
+	let mut state = state.lock().await;
+	state.queue.clear();
+	for (id, task) in state.chats.drain()
+	{
+		trace!("Connection closed; aborting chat: {id}");
+		task.abort();
+	}
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 //                                   Chat.                                    //
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Start a chat with the given messages. This function will send the messages
-/// to the OpenAI API and then stream the responses back to the client via a
-/// series of [`AppMessage::NextChatFragment`] messages. When the chat is
-/// complete, a [`AppMessage::ChatCompleted`] message will be sent.
+/// Start the requested chat. The chat runs in its own task, so that the
+/// session can continue to receive messages, e.g.,
+/// [`AppMessage::CancelChat`], while the assistant generates its response.
 ///
-/// If the chat assistant is busy, then a [`AppError::ChatError`] will be
-/// returned.
+/// If the chat assistant is busy, then the chat is either rejected with an
+/// [`AppError::ChatBusy`] or queued and acknowledged with
+/// [`AppMessage::ChatQueued`], according to the session's
+/// [busy policy](BusyPolicy). If the request identifier is already in use,
+/// then the chat is rejected with an [`AppError::ChatError`].
 ///
 /// # Arguments
 ///
-/// - `messages`: The messages to send to the chat assistant.
+/// - `request`: The chat request.
 /// - `send`: The websocket sink to send messages to the client.
 /// - `state`: The session state.
-async fn chat(
-	messages: Vec<Message>,
+async fn start_chat(
+	request: ChatRequest,
 	send: &Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>,
 	state: &Arc<Mutex<SessionState>>
 )
 {
-	// If the chat assistant is busy, then we can't start a new chat.
+	let id = request.id;
+	// Hold the lock until the task is recorded, so that the task cannot
+	// release its slot before we know about it.
+	let mut locked = state.lock().await;
+	let reply = if locked.chat_known(id)
+	{
+		let e = AppError::ChatError;
+		debug!("Chat already in progress: {id}: {e}");
+		Some(AppMessage::Error(id, e))
+	}
+	else if !locked.chat_busy()
+	{
+		spawn_chat(request, send, state, &mut locked);
+		None
+	}
+	else
 	{
-		let mut state = state.lock().await;
-		match state.chat_busy
+		match locked.config.busy_policy
 		{
-			true =>
+			BusyPolicy::Reject =>
 			{
-				let e = AppError::ChatError;
-				debug!("Chat assistant is busy: {e}");
-				return
+				let e = AppError::ChatBusy;
+				debug!("Chat assistant is busy: {id}: {e}");
+				Some(AppMessage::Error(id, e))
 			},
-			false =>
+			BusyPolicy::Queue =>
 			{
-				trace!("Chat assistant is now busy");
-				state.chat_busy = true;
+				trace!("Chat assistant is busy; chat queued: {id}");
+				locked.queue.push_back(request);
+				Some(AppMessage::ChatQueued(id))
 			}
 		}
+	};
+	drop(locked);
+	if let Some(reply) = reply
+	{
+		let _ = reply.send_to_client(send).await;
 	}
+}
+
+/// Spawn the task that runs the requested chat, and record it in the session
+/// state.
+///
+/// # Arguments
+///
+/// - `request`: The chat request.
+/// - `send`: The websocket sink to send messages to the client.
+/// - `state`: The session state.
+/// - `locked`: The session state, already locked by the caller.
+fn spawn_chat(
+	request: ChatRequest,
+	send: &Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>,
+	state: &Arc<Mutex<SessionState>>,
+	locked: &mut SessionState
+)
+{
+	let id = request.id;
+	trace!("Chat started: {id}");
+	let send = Arc::clone(send);
+	let state = Arc::clone(state);
+	let task = tokio::spawn(async move {
+		chat(request, &send, &state).await;
+	});
+	locked.chats.insert(id, task.abort_handle());
+}
+
+/// Cancel the specified chat, if it is still in progress or queued. Aborting
+/// the chat task drops the upstream completion stream, which closes the
+/// connection to the chat assistant and thereby stops the generation. The
+/// client is answered with [`AppMessage::ChatCancelled`].
+///
+/// # Arguments
+///
+/// - `id`: The request identifier of the chat to cancel.
+/// - `send`: The websocket sink to send messages to the client.
+/// - `state`: The session state.
+async fn cancel_chat(
+	id: Uuid,
+	send: &Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>,
+	state: &Arc<Mutex<SessionState>>
+)
+{
+	let mut locked = state.lock().await;
+	if let Some(task) = locked.chats.remove(&id)
+	{
+		task.abort();
+		trace!("Chat cancelled: {id}");
+		// Cancellation frees a slot, so start the next queued chat, if any.
+		if let Some(next) = locked.queue.pop_front()
+		{
+			spawn_chat(next, send, state, &mut locked);
+		}
+	}
+	else if let Some(index) = locked.queue.iter().position(|r| r.id == id)
+	{
+		locked.queue.remove(index);
+		trace!("Queued chat cancelled: {id}");
+	}
+	else
+	{
+		debug!("No chat to cancel: {id}");
+		return
+	}
+	drop(locked);
+	let _ = AppMessage::ChatCancelled(id).send_to_client(send).await;
+}
+
+/// Run the requested chat. This function will send the messages to the chosen
+/// [chat backend](super::ChatBackend) and then stream the responses back to
+/// the client via a series of [`AppMessage::NextChatFragment`] messages. When
+/// the chat is complete, a [`AppMessage::ChatCompleted`] message will be sent.
+///
+/// The caller must already have recorded the chat in the session state. This
+/// function removes it again when the chat concludes, and then starts the next
+/// queued chat, if any.
+///
+/// # Arguments
+///
+/// - `request`: The chat request.
+/// - `send`: The websocket sink to send messages to the client.
+/// - `state`: The session state.
+async fn chat(
+	request: ChatRequest,
+	send: &Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>,
+	state: &Arc<Mutex<SessionState>>
+)
+{
+	let id = request.id;
 	// Deal with the chat and present the conclusion to the client.
-	match just_chat(messages, send, state).await
+	match just_chat(request, send, state).await
 	{
 		Ok(_) =>
 		{
-			trace!("Chat completed");
-			let _ = AppMessage::ChatCompleted.send_to_client(send).await;
+			trace!("Chat completed: {id}");
+			let _ = AppMessage::ChatCompleted(id).send_to_client(send).await;
 		},
 		Err(e) =>
 		{
-			debug!("Chat error: {:?}", e);
-			let _ = AppMessage::Error(e).send_to_client(send).await;
+			debug!("Chat error: {id}: {:?}", e);
+			let _ = AppMessage::Error(id, e).send_to_client(send).await;
 		}
 	};
-	// The chat assistant is no longer busy.
-	let mut state = state.lock().await;
-	state.chat_busy = false;
-	trace!("Chat assistant is now available");
+	// The chat is no longer in progress, so start the next queued chat, if
+	// any.
+	let mut locked = state.lock().await;
+	locked.chats.remove(&id);
+	trace!("Chat concluded: {id}");
+	if let Some(next) = locked.queue.pop_front()
+	{
+		spawn_chat(next, send, state, &mut locked);
+	}
 }
 
-/// Start a chat with the given messages. This function will send the messages
-/// to the OpenAI API and then stream the responses back to the client via a
-/// series of [`AppMessage::NextChatFragment`] messages.
+/// Run the requested chat. This function will send the messages to the chosen
+/// [chat backend](super::ChatBackend), using the requested
+/// [generation parameters](super::GenerationParams) as
+/// [resolved](super::GenerationConfig::resolve) against the server's
+/// configuration, and then stream the responses back to the client via a series
+/// of [`AppMessage::NextChatFragment`] messages.
+///
+/// If the requested system prompt has a [schema](OutputSchema), then the
+/// complete response must match it. A response that does not is retried, with
+/// the validation errors as feedback for the assistant, up to the session's
+/// limit; each retry is announced by an [`AppMessage::ChatRetried`] message.
 ///
 /// This function does not handle the chat assistant's busy state. The caller
 /// must handle this, and ensure that the state is always instantaneously
@@ -137,65 +344,622 @@ async fn chat(
 ///
 /// # Arguments
 ///
-/// - `messages`: The messages to send to the chat assistant.
+/// - `request`: The chat request.
 /// - `send`: The websocket sink to send messages to the client.
 /// - `state`: The session state.
 ///
 /// # Returns
 ///
 /// This function returns `()` if the chat completes successfully. If there is
-/// an error, then an [`AppError`] is returned. In neither case is the
-/// conclusion transmitted to the client. This is the responsibility of the
-/// caller.
+/// an error, then an [`AppError`] is returned, e.g., an
+/// [`AppError::InvalidOutput`] if no response matched the schema. In neither
+/// case is the conclusion transmitted to the client. This is the
+/// responsibility of the caller.
 async fn just_chat(
-	messages: Vec<Message>,
+	request: ChatRequest,
 	send: &Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>,
 	state: &Arc<Mutex<SessionState>>
 ) -> Result<(), AppError>
 {
-	// Convert the messages to the OpenAI message type.
-	let messages: Vec<ChatCompletionRequestMessage> = messages
+	let ChatRequest {
+		id,
+		mut messages,
+		prompt,
+		params,
+		backend
+	} = request;
+	// Clone the backends and configuration, so that the session is not locked
+	// while the stream is being established.
+	let (router, generation, json_mode, retries, window, summaries) = {
+		let state = state.lock().await;
+		(
+			Arc::clone(&state.config.backends),
+			state.config.generation.clone(),
+			state.config.json_mode,
+			state.config.schema_retries,
+			state.config.context_window,
+			state
+				.config
+				.summarize_context
+				.then(|| Arc::clone(&state.config.summaries))
+		)
+	};
+	// Load the prompt's schema, if any. A broken prompt or schema is a
+	// configuration problem, so there is no point in asking the assistant
+	// anything.
+	let library = PromptLibrary::configured();
+	let info = library
+		.load(prompt.as_deref())
+		.map_err(|e| {
+			debug!("Failed to load system prompt: {id}: {e}");
+			AppError::ServerError
+		})?
+		.map(|prompt| prompt.info);
+	let schema = match &info
+	{
+		Some(info) => library.schema(info).map_err(|e| {
+			debug!("Failed to load schema: {id}: {e}");
+			AppError::ServerError
+		})?,
+		None => None
+	};
+	// If the prompt drives the model world, then tell the assistant about the
+	// world as it stands after the steps so far. This may add a system message
+	// ahead of the client's messages.
+	let count = messages.len();
+	if info.as_ref().is_some_and(|info| info.world)
+	{
+		let world = World::replay(&messages);
+		trace!("World: {id}: {:#?}", world);
+		describe_world(&mut messages, &world);
+	}
+	let added = messages.len() - count;
+	let json_mode =
+		json_mode && schema.as_ref().is_some_and(OutputSchema::expects_object);
+	// Offer the assistant whichever tools the prompt enables.
+	let tools = match &info
+	{
+		Some(info) => ToolRegistry::configured().select(&info.tools),
+		None => ToolRegistry::default()
+	};
+	// The chosen backend does the counting and summarizing, even if another
+	// stands in for it.
+	let primary = router.get(backend.as_deref()).clone();
+	let backend = &*primary.backend;
+	// Resolution fills in every parameter that has a default, so only the
+	// optional parameters need special handling. The backend's own model, if
+	// any, takes the place of the server's default.
+	let chose_model = params
+		.model
+		.as_ref()
+		.is_some_and(|model| !model.trim().is_empty());
+	let mut params = generation.resolve(&params);
+	if !chose_model && let Some(model) = &primary.model
+	{
+		params.model = Some(model.clone());
+	}
+	trace!(
+		"Generation parameters: {id}: {}: {:#?}",
+		primary.name, params
+	);
+	// Fit the messages to the context window, leaving room for the response.
+	let budget = window.saturating_sub(params.max_tokens.unwrap_or(0) as usize);
+	let mut messages = manage_context(
+		id,
+		backend,
+		&params,
+		messages,
+		added,
+		budget,
+		summaries.as_deref(),
+		send
+	)
+	.await?;
+	let mut usage = Usage {
+		exact: true,
+		..Default::default()
+	};
+	let mut attempt = 0;
+	let mut tool_rounds = 0;
+	loop
+	{
+		// Tool calls and retries lengthen the chat, so it may no longer fit.
+		if tool_rounds > 0 || attempt > 0
+		{
+			messages = refit_context(id, backend, messages, budget).await?;
+		}
+		// Once the assistant has used up its tool calls, it has to answer
+		// without them.
+		let offered = match tool_rounds < MAX_TOOL_ROUNDS
+		{
+			true => &tools,
+			false => &ToolRegistry::default()
+		};
+		let Completion {
+			message: response,
+			usage: reported,
+			..
+		} = stream_chat(
+			id, &router, &primary, &params, &messages, json_mode, offered, send
+		)
+		.await?;
+		// Count the tokens whenever the backend does not report them.
+		usage += match reported
+		{
+			Some(reported) => reported,
+			None => Usage {
+				prompt_tokens: backend
+					.count_tokens(&messages)
+					.await
+					.iter()
+					.sum(),
+				completion_tokens: backend
+					.count_tokens(std::slice::from_ref(&response))
+					.await
+					.iter()
+					.sum(),
+				exact: backend.counts_exact()
+			}
+		};
+		if !response.tool_calls.is_empty()
+		{
+			tool_rounds += 1;
+			let calls = response.tool_calls.clone();
+			AppMessage::ToolCalls(id, calls.clone())
+				.send_to_client(send)
+				.await?;
+			messages.push(response);
+			for call in &calls
+			{
+				trace!("Calling tool: {id}: {:?}", call);
+				let result = offered.call(call);
+				AppMessage::ToolResult(id, result.clone())
+					.send_to_client(send)
+					.await?;
+				messages.push(result);
+			}
+			continue
+		}
+		let Some(schema) = &schema
+		else
+		{
+			break
+		};
+		match schema.validate(&response.content)
+		{
+			Ok(_) => break,
+			Err(e) if attempt < retries =>
+			{
+				attempt += 1;
+				debug!("Invalid output; retrying: {id}: {e}");
+				AppMessage::ChatRetried(id).send_to_client(send).await?;
+				messages.push(response);
+				messages.push(Role::User.message(format!(
+					"Your response is invalid. {e}. Respond again with \
+					corrected output only."
+				)));
+			},
+			Err(e) =>
+			{
+				debug!("Invalid output: {id}: {e}");
+				return Err(AppError::InvalidOutput(e.to_string()))
+			}
+		}
+	}
+	trace!("Usage: {id}: {:?}", usage);
+	AppMessage::ChatUsage(id, usage).send_to_client(send).await
+}
+
+/// Fit the specified messages to the context budget, by leaving out the oldest
+/// turns and optionally summarizing them. Tells the client which of its
+/// messages were left out, via [`AppMessage::ContextTrimmed`].
+///
+/// # Arguments
+///
+/// - `id`: The request identifier.
+/// - `backend`: The chat backend, for counting and summarizing.
+/// - `params`: The resolved generation parameters, for summarizing.
+/// - `messages`: The messages to send to the chat assistant.
+/// - `added`: The number of messages that the server added ahead of the
+///   client's messages, which offset the indices that the client knows.
+/// - `budget`: The context budget, in tokens.
+/// - `summaries`: The summaries of messages left out before, or `None` not to
+///   summarize the messages that are left out.
+/// - `send`: The websocket sink to send messages to the client.
+///
+/// # Returns
+///
+/// The messages that fit.
+#[allow(clippy::too_many_arguments)]
+async fn manage_context(
+	id: Uuid,
+	backend: &dyn ChatBackend,
+	params: &GenerationParams,
+	messages: Vec<Message>,
+	added: usize,
+	budget: usize,
+	summaries: Option<&SummaryCache>,
+	send: &Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>
+) -> Result<Vec<Message>, AppError>
+{
+	let sizes = backend.count_tokens(&messages).await;
+	let fitted = fit_context(&messages, &sizes, budget);
+	if fitted.excluded.is_empty()
+	{
+		return Ok(fitted.messages)
+	}
+	// A summary takes up room too, so leave out enough to make room for it.
+	let mut summarized = false;
+	let fitted = match summaries
+	{
+		Some(summaries) =>
+		{
+			let mut fitted = fit_context(
+				&messages,
+				&sizes,
+				budget.saturating_sub(SUMMARY_TOKENS)
+			);
+			let excluded = fitted
+				.excluded
+				.iter()
+				.map(|&index| &messages[index])
+				.collect::<Vec<_>>();
+			match summarize_excluded(backend, params, summaries, &excluded)
+				.await
+			{
+				Ok(summary) =>
+				{
+					// The summary follows the system message, if any.
+					let summary = Role::System.message(format!(
+						"Summary of the earlier conversation:\n\n{summary}"
+					));
+					let index = match fitted.messages.first()
+					{
+						Some(first) if first.role == Role::System => 1,
+						_ => 0
+					};
+					fitted.tokens += backend
+						.count_tokens(std::slice::from_ref(&summary))
+						.await
+						.iter()
+						.sum::<usize>();
+					fitted.messages.insert(index, summary);
+					summarized = true;
+				},
+				Err(e) =>
+				{
+					debug!("Failed to summarize context; truncating: {id}: {e}")
+				}
+			}
+			match summarized
+			{
+				true => fitted,
+				false => fit_context(&messages, &sizes, budget)
+			}
+		},
+		None => fitted
+	};
+	debug!(
+		"Context trimmed: {id}: {} messages left out, {} tokens remain",
+		fitted.excluded.len(),
+		fitted.tokens
+	);
+	// Only the pinned messages remain, and they still do not fit, so the
+	// request is bound to fail.
+	if fitted.tokens > budget
+	{
+		return Err(AppError::ContextExceeded(fitted.tokens))
+	}
+	let excluded = fitted
+		.excluded
 		.iter()
-		.map(TryInto::try_into)
-		.collect::<Result<Vec<_>, _>>()
-		.map_err(|_| AppError::ChatError)?;
-	// Create a chat stream.
-	let request = CreateChatCompletionRequestArgs::default()
-		.model(MODEL)
-		.messages(messages)
-		.max_tokens(150u16)
-		.temperature(0.8f32)
-		.top_p(0.95f32)
-		.stream(true)
-		.build()
-		.map_err(|_| AppError::ChatError)?;
-	let mut chat_stream = {
-		let client = &state.lock().await.chat_client;
-		client
-			.chat()
-			.create_stream(request)
-			.await
-			.map_err(|_| AppError::ChatError)?
+		.filter_map(|index| index.checked_sub(added))
+		.collect();
+	AppMessage::ContextTrimmed(id, excluded, summarized)
+		.send_to_client(send)
+		.await?;
+	Ok(fitted.messages)
+}
+
+/// Fit the messages of a chat in progress to the context budget again, after
+/// tool calls or retries have lengthened it, by leaving out the oldest turns.
+/// The client already heard what was left out when the chat started, so it is
+/// not told again.
+///
+/// # Arguments
+///
+/// - `id`: The request identifier.
+/// - `backend`: The chat backend, for counting.
+/// - `messages`: The messages to send to the chat assistant.
+/// - `budget`: The context budget, in tokens.
+///
+/// # Returns
+///
+/// The messages that fit.
+async fn refit_context(
+	id: Uuid,
+	backend: &dyn ChatBackend,
+	messages: Vec<Message>,
+	budget: usize
+) -> Result<Vec<Message>, AppError>
+{
+	let sizes = backend.count_tokens(&messages).await;
+	let fitted = fit_context(&messages, &sizes, budget);
+	if fitted.tokens > budget
+	{
+		return Err(AppError::ContextExceeded(fitted.tokens))
+	}
+	if !fitted.excluded.is_empty()
+	{
+		debug!(
+			"Context trimmed again: {id}: {} messages left out, {} tokens \
+			remain",
+			fitted.excluded.len(),
+			fitted.tokens
+		);
+	}
+	Ok(fitted.messages)
+}
+
+/// Summarize the specified messages, which are about to be left out of the
+/// context. An earlier turn of the same conversation usually summarized most of
+/// them already, so only the rest go to the chat backend, along with the
+/// earlier summary.
+///
+/// # Arguments
+///
+/// - `backend`: The chat backend.
+/// - `params`: The resolved generation parameters. Only the model applies.
+/// - `summaries`: The summaries of messages left out before.
+/// - `messages`: The messages to summarize.
+///
+/// # Returns
+///
+/// The summary.
+async fn summarize_excluded(
+	backend: &dyn ChatBackend,
+	params: &GenerationParams,
+	summaries: &SummaryCache,
+	messages: &[&Message]
+) -> Result<String, AppError>
+{
+	let (covered, earlier) = match summaries.longest(messages)
+	{
+		Some(cached) if cached.covered == messages.len() =>
+		{
+			return Ok(cached.summary)
+		},
+		Some(cached) => (cached.covered, Some(cached.summary)),
+		None => (0, None)
+	};
+	let summary = summarize_messages(
+		backend,
+		params,
+		earlier.as_deref(),
+		&messages[covered..]
+	)
+	.await?;
+	summaries.insert(messages, summary.clone());
+	Ok(summary)
+}
+
+/// Ask the chat backend to summarize the specified messages, continuing an
+/// earlier summary, if any.
+///
+/// # Arguments
+///
+/// - `backend`: The chat backend.
+/// - `params`: The resolved generation parameters. Only the model applies.
+/// - `earlier`: The summary of the messages before these, if any.
+/// - `messages`: The messages to summarize.
+///
+/// # Returns
+///
+/// The summary.
+async fn summarize_messages(
+	backend: &dyn ChatBackend,
+	params: &GenerationParams,
+	earlier: Option<&str>,
+	messages: &[&Message]
+) -> Result<String, AppError>
+{
+	let transcript =
+		earlier
+			.map(|summary| {
+				format!("Summary of the earlier conversation: {summary}")
+			})
+			.into_iter()
+			.chain(messages.iter().map(|message| {
+				format!("{:?}: {}", message.role, message.content)
+			}))
+			.collect::<Vec<_>>()
+			.join("\n\n");
+	let summary = backend
+		.complete(CompletionRequest {
+			params: GenerationParams {
+				model: params.model.clone(),
+				max_tokens: Some(SUMMARY_TOKENS as u16),
+				..Default::default()
+			},
+			messages: vec![
+				Role::System.message(SUMMARY_PROMPT.to_string()),
+				Role::User.message(transcript),
+			],
+			json_mode: false,
+			tools: vec![]
+		})
+		.await?;
+	Ok(summary.trim().to_string())
+}
+
+/// Describe the specified world at the end of the system message, adding a
+/// system message if there is none.
+///
+/// # Arguments
+///
+/// - `messages`: The messages to send to the chat assistant.
+/// - `world`: The model world.
+fn describe_world(messages: &mut Vec<Message>, world: &World)
+{
+	let description = world.describe();
+	if description.is_empty()
+	{
+		return
+	}
+	let description = format!(
+		"The model world currently stands as follows:\n\n{description}"
+	);
+	match messages.first_mut()
+	{
+		Some(system) if system.role == Role::System =>
+		{
+			system.content = format!("{}\n\n{description}", system.content);
+		},
+		_ => messages.insert(0, Role::System.message(description))
+	}
+}
+
+/// Ask the chat backend for a single response, and stream its content back to
+/// the client via a series of [`AppMessage::NextChatFragment`] messages. The
+/// arguments of any tool calls arrive in pieces too, so they are accumulated
+/// until the response is complete. If the chosen backend is unreachable, then
+/// the next healthy backend stands in; see [`start_stream`].
+///
+/// # Arguments
+///
+/// - `id`: The request identifier.
+/// - `router`: The chat backends.
+/// - `primary`: The chosen backend.
+/// - `params`: The resolved generation parameters.
+/// - `messages`: The messages to send to the chat assistant.
+/// - `json_mode`: Whether to ask for a JSON object, e.g., via OpenAI's JSON
+///   mode.
+/// - `tools`: The tools that the assistant may call.
+/// - `send`: The websocket sink to send messages to the client.
+///
+/// # Returns
+///
+/// The [assembled](CompletionAssembler) completion, whose message includes
+/// any tool calls.
+#[allow(clippy::too_many_arguments)]
+async fn stream_chat(
+	id: Uuid,
+	router: &BackendRouter,
+	primary: &RoutedBackend,
+	params: &GenerationParams,
+	messages: &[Message],
+	json_mode: bool,
+	tools: &ToolRegistry,
+	send: &Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>
+) -> Result<Completion, AppError>
+{
+	let request = CompletionRequest {
+		params: params.clone(),
+		messages: messages.to_vec(),
+		json_mode,
+		tools: tools.definitions()
 	};
+	let (name, mut chat_stream) =
+		start_stream(id, router, primary, request).await?;
 	// Process the chat stream.
-	while let Some(fragment) = chat_stream.next().await
+	let mut assembler = CompletionAssembler::default();
+	while let Some(chunk) = chat_stream.next().await
+	{
+		let chunk = chunk.map_err(|e| {
+			debug!("Chat failed: {id}: {name}: {e}");
+			AppError::ChatError
+		})?;
+		if let Some(fragment) = assembler.push(chunk)
+		{
+			let message = AppMessage::NextChatFragment(id, fragment);
+			message.send_to_client(send).await?;
+		}
+	}
+	let completion = assembler.finish();
+	match completion.finish_reason
 	{
-		trace!("Received chat fragment: {:#?}", fragment);
-		let mut fragment = fragment.map_err(|_| AppError::ChatError)?;
-		let choice = fragment.choices.first_mut().ok_or(AppError::ChatError)?;
-		let fragment = match choice.finish_reason
+		Some(FinishReason::Length) =>
 		{
-			Some(reason) =>
+			debug!("Response cut off at the token limit: {id}: {name}")
+		},
+		Some(FinishReason::ContentFilter) =>
+		{
+			debug!("Response withheld by the content filter: {id}: {name}")
+		},
+		_ =>
+		{}
+	}
+	Ok(completion)
+}
+
+/// Start streaming a response from the chosen backend, or, if it cannot be
+/// reached before the first piece of the response arrives, from the next
+/// healthy backend. A backend that stands in for another uses its own model,
+/// if its profile names one. Backends that cannot be reached are marked
+/// unhealthy, so that later requests try them last.
+///
+/// # Arguments
+///
+/// - `id`: The request identifier.
+/// - `router`: The chat backends.
+/// - `primary`: The chosen backend.
+/// - `request`: The completion request, for the chosen backend.
+///
+/// # Returns
+///
+/// The name of the backend that answered, and the stream of pieces of its
+/// response.
+async fn start_stream(
+	id: Uuid,
+	router: &BackendRouter,
+	primary: &RoutedBackend,
+	request: CompletionRequest
+) -> Result<(String, CompletionStream), AppError>
+{
+	for routed in router.route(Some(&primary.name))
+	{
+		let mut request = request.clone();
+		if routed.name != primary.name
+		{
+			warn!("Failing over: {id}: {} -> {}", primary.name, routed.name);
+			if let Some(model) = &routed.model
 			{
-				trace!("Chat finished: {:#?}", reason);
-				break
+				request.params.model = Some(model.clone());
+			}
+		}
+		// Some backends only report a failure to connect as the first piece
+		// of the response, so wait for that before committing to a backend.
+		let started = match routed.backend.stream_completion(request).await
+		{
+			Ok(mut chat_stream) => match chat_stream.next().await
+			{
+				Some(Err(e)) => Err(e),
+				first => Ok((first, chat_stream))
 			},
-			None => choice.delta.content.take().ok_or(AppError::ChatError)?
+			Err(e) => Err(e)
 		};
-		let message = AppMessage::NextChatFragment(fragment);
-		message.send_to_client(send).await?;
+		match started
+		{
+			Err(BackendError::Unavailable(e)) =>
+			{
+				warn!("Backend unavailable: {id}: {}: {e}", routed.name);
+				router.mark_unhealthy(&routed.name);
+			},
+			Err(e) =>
+			{
+				debug!("Failed to start chat: {id}: {}: {e}", routed.name);
+				return Err(AppError::ChatError)
+			},
+			Ok((first, rest)) =>
+			{
+				router.mark_healthy(&routed.name);
+				let chat_stream = stream::iter(first).chain(rest).boxed();
+				return Ok((routed.name, chat_stream))
+			}
+		}
 	}
-	Ok(())
+	debug!("No backend available: {id}");
+	Err(AppError::ChatError)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -267,6 +1031,18 @@ fn decode_message(
 //                                 Constants.                                 //
 ////////////////////////////////////////////////////////////////////////////////
 
-/// The model to use for the chat completion. This is the model that will be
-/// used to generate the chat responses.
-const MODEL: &str = "mistralai_mixtral-8x7b-instruct-v0.1";
+/// The maximum number of rounds of tool calls in a single chat. This stops an
+/// assistant that keeps calling tools from running forever.
+const MAX_TOOL_ROUNDS: usize = 4;
+
+/// The maximum number of tokens in a summary of the messages that do not fit
+/// in the context window.
+const SUMMARY_TOKENS: usize = 512;
+
+/// The instructions for summarizing the messages that do not fit in the
+/// context window.
+const SUMMARY_PROMPT: &str = "Summarize the following conversation between a \
+	user and an assistant in a few short paragraphs. Keep every name, fact, \
+	decision, and unresolved thread that later turns may depend on. If the \
+	conversation begins with a summary of what came before, fold it into \
+	yours. Write the summary only, with no preamble.";
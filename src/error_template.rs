@@ -14,6 +14,15 @@ pub enum AppError
 	#[error("Chat Error")]
 	ChatError,
 
+	#[error("Chat Busy")]
+	ChatBusy,
+
+	#[error("Invalid Output: {0}")]
+	InvalidOutput(String),
+
+	#[error("Context Exceeded: {0} tokens")]
+	ContextExceeded(usize),
+
 	#[error("Server Error")]
 	ServerError
 }
@@ -26,6 +35,9 @@ impl AppError
 		{
 			AppError::NotFound => StatusCode::NOT_FOUND,
 			AppError::ChatError => StatusCode::INTERNAL_SERVER_ERROR,
+			AppError::ChatBusy => StatusCode::TOO_MANY_REQUESTS,
+			AppError::InvalidOutput(_) => StatusCode::BAD_GATEWAY,
+			AppError::ContextExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
 			AppError::ServerError => StatusCode::INTERNAL_SERVER_ERROR
 		}
 	}
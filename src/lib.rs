@@ -1,3 +1,5 @@
+#![recursion_limit = "256"]
+
 pub mod app;
 pub mod chat;
 pub mod error_template;
@@ -13,3 +15,34 @@ pub fn hydrate()
 	log::debug!("Logging online: ≥{}", level);
 	leptos::mount::hydrate_body(App);
 }
+
+/// Build the application's router: the chat websocket at `/api/chat`, and the
+/// Leptos routes for everything else.
+///
+/// # Arguments
+///
+/// - `leptos_options`: The Leptos options.
+/// - `config`: The configuration of the chat sessions, including the chat
+///   backends.
+#[cfg(feature = "ssr")]
+pub fn router(
+	leptos_options: leptos::prelude::LeptosOptions,
+	config: chat::SessionConfig
+) -> axum::Router
+{
+	use crate::app::{App, shell};
+	use crate::chat::chat_handler;
+	use axum::{Extension, Router, routing::get};
+	use leptos_axum::{LeptosRoutes, generate_route_list};
+
+	let routes = generate_route_list(App);
+	Router::new()
+		.route("/api/chat", get(chat_handler))
+		.leptos_routes(&leptos_options, routes, {
+			let leptos_options = leptos_options.clone();
+			move || shell(leptos_options.clone())
+		})
+		.fallback(leptos_axum::file_and_error_handler(shell))
+		.layer(Extension(config))
+		.with_state(leptos_options)
+}
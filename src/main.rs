@@ -4,13 +4,10 @@
 #[tokio::main]
 async fn main()
 {
-	use axum::Router;
-	use axum::routing::get;
-	use chat_base::app::{App, shell};
-	use chat_base::chat::chat_handler;
+	use chat_base::chat::SessionConfig;
+	use chat_base::router;
 	use dotenvy::dotenv;
 	use leptos::prelude::*;
-	use leptos_axum::{LeptosRoutes, generate_route_list};
 	use tracing::{Level, debug, info};
 	use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
@@ -52,17 +49,10 @@ async fn main()
 	debug!("{:#?}", leptos_options);
 
 	let addr = leptos_options.site_addr;
-	let routes = generate_route_list(App);
 
-	// Build the application from its routes and the configured Leptos options.
-	let app = Router::new()
-		.route("/api/chat", get(chat_handler))
-		.leptos_routes(&leptos_options, routes, {
-			let leptos_options = leptos_options.clone();
-			move || shell(leptos_options.clone())
-		})
-		.fallback(leptos_axum::file_and_error_handler(shell))
-		.with_state(leptos_options);
+	// Build the application from its routes, the configured Leptos options,
+	// and the configured chat backends.
+	let app = router(leptos_options, SessionConfig::configured());
 
 	let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
 	info!("Listening on http://{}", &addr);
@@ -0,0 +1,134 @@
+#![cfg(feature = "ssr")]
+
+use async_openai::{Client, config::OpenAIConfig};
+use axum::http::{StatusCode, header::CONTENT_TYPE};
+use chat_base::chat::{
+	BackendError, ChatBackend, CompletionChunk, CompletionRequest,
+	GenerationParams, OllamaBackend, OpenAiBackend, Role
+};
+use futures::StreamExt;
+use tokio::net::TcpListener;
+
+/// Serve the specified response to every request on an ephemeral port.
+///
+/// # Returns
+///
+/// The base URL of the server.
+async fn serve(status: StatusCode, content_type: &str, body: &str) -> String
+{
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let addr = listener.local_addr().unwrap();
+	let response = (
+		status,
+		[(CONTENT_TYPE, content_type.to_string())],
+		body.to_string()
+	);
+	let app = axum::Router::new()
+		.fallback(move || std::future::ready(response.clone()));
+	tokio::spawn(async move {
+		axum::serve(listener, app.into_make_service())
+			.await
+			.unwrap();
+	});
+	format!("http://{addr}")
+}
+
+/// Get the base URL of a port that nothing listens on.
+async fn unreachable() -> String
+{
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	format!("http://{}", listener.local_addr().unwrap())
+}
+
+/// Create a backend for the OpenAI API at the specified base URL.
+fn openai(url: &str) -> OpenAiBackend
+{
+	OpenAiBackend::new(Client::with_config(
+		OpenAIConfig::new().with_api_base(format!("{url}/v1"))
+	))
+}
+
+/// Create a request for a completion.
+fn request() -> CompletionRequest
+{
+	CompletionRequest {
+		params: GenerationParams {
+			model: Some("model".into()),
+			..Default::default()
+		},
+		messages: vec![Role::User.message("Hello?".into())],
+		json_mode: false,
+		tools: vec![]
+	}
+}
+
+#[tokio::test]
+async fn reports_unreachable_servers_as_unavailable()
+{
+	let url = unreachable().await;
+	let openai = openai(&url);
+	let ollama = OllamaBackend::new(&url);
+	for backend in [&openai as &dyn ChatBackend, &ollama]
+	{
+		let error = backend.stream_completion(request()).await.err().unwrap();
+		assert!(
+			matches!(error, BackendError::Unavailable(_)),
+			"{}: {error}",
+			backend.name()
+		);
+		let error = backend.list_models().await.unwrap_err();
+		assert!(
+			matches!(error, BackendError::Unavailable(_)),
+			"{}: {error}",
+			backend.name()
+		);
+	}
+}
+
+#[tokio::test]
+async fn reports_openai_error_responses()
+{
+	let url = serve(
+		StatusCode::INTERNAL_SERVER_ERROR,
+		"application/json",
+		r#"{"error":{"message":"The model crashed","type":"server_error"}}"#
+	)
+	.await;
+	let error = openai(&url)
+		.stream_completion(request())
+		.await
+		.err()
+		.unwrap();
+	assert_eq!(
+		error,
+		BackendError::Backend(
+			"500 Internal Server Error: The model crashed".into()
+		)
+	);
+}
+
+#[tokio::test]
+async fn reports_openai_error_events()
+{
+	let url = serve(
+		StatusCode::OK,
+		"text/event-stream",
+		concat!(
+			"data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",",
+			"\"created\":0,\"model\":\"model\",\"choices\":[{\"index\":0,",
+			"\"delta\":{\"content\":\"Once\"},\"finish_reason\":null}]}\n\n",
+			": keep-alive\n\n",
+			"data: {\"error\":{\"message\":\"Out of memory\"}}\n\n"
+		)
+	)
+	.await;
+	let stream = openai(&url).stream_completion(request()).await.unwrap();
+	let results = stream.collect::<Vec<_>>().await;
+	assert_eq!(
+		results,
+		vec![
+			Ok(CompletionChunk::Content("Once".into())),
+			Err(BackendError::Backend("Out of memory".into())),
+		]
+	);
+}
@@ -0,0 +1,112 @@
+#![cfg(feature = "ssr")]
+
+use chat_base::chat::{
+	CachedSummary, Message, Role, SummaryCache, ToolCall, fit_context
+};
+
+/// Create an assistant message that calls a tool.
+fn call(id: &str) -> Message
+{
+	Message {
+		tool_calls: vec![ToolCall {
+			id: id.into(),
+			name: "roll".into(),
+			arguments: r#"{"expression":"1d20"}"#.into()
+		}],
+		..Role::Assistant.message(String::new())
+	}
+}
+
+/// Create a tool message that answers the specified call.
+fn result(id: &str) -> Message
+{
+	Message {
+		tool_call: Some(call(id).tool_calls.remove(0)),
+		..Role::Tool.message("1d20: [17] = 17".into())
+	}
+}
+
+#[test]
+fn leaves_out_the_oldest_turns()
+{
+	let messages = vec![
+		Role::System.message("S".into()),
+		Role::User.message("U1".into()),
+		Role::Assistant.message("A1".into()),
+		Role::User.message("U2".into()),
+	];
+	let fitted = fit_context(&messages, &[10, 10, 10, 10], 30);
+	assert_eq!(fitted.excluded, vec![1]);
+	assert_eq!(fitted.tokens, 30);
+	assert_eq!(fitted.messages.len(), 3);
+	// The system message and the latest message stay, whatever the budget.
+	let fitted = fit_context(&messages, &[10, 10, 10, 10], 0);
+	assert_eq!(fitted.excluded, vec![1, 2]);
+	assert_eq!(fitted.tokens, 20);
+}
+
+#[test]
+fn leaves_out_tool_results_with_their_calls()
+{
+	let messages = vec![
+		Role::System.message("S".into()),
+		Role::User.message("U1".into()),
+		call("a"),
+		result("a"),
+		Role::Assistant.message("A1".into()),
+		Role::User.message("U2".into()),
+	];
+	let fitted = fit_context(&messages, &[10; 6], 30);
+	assert_eq!(fitted.excluded, vec![1, 2, 3]);
+	assert!(fitted.messages.iter().all(|m| m.role != Role::Tool));
+}
+
+#[test]
+fn keeps_the_call_of_a_pinned_tool_result()
+{
+	let messages = vec![
+		Role::System.message("S".into()),
+		Role::User.message("U1".into()),
+		call("a"),
+		result("a"),
+		result("a"),
+	];
+	let fitted = fit_context(&messages, &[10; 5], 20);
+	// The latest message is a tool result, so its call and the other result
+	// stay too, even though they exceed the budget.
+	assert_eq!(fitted.excluded, vec![1]);
+	assert_eq!(fitted.tokens, 40);
+	assert_eq!(fitted.messages[1], call("a"));
+}
+
+#[test]
+fn finds_the_longest_summarized_prefix()
+{
+	let messages = [
+		Role::User.message("U1".into()),
+		Role::Assistant.message("A1".into()),
+		Role::User.message("U2".into()),
+		Role::Assistant.message("A2".into())
+	];
+	let messages = messages.iter().collect::<Vec<_>>();
+	let cache = SummaryCache::new();
+	assert_eq!(cache.longest(&messages), None);
+	cache.insert(&messages[..1], "one".into());
+	cache.insert(&messages[..2], "two".into());
+	assert_eq!(
+		cache.longest(&messages),
+		Some(CachedSummary {
+			covered: 2,
+			summary: "two".into()
+		})
+	);
+	assert_eq!(cache.longest(&messages[..1]).unwrap().summary, "one");
+	// Editing a summarized message invalidates the summaries that cover it.
+	let edited = Role::Assistant.message("B1".into());
+	let mut changed = messages.clone();
+	changed[1] = &edited;
+	assert_eq!(cache.longest(&changed).unwrap().covered, 1);
+	let user = Role::User.message("A1".into());
+	changed[1] = &user;
+	assert_eq!(cache.longest(&changed).unwrap().covered, 1);
+}
@@ -0,0 +1,226 @@
+#![cfg(feature = "ssr")]
+
+use chat_base::chat::{
+	DiceError, DiceExpression, DiceRoll, RandomTable, RollTool, TableEntry,
+	Tool
+};
+use rand::{SeedableRng, rngs::mock::StepRng};
+use rand_chacha::ChaCha8Rng;
+use serde_json::json;
+
+/// Parse the specified dice expression.
+fn parse(expression: &str) -> Result<DiceExpression, DiceError>
+{
+	expression.parse::<DiceExpression>()
+}
+
+/// Roll the specified dice expression with the specified seed.
+fn roll(expression: &str, seed: u64) -> DiceRoll
+{
+	parse(expression)
+		.unwrap()
+		.roll(&mut ChaCha8Rng::seed_from_u64(seed))
+}
+
+/// Get the faces of the dice in the specified parts of a roll, in order, with
+/// the faces of dropped dice negated.
+fn faces(parts: &str) -> Vec<i64>
+{
+	parts
+		.trim_matches(['[', ']'])
+		.split(", ")
+		.map(|face| match face.strip_prefix("dropped ")
+		{
+			Some(face) => -face.parse::<i64>().unwrap(),
+			None => face.parse().unwrap()
+		})
+		.collect()
+}
+
+#[test]
+fn rejects_malformed_expressions()
+{
+	let cases = [
+		("", DiceError::Empty),
+		("   ", DiceError::Empty),
+		("3x6", DiceError::Unexpected('x')),
+		("3d6*2", DiceError::Unexpected('*')),
+		("d", DiceError::MissingNumber),
+		("2d", DiceError::MissingNumber),
+		("3d6+", DiceError::MissingNumber),
+		("2d20k", DiceError::MissingNumber),
+		("2d20kx1", DiceError::Unexpected('x')),
+		("1d0", DiceError::InvalidSides),
+		("1d1!", DiceError::InvalidExplode),
+		("2d20kh3", DiceError::InvalidKeep(3, 2)),
+		("2d20kl0", DiceError::InvalidKeep(0, 2))
+	];
+	for (expression, error) in cases
+	{
+		assert_eq!(parse(expression), Err(error), "{expression:?}");
+	}
+}
+
+#[test]
+fn enforces_limits()
+{
+	let cases = [
+		("0d6", DiceError::TooManyDice),
+		("101d6", DiceError::TooManyDice),
+		// The limit covers the whole expression, not just each term.
+		("60d6+41d6", DiceError::TooManyDice),
+		("1d1001", DiceError::InvalidSides),
+		// Huge numbers saturate rather than wrap around to something small.
+		("4294967297d6", DiceError::TooManyDice),
+		("1d4294967297", DiceError::InvalidSides),
+		("99999999999999999999d6", DiceError::TooManyDice)
+	];
+	for (expression, error) in cases
+	{
+		assert_eq!(parse(expression), Err(error), "{expression:?}");
+	}
+	assert!(parse("100d6").is_ok());
+	assert!(parse("60d6+40d6").is_ok());
+	assert!(parse("1d1000").is_ok());
+}
+
+#[test]
+fn normalizes_expressions()
+{
+	assert_eq!(parse(" 3D6 + 2 ").unwrap().to_string(), "3d6+2");
+	assert_eq!(roll("-1d4+10", 7).expression, "-1d4+10");
+	let roll = roll("2d6-1", 7);
+	let (dice, constant) = roll.parts.split_once(" - ").unwrap();
+	assert_eq!(constant, "1");
+	assert_eq!(roll.total, faces(dice).iter().sum::<i64>() - 1);
+}
+
+#[test]
+fn rolls_percentile_dice()
+{
+	for seed in 0..100
+	{
+		let percentile = roll("d%", seed);
+		assert_eq!(percentile.total, roll("1d100", seed).total);
+		assert!((1..=100).contains(&percentile.total));
+	}
+}
+
+#[test]
+fn keeps_highest_or_lowest_dice()
+{
+	for seed in 0..50
+	{
+		// Keeping uses the same dice as rolling them all.
+		let all = faces(&roll("2d20", seed).parts);
+		let highest = roll("2d20kh1", seed);
+		let lowest = roll("2d20kl", seed);
+		assert_eq!(highest.total, *all.iter().max().unwrap());
+		assert_eq!(lowest.total, *all.iter().min().unwrap());
+		let highest = faces(&highest.parts);
+		assert_eq!(highest.iter().filter(|face| **face < 0).count(), 1);
+		assert_eq!(
+			highest.iter().map(|face| face.abs()).collect::<Vec<_>>(),
+			all
+		);
+	}
+	let roll = roll("4d6kh3", 3);
+	assert_eq!(
+		faces(&roll.parts)
+			.iter()
+			.filter(|face| **face > 0)
+			.sum::<i64>(),
+		roll.total
+	);
+}
+
+#[test]
+fn explodes_dice_on_their_highest_face()
+{
+	let mut exploded = 0;
+	for seed in 0..200
+	{
+		let roll = roll("3d4!", seed);
+		// Every die shows each of its rolls, and every roll but the last
+		// shows the highest face.
+		let mut total = 0;
+		for die in roll.parts.trim_matches(['[', ']']).split(", ")
+		{
+			let rolls = die.split('+').collect::<Vec<_>>();
+			for exploding in &rolls[..rolls.len() - 1]
+			{
+				assert_eq!(*exploding, "4!");
+			}
+			total += rolls
+				.iter()
+				.map(|roll| roll.trim_end_matches('!').parse::<i64>().unwrap())
+				.sum::<i64>();
+			exploded += rolls.len() - 1;
+		}
+		assert_eq!(roll.total, total);
+	}
+	assert!(exploded > 0);
+}
+
+#[test]
+fn limits_explosions()
+{
+	// A generator that always rolls a six: the sampler maps this value to the
+	// highest of six faces.
+	let mut rng = StepRng::new(0xd555_5556, 0);
+	let roll = parse("1d6!").unwrap().roll(&mut rng);
+	// The first roll and twenty more, the last of which would explode again.
+	assert_eq!(roll.total, 21 * 6);
+	assert_eq!(roll.parts, format!("[{}]", vec!["6!"; 21].join("+")));
+}
+
+#[test]
+fn reproduces_rolls_from_seeds()
+{
+	for expression in ["3d6+2", "2d20kh1", "4d6!", "d%-1d8+1d6-1"]
+	{
+		assert_eq!(roll(expression, 42), roll(expression, 42));
+		let rolls = (0..20)
+			.map(|seed| roll(expression, seed).parts)
+			.collect::<std::collections::BTreeSet<_>>();
+		assert!(rolls.len() > 1, "{expression}");
+	}
+	let tool = RollTool;
+	let arguments = json!({ "expression": "3d6", "seed": 1234 });
+	let result = tool.call(arguments.clone()).unwrap();
+	assert_eq!(tool.call(arguments).unwrap(), result);
+	assert!(result.ends_with("(seed 1234)"), "{result}");
+}
+
+#[test]
+fn rolls_on_weighted_tables()
+{
+	let entry = |weight, result: &str| TableEntry {
+		weight,
+		result: result.into()
+	};
+	let table = RandomTable {
+		id: "weather".into(),
+		name: None,
+		description: None,
+		entries: vec![entry(3, "Rain"), entry(0, "Never"), entry(1, "Snow")]
+	};
+	assert!(table.validate().is_ok());
+	let results = (0..200)
+		.map(|seed| {
+			let first = table.roll(&mut ChaCha8Rng::seed_from_u64(seed));
+			let second = table.roll(&mut ChaCha8Rng::seed_from_u64(seed));
+			assert_eq!(first, second);
+			first.unwrap().result.clone()
+		})
+		.collect::<Vec<_>>();
+	assert!(results.iter().all(|result| result != "Never"));
+	assert!(results.iter().any(|result| result == "Rain"));
+	assert!(results.iter().any(|result| result == "Snow"));
+	let empty = RandomTable {
+		entries: vec![entry(0, "Never")],
+		..table
+	};
+	assert!(empty.validate().is_err());
+	assert_eq!(empty.roll(&mut ChaCha8Rng::seed_from_u64(0)), None);
+}
@@ -0,0 +1,203 @@
+use chat_base::chat::{MessageTree, Role};
+use uuid::Uuid;
+
+/// Get the content of the messages along the active branch.
+fn contents(tree: &MessageTree) -> Vec<String>
+{
+	tree.active_branch()
+		.into_iter()
+		.map(|(_, message)| message.content)
+		.collect()
+}
+
+/// Create a tree with a system message, a user message, and an assistant
+/// message.
+///
+/// # Returns
+///
+/// The tree and the identifiers of its messages, in order.
+fn conversation() -> (MessageTree, [Uuid; 3])
+{
+	let mut tree = MessageTree::with_root(Role::System.message("S".into()));
+	let system = tree.tip().unwrap();
+	let user = tree.append(Role::User.message("U".into()));
+	let assistant = tree.append(Role::Assistant.message("A".into()));
+	(tree, [system, user, assistant])
+}
+
+#[test]
+fn appends_along_the_active_branch()
+{
+	let (tree, [system, user, assistant]) = conversation();
+	assert_eq!(contents(&tree), vec!["S", "U", "A"]);
+	let ids = tree
+		.active_branch()
+		.into_iter()
+		.map(|(id, _)| id)
+		.collect::<Vec<_>>();
+	assert_eq!(ids, vec![system, user, assistant]);
+	assert_eq!(tree.tip(), Some(assistant));
+	assert_eq!(tree.siblings(assistant), (0, 1));
+}
+
+#[test]
+fn switches_between_alternatives()
+{
+	let (mut tree, [.., first]) = conversation();
+	let second = tree
+		.add_sibling(first, Role::Assistant.message("B".into()))
+		.unwrap();
+	assert_eq!(contents(&tree), vec!["S", "U", "B"]);
+	assert_eq!(tree.siblings(second), (1, 2));
+	tree.switch(second, -1);
+	assert_eq!(contents(&tree), vec!["S", "U", "A"]);
+	// Offsets are clamped to the available alternatives.
+	tree.switch(first, 5);
+	assert_eq!(contents(&tree), vec!["S", "U", "B"]);
+	tree.switch(second, -5);
+	assert_eq!(contents(&tree), vec!["S", "U", "A"]);
+}
+
+#[test]
+fn rewinds_and_resumes()
+{
+	let (mut tree, [_, user, assistant]) = conversation();
+	tree.rewind(user);
+	assert_eq!(contents(&tree), vec!["S", "U"]);
+	assert!(tree.can_resume());
+	tree.resume();
+	assert_eq!(contents(&tree), vec!["S", "U", "A"]);
+	assert!(!tree.can_resume());
+	// Appending after a rewind starts a new alternative.
+	tree.rewind(user);
+	let retry = tree.append(Role::Assistant.message("B".into()));
+	assert_eq!(contents(&tree), vec!["S", "U", "B"]);
+	assert_eq!(tree.siblings(retry), (1, 2));
+	assert!(!tree.can_resume());
+	tree.switch(retry, -1);
+	assert_eq!(tree.tip(), Some(assistant));
+}
+
+#[test]
+fn rewinds_before_a_message()
+{
+	let (mut tree, [_, user, _]) = conversation();
+	tree.rewind_before(user);
+	assert_eq!(contents(&tree), vec!["S"]);
+	let edit = tree.append(Role::User.message("V".into()));
+	assert_eq!(contents(&tree), vec!["S", "V"]);
+	assert_eq!(tree.siblings(edit), (1, 2));
+	tree.switch(edit, -1);
+	assert_eq!(contents(&tree), vec!["S", "U", "A"]);
+}
+
+#[test]
+fn removes_messages_keeping_their_continuations()
+{
+	let (mut tree, [_, user, first]) = conversation();
+	let second = tree
+		.add_sibling(first, Role::Assistant.message("B".into()))
+		.unwrap();
+	// The removed message's continuations take its place, and the active one
+	// stays active.
+	tree.remove(user);
+	assert_eq!(contents(&tree), vec!["S", "B"]);
+	assert_eq!(tree.siblings(first), (0, 2));
+	assert_eq!(tree.siblings(second), (1, 2));
+	assert!(tree.get(user).is_none());
+}
+
+#[test]
+fn removes_alternatives_keeping_the_selection()
+{
+	let (mut tree, [.., first]) = conversation();
+	let second = tree
+		.add_sibling(first, Role::Assistant.message("B".into()))
+		.unwrap();
+	let third = tree
+		.add_sibling(first, Role::Assistant.message("C".into()))
+		.unwrap();
+	// Give the first alternative a continuation, and then select the third.
+	tree.switch(third, -2);
+	let follow_up = tree.append(Role::User.message("F".into()));
+	tree.switch(first, 2);
+	assert_eq!(contents(&tree), vec!["S", "U", "C"]);
+	// Removing an earlier alternative shifts the selection along with the
+	// selected alternative.
+	tree.remove(first);
+	assert_eq!(contents(&tree), vec!["S", "U", "C"]);
+	assert_eq!(tree.siblings(follow_up), (0, 3));
+	assert_eq!(tree.siblings(third), (2, 3));
+	// Removing the selected alternative falls back to a neighbor.
+	tree.remove(third);
+	assert_eq!(contents(&tree), vec!["S", "U", "B"]);
+	tree.remove(second);
+	assert_eq!(contents(&tree), vec!["S", "U", "F"]);
+	tree.remove(follow_up);
+	assert_eq!(contents(&tree), vec!["S", "U"]);
+}
+
+#[test]
+fn replaces_messages_in_place()
+{
+	let (mut tree, [_, user, first]) = conversation();
+	let second = tree
+		.add_sibling(first, Role::Assistant.message("B".into()))
+		.unwrap();
+	let replaced = tree
+		.replace(first, Role::Assistant.message("A2".into()))
+		.unwrap();
+	assert_ne!(replaced, first);
+	assert_eq!(tree.siblings(replaced), (0, 2));
+	assert_eq!(contents(&tree), vec!["S", "U", "B"]);
+	let edited = tree.replace(user, Role::User.message("V".into())).unwrap();
+	assert_eq!(contents(&tree), vec!["S", "V", "B"]);
+	tree.switch(second, -1);
+	assert_eq!(contents(&tree), vec!["S", "V", "A2"]);
+	assert_eq!(tree.siblings(edited), (0, 1));
+	assert!(
+		tree.replace(first, Role::User.message("X".into()))
+			.is_none()
+	);
+}
+
+#[test]
+fn sets_the_system_message()
+{
+	let mut tree = MessageTree::with_root(Role::User.message("U".into()));
+	tree.append(Role::Assistant.message("A".into()));
+	let system = tree.set_system(Role::System.message("S".into()));
+	assert_eq!(contents(&tree), vec!["S", "U", "A"]);
+	let replaced = tree.set_system(Role::System.message("T".into()));
+	assert_ne!(replaced, system);
+	assert_eq!(contents(&tree), vec!["T", "U", "A"]);
+}
+
+#[test]
+fn tolerates_dangling_identifiers()
+{
+	let (tree, [system, user, assistant]) = conversation();
+	// Damage the stored tree by dropping the user message from its nodes.
+	let mut json = serde_json::to_value(&tree).unwrap();
+	json["nodes"]
+		.as_object_mut()
+		.unwrap()
+		.remove(&user.to_string())
+		.unwrap();
+	let mut tree = serde_json::from_value::<MessageTree>(json).unwrap();
+	// The active branch ends at the dangling identifier.
+	assert_eq!(contents(&tree), vec!["S"]);
+	assert_eq!(tree.tip(), Some(system));
+	// Nothing panics on the orphaned assistant message.
+	assert_eq!(tree.siblings(assistant), (0, 1));
+	tree.switch(assistant, 1);
+	tree.rewind_before(assistant);
+	assert!(
+		tree.add_sibling(assistant, Role::User.message("X".into()))
+			.is_none()
+	);
+	tree.remove(assistant);
+	// New messages still attach to the tip.
+	tree.append(Role::User.message("V".into()));
+	assert_eq!(contents(&tree), vec!["S", "V"]);
+}
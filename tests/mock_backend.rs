@@ -0,0 +1,227 @@
+#![cfg(feature = "ssr")]
+
+use chat_base::chat::{
+	BackendError, ChatBackend, CompletionChunk, CompletionRequest,
+	FinishReason, GenerationParams, MockBackend, MockError, MockErrorKind,
+	MockResponse, MockScript, MockToolCall, Role
+};
+use futures::StreamExt;
+
+/// Create a completion request for the specified user message.
+fn request(content: &str) -> CompletionRequest
+{
+	CompletionRequest {
+		params: GenerationParams::default(),
+		messages: vec![
+			Role::System.message("You are a mock.".into()),
+			Role::User.message(content.into()),
+		],
+		json_mode: false,
+		tools: vec![]
+	}
+}
+
+/// Stream a completion from the specified backend, collecting every chunk.
+async fn collect(
+	backend: &MockBackend,
+	content: &str
+) -> Result<Vec<Result<CompletionChunk, BackendError>>, BackendError>
+{
+	let stream = backend.stream_completion(request(content)).await?;
+	Ok(stream.collect().await)
+}
+
+/// Get the content fragments among the specified chunks.
+fn fragments(chunks: &[Result<CompletionChunk, BackendError>]) -> Vec<String>
+{
+	chunks
+		.iter()
+		.filter_map(|chunk| match chunk
+		{
+			Ok(CompletionChunk::Content(fragment)) => Some(fragment.clone()),
+			_ => None
+		})
+		.collect()
+}
+
+#[tokio::test]
+async fn echoes_latest_user_message()
+{
+	let backend = MockBackend::echo();
+	let chunks = collect(&backend, "Hello, mock!").await.unwrap();
+	assert_eq!(fragments(&chunks), vec!["Hello, mock!"]);
+	assert_eq!(backend.requests().len(), 1);
+	assert_eq!(backend.requests()[0].messages.len(), 2);
+}
+
+#[tokio::test]
+async fn streams_content_in_chunks()
+{
+	let backend = MockBackend::new(MockScript {
+		chunk_size: 4,
+		responses: vec![MockResponse {
+			content: "The goblin flees.".into(),
+			..Default::default()
+		}],
+		..Default::default()
+	});
+	let chunks = collect(&backend, "Attack!").await.unwrap();
+	assert_eq!(
+		fragments(&chunks),
+		vec!["The ", "gobl", "in f", "lees", "."]
+	);
+}
+
+#[tokio::test]
+async fn cycles_through_scripted_responses()
+{
+	let backend = MockBackend::new(MockScript {
+		responses: vec![
+			MockResponse {
+				content: "First".into(),
+				..Default::default()
+			},
+			MockResponse {
+				echo: true,
+				..Default::default()
+			},
+		],
+		..Default::default()
+	});
+	let mut contents = vec![];
+	for message in ["one", "two", "three"]
+	{
+		let chunks = collect(&backend, message).await.unwrap();
+		contents.push(fragments(&chunks).concat());
+	}
+	assert_eq!(contents, vec!["First", "two", "First"]);
+}
+
+#[tokio::test]
+async fn calls_tools_after_content()
+{
+	let backend = MockBackend::new(MockScript {
+		responses: vec![MockResponse {
+			content: "Rolling.".into(),
+			tool_calls: vec![MockToolCall {
+				name: "roll".into(),
+				arguments: r#"{"expression":"1d20"}"#.into()
+			}],
+			..Default::default()
+		}],
+		..Default::default()
+	});
+	let chunks = collect(&backend, "Roll initiative").await.unwrap();
+	assert_eq!(chunks.len(), 2);
+	assert_eq!(
+		chunks[1].as_ref().unwrap(),
+		&CompletionChunk::ToolCall {
+			index: 0,
+			id: Some("call_0".into()),
+			name: Some("roll".into()),
+			arguments: r#"{"expression":"1d20"}"#.into()
+		}
+	);
+}
+
+#[tokio::test]
+async fn injects_errors_after_fragments()
+{
+	let backend = MockBackend::new(MockScript {
+		chunk_size: 1,
+		responses: vec![MockResponse {
+			content: "abcdef".into(),
+			error: Some(MockError {
+				kind: MockErrorKind::Response,
+				after: 2,
+				message: "garbled".into()
+			}),
+			..Default::default()
+		}],
+		..Default::default()
+	});
+	let chunks = collect(&backend, "Hi").await.unwrap();
+	assert_eq!(fragments(&chunks), vec!["a", "b"]);
+	assert!(matches!(
+		chunks.last(),
+		Some(Err(BackendError::Response(message))) if message == "garbled"
+	));
+}
+
+#[tokio::test]
+async fn fails_before_tool_calls_and_finish()
+{
+	let backend = MockBackend::new(MockScript {
+		chunk_size: 2,
+		responses: vec![MockResponse {
+			content: "abcd".into(),
+			tool_calls: vec![MockToolCall {
+				name: "roll".into(),
+				arguments: "{}".into()
+			}],
+			finish_reason: Some(FinishReason::ToolCalls),
+			error: Some(MockError {
+				kind: MockErrorKind::Backend,
+				after: 2,
+				message: "crashed".into()
+			}),
+			..Default::default()
+		}],
+		..Default::default()
+	});
+	let chunks = collect(&backend, "Hi").await.unwrap();
+	// The error follows the last content fragment directly.
+	assert_eq!(chunks.len(), 3);
+	assert_eq!(fragments(&chunks), vec!["ab", "cd"]);
+	assert_eq!(
+		chunks.last(),
+		Some(&Err(BackendError::Backend("crashed".into())))
+	);
+}
+
+#[tokio::test]
+async fn fails_to_start_when_unavailable()
+{
+	let backend = MockBackend::new(MockScript {
+		responses: vec![MockResponse {
+			error: Some(MockError {
+				kind: MockErrorKind::Unavailable,
+				..Default::default()
+			}),
+			..Default::default()
+		}],
+		..Default::default()
+	});
+	assert!(matches!(
+		collect(&backend, "Hi").await,
+		Err(BackendError::Unavailable(_))
+	));
+}
+
+#[tokio::test]
+async fn reads_script_from_toml()
+{
+	let script = toml::from_str::<MockScript>(
+		r#"
+		chunk_size = 3
+		delay_ms = 1
+		models = ["tiny", "huge"]
+
+		[[responses]]
+		content = "Hi!"
+
+		[[responses]]
+		error = { kind = "unavailable", message = "down" }
+		"#
+	)
+	.unwrap();
+	assert_eq!(script.chunk_size, 3);
+	assert_eq!(
+		script.responses[1].error.as_ref().unwrap().kind,
+		MockErrorKind::Unavailable
+	);
+	let backend = MockBackend::new(script);
+	assert_eq!(backend.list_models().await.unwrap(), vec!["huge", "tiny"]);
+	let chunks = collect(&backend, "Hello").await.unwrap();
+	assert_eq!(fragments(&chunks), vec!["Hi!"]);
+}
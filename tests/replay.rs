@@ -0,0 +1,267 @@
+#![cfg(feature = "ssr")]
+
+use async_openai::{Client, config::OpenAIConfig};
+use axum::{
+	body::{Body, Bytes},
+	http::{StatusCode, header::CONTENT_TYPE}
+};
+use chat_base::chat::{
+	BackendError, BackendKind, ChatBackend, CompletionAssembler,
+	CompletionChunk, Exchange, FinishReason, OllamaBackend, OpenAiBackend,
+	Recorder, ReplayBackend, ToolCall, Usage
+};
+use futures::{StreamExt, stream};
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+/// Get the path to the specified fixture.
+fn fixture(name: &str) -> PathBuf
+{
+	PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+		.join("tests/fixtures")
+		.join(format!("{name}.json"))
+}
+
+/// Read the exchange in the specified fixture.
+fn exchange(name: &str) -> Exchange { Exchange::read(&fixture(name)).unwrap() }
+
+/// Serve the response of the specified exchange to every request on an
+/// ephemeral port. The body goes out in small pieces that do not align with
+/// its lines.
+///
+/// # Returns
+///
+/// The base URL of the server.
+async fn serve(exchange: &Exchange) -> String
+{
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let addr = listener.local_addr().unwrap();
+	let status = StatusCode::from_u16(exchange.status).unwrap();
+	let content_type = match exchange.kind
+	{
+		BackendKind::Ollama => "application/x-ndjson",
+		_ => "text/event-stream"
+	};
+	let body = exchange
+		.lines
+		.iter()
+		.map(|line| line.text.as_str())
+		.collect::<String>()
+		.into_bytes();
+	let app = axum::Router::new().fallback(move || {
+		let pieces = body
+			.chunks(7)
+			.map(|piece| Ok::<_, std::io::Error>(Bytes::copy_from_slice(piece)))
+			.collect::<Vec<_>>();
+		std::future::ready((
+			status,
+			[(CONTENT_TYPE, content_type)],
+			Body::from_stream(stream::iter(pieces))
+		))
+	});
+	tokio::spawn(async move {
+		axum::serve(listener, app.into_make_service())
+			.await
+			.unwrap();
+	});
+	format!("http://{addr}")
+}
+
+/// Create the live backend of the specified kind for the server at the
+/// specified base URL.
+fn live(kind: BackendKind, url: &str) -> Box<dyn ChatBackend>
+{
+	match kind
+	{
+		BackendKind::OpenAi =>
+		{
+			Box::new(OpenAiBackend::new(Client::with_config(
+				OpenAIConfig::new().with_api_base(format!("{url}/v1"))
+			)))
+		},
+		BackendKind::Ollama => Box::new(OllamaBackend::new(url)),
+		kind => panic!("No live backend of kind {kind:?}")
+	}
+}
+
+/// Ask the live backend of the fixture's kind for a completion, with the
+/// fixture's response served to it.
+///
+/// # Returns
+///
+/// Every piece of the response, or the error that kept it from starting.
+async fn stream_live(
+	name: &str
+) -> Result<Vec<Result<CompletionChunk, BackendError>>, BackendError>
+{
+	let exchange = exchange(name);
+	let url = serve(&exchange).await;
+	let stream = live(exchange.kind, &url)
+		.stream_completion(exchange.request)
+		.await?;
+	Ok(stream.collect().await)
+}
+
+#[tokio::test]
+async fn assembles_tool_call_arguments_across_fragments()
+{
+	let chunks = stream_live("openai_tool_call").await.unwrap();
+	let stream = stream::iter(chunks).boxed();
+	let completion = CompletionAssembler::collect(stream).await.unwrap();
+	assert_eq!(completion.message.content, "");
+	assert_eq!(
+		completion.message.tool_calls,
+		vec![ToolCall {
+			id: "call_8f2kq1".into(),
+			name: "roll".into(),
+			arguments: r#"{"expression":"1d20+3"}"#.into()
+		}]
+	);
+	assert_eq!(completion.finish_reason, Some(FinishReason::ToolCalls));
+	assert_eq!(completion.usage, None);
+}
+
+#[tokio::test]
+async fn reports_usage_and_cut_off_responses()
+{
+	let chunks = stream_live("ollama_length").await.unwrap();
+	let stream = stream::iter(chunks).boxed();
+	let completion = CompletionAssembler::collect(stream).await.unwrap();
+	assert_eq!(
+		completion.message.content,
+		"The goblin staggers back, clutching its side and snarling at the"
+	);
+	assert!(completion.message.tool_calls.is_empty());
+	assert_eq!(completion.finish_reason, Some(FinishReason::Length));
+	assert_eq!(
+		completion.usage,
+		Some(Usage {
+			prompt_tokens: 48,
+			completion_tokens: 12,
+			exact: true
+		})
+	);
+}
+
+#[tokio::test]
+async fn maps_errors_mid_stream()
+{
+	let chunks = stream_live("openai_stream_error").await.unwrap();
+	assert_eq!(
+		chunks,
+		vec![
+			Ok(CompletionChunk::Content("Once".into())),
+			Ok(CompletionChunk::Content(" upon".into())),
+			Err(BackendError::Backend("CUDA error: out of memory".into())),
+		]
+	);
+}
+
+#[tokio::test]
+async fn maps_error_statuses()
+{
+	let error = stream_live("openai_overloaded").await.unwrap_err();
+	assert_eq!(
+		error,
+		BackendError::Backend(
+			"503 Service Unavailable: The server is overloaded. Try again \
+			 later."
+				.into()
+		)
+	);
+}
+
+#[tokio::test]
+async fn replays_like_the_live_backends()
+{
+	for name in [
+		"openai_tool_call",
+		"openai_stream_error",
+		"openai_overloaded",
+		"ollama_length"
+	]
+	{
+		let live = stream_live(name).await;
+		let replay = ReplayBackend::read(fixture(name)).unwrap();
+		let replayed =
+			match replay.stream_completion(exchange(name).request).await
+			{
+				Ok(stream) => Ok(stream.collect::<Vec<_>>().await),
+				Err(e) => Err(e)
+			};
+		assert_eq!(replayed, live, "{name}");
+	}
+}
+
+#[tokio::test]
+async fn forwards_only_content_fragments()
+{
+	let backend = ReplayBackend::read(fixture("ollama_length")).unwrap();
+	let mut stream = backend
+		.stream_completion(exchange("ollama_length").request)
+		.await
+		.unwrap();
+	let mut assembler = CompletionAssembler::default();
+	let mut fragments = vec![];
+	while let Some(chunk) = stream.next().await
+	{
+		fragments.extend(assembler.push(chunk.unwrap()));
+	}
+	assert_eq!(fragments.len(), 12);
+	assert_eq!(fragments.concat(), assembler.finish().message.content);
+}
+
+#[tokio::test]
+async fn replays_directories_in_order()
+{
+	let backend =
+		ReplayBackend::read(fixture("openai_tool_call").parent().unwrap())
+			.unwrap();
+	let request = exchange("ollama_length").request;
+	// The fixtures sort by name, so the Ollama exchange comes first.
+	let stream = backend.stream_completion(request.clone()).await.unwrap();
+	let completion = CompletionAssembler::collect(stream).await.unwrap();
+	assert_eq!(completion.finish_reason, Some(FinishReason::Length));
+	assert_eq!(
+		backend.list_models().await.unwrap(),
+		vec!["llama3.1:8b", "qwen2.5-7b-instruct"]
+	);
+}
+
+#[tokio::test]
+async fn records_exchanges_for_replay()
+{
+	let dir =
+		std::env::temp_dir().join(format!("chat-base-{}", Uuid::new_v4()));
+	let fixture = exchange("openai_tool_call");
+	let url = serve(&fixture).await;
+	let backend = OpenAiBackend::new(Client::with_config(
+		OpenAIConfig::new().with_api_base(format!("{url}/v1"))
+	))
+	.with_recorder(Recorder::new(&dir));
+	let stream = backend
+		.stream_completion(fixture.request.clone())
+		.await
+		.unwrap();
+	let original = stream.collect::<Vec<_>>().await;
+	let exchanges = Exchange::read_all(&dir).unwrap();
+	std::fs::remove_dir_all(&dir).unwrap();
+	// The recording holds the body as it came over the wire.
+	assert_eq!(exchanges.len(), 1);
+	let recorded = &exchanges[0];
+	assert_eq!(recorded.kind, BackendKind::OpenAi);
+	assert_eq!(recorded.request, fixture.request);
+	assert_eq!(recorded.status, 200);
+	let texts = |exchange: &Exchange| {
+		exchange
+			.lines
+			.iter()
+			.map(|line| line.text.clone())
+			.collect::<Vec<_>>()
+	};
+	assert_eq!(texts(recorded), texts(&fixture));
+	let replay = ReplayBackend::new(exchanges);
+	let stream = replay.stream_completion(fixture.request).await.unwrap();
+	assert_eq!(stream.collect::<Vec<_>>().await, original);
+}
@@ -0,0 +1,480 @@
+#![cfg(feature = "ssr")]
+
+use async_openai::{Client, config::OpenAIConfig};
+use chat_base::{
+	chat::{
+		AppMessage, BackendRouter, BusyPolicy, ChatBackend, ChatRequest,
+		MockBackend, MockError, MockErrorKind, MockResponse, MockScript,
+		OpenAiBackend, ReplayBackend, Role, RoutedBackend, SessionConfig,
+		Usage
+	},
+	error_template::AppError,
+	router
+};
+use futures::{SinkExt, StreamExt};
+use leptos::prelude::LeptosOptions;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+	MaybeTlsStream, WebSocketStream, connect_async,
+	tungstenite::Message as WebSocketMessage
+};
+use uuid::Uuid;
+
+/// A websocket connection to a server under test.
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serve the application on an ephemeral port, and connect to its chat
+/// websocket.
+async fn connect(config: SessionConfig) -> Socket
+{
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let addr = listener.local_addr().unwrap();
+	let app = router(LeptosOptions::default(), config);
+	tokio::spawn(async move {
+		axum::serve(listener, app.into_make_service())
+			.await
+			.unwrap();
+	});
+	let (socket, _) = connect_async(format!("ws://{addr}/api/chat"))
+		.await
+		.unwrap();
+	socket
+}
+
+/// Get a session configuration whose only backend follows the specified
+/// script.
+fn scripted(script: MockScript) -> SessionConfig
+{
+	mocks(vec![("mock", script)])
+}
+
+/// Get a session configuration whose backends follow the specified scripts,
+/// in order of preference.
+fn mocks(scripts: Vec<(&str, MockScript)>) -> SessionConfig
+{
+	backends(
+		scripts
+			.into_iter()
+			.map(|(name, script)| {
+				(name, Arc::new(MockBackend::new(script)) as Arc<_>)
+			})
+			.collect()
+	)
+}
+
+/// Get a session configuration with the specified backends, in order of
+/// preference.
+fn backends(backends: Vec<(&str, Arc<dyn ChatBackend>)>) -> SessionConfig
+{
+	let backends = backends
+		.into_iter()
+		.map(|(name, backend)| RoutedBackend {
+			name: name.into(),
+			model: None,
+			backend
+		})
+		.collect();
+	SessionConfig::new(Arc::new(BackendRouter::new(backends)))
+}
+
+/// Get a backend that replays the specified fixture.
+fn replay(name: &str) -> Arc<dyn ChatBackend>
+{
+	let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+		.join("tests/fixtures")
+		.join(format!("{name}.json"));
+	Arc::new(ReplayBackend::read(path).unwrap())
+}
+
+/// Get an OpenAI backend for a port that nothing listens on.
+async fn unreachable() -> Arc<dyn ChatBackend>
+{
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let addr = listener.local_addr().unwrap();
+	Arc::new(OpenAiBackend::new(Client::with_config(
+		OpenAIConfig::new().with_api_base(format!("http://{addr}/v1"))
+	)))
+}
+
+/// Get a script with a single response.
+fn respond(
+	response: MockResponse,
+	chunk_size: usize,
+	delay_ms: u64
+) -> MockScript
+{
+	MockScript {
+		chunk_size,
+		delay_ms,
+		responses: vec![response],
+		..Default::default()
+	}
+}
+
+/// Get a request to start a chat with the specified user message.
+fn start(id: Uuid, content: &str) -> AppMessage
+{
+	AppMessage::StartChat(ChatRequest {
+		id,
+		messages: vec![Role::User.message(content.into())],
+		prompt: None,
+		params: Default::default(),
+		backend: None
+	})
+}
+
+/// Send the specified message, framed as the client frames it.
+async fn send(socket: &mut Socket, message: &AppMessage)
+{
+	let bytes = bincode::serialize(message).unwrap();
+	socket.send(WebSocketMessage::Binary(bytes)).await.unwrap();
+}
+
+/// Receive the next message from the server, skipping any notifications of
+/// changed system prompts.
+async fn receive(socket: &mut Socket) -> AppMessage
+{
+	loop
+	{
+		let frame = tokio::time::timeout(TIMEOUT, socket.next())
+			.await
+			.expect("Timed out waiting for the server")
+			.expect("The server closed the connection")
+			.unwrap();
+		let WebSocketMessage::Binary(bytes) = frame
+		else
+		{
+			panic!("Unexpected frame: {frame:?}")
+		};
+		match bincode::deserialize(&bytes).unwrap()
+		{
+			AppMessage::SystemPromptChanged(_) => continue,
+			message => return message
+		}
+	}
+}
+
+/// Receive messages from the server until the specified number of chats have
+/// concluded, whether by completion, cancellation, or error.
+async fn receive_until_concluded(
+	socket: &mut Socket,
+	chats: usize
+) -> Vec<AppMessage>
+{
+	let mut messages = vec![];
+	let mut concluded = 0;
+	while concluded < chats
+	{
+		let message = receive(socket).await;
+		if matches!(
+			message,
+			AppMessage::ChatCompleted(_)
+				| AppMessage::ChatCancelled(_)
+				| AppMessage::Error(..)
+		)
+		{
+			concluded += 1;
+		}
+		messages.push(message);
+	}
+	messages
+}
+
+/// Get the content fragments of the specified chat among the specified
+/// messages.
+fn fragments(messages: &[AppMessage], id: Uuid) -> Vec<String>
+{
+	messages
+		.iter()
+		.filter_map(|message| match message
+		{
+			AppMessage::NextChatFragment(chat, fragment) if *chat == id =>
+			{
+				Some(fragment.clone())
+			},
+			_ => None
+		})
+		.collect()
+}
+
+/// Get the position of the first of the specified messages that matches the
+/// predicate.
+fn position(
+	messages: &[AppMessage],
+	predicate: impl Fn(&AppMessage) -> bool
+) -> usize
+{
+	messages
+		.iter()
+		.position(predicate)
+		.unwrap_or_else(|| panic!("No such message: {messages:#?}"))
+}
+
+#[tokio::test]
+async fn streams_fragments_in_order()
+{
+	let content = MockResponse {
+		content: "The goblin flees.".into(),
+		..Default::default()
+	};
+	let mut socket = connect(scripted(respond(content, 4, 0))).await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Attack!")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(
+		fragments(&messages, id),
+		vec!["The ", "gobl", "in f", "lees", "."]
+	);
+	// The usage follows the last fragment, and the completion comes last.
+	let usage = position(&messages, |m| matches!(m, AppMessage::ChatUsage(..)));
+	let last = position(
+		&messages,
+		|m| matches!(m, AppMessage::NextChatFragment(_, f) if f == ".")
+	);
+	assert!(last < usage);
+	assert_eq!(messages.last(), Some(&AppMessage::ChatCompleted(id)));
+	assert!(messages.iter().all(|m| m.request_id() == Some(id)));
+}
+
+#[tokio::test]
+async fn rejects_chats_while_busy()
+{
+	let slow = MockResponse {
+		content: "Patience.".into(),
+		..Default::default()
+	};
+	let config =
+		scripted(respond(slow, 1, 20)).with_busy_policy(BusyPolicy::Reject);
+	let mut socket = connect(config.with_max_chats(1)).await;
+	let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+	send(&mut socket, &start(first, "One")).await;
+	send(&mut socket, &start(second, "Two")).await;
+	let messages = receive_until_concluded(&mut socket, 2).await;
+	// The second chat is turned away while the first is still streaming.
+	let rejected = position(&messages, |m| {
+		*m == AppMessage::Error(second, AppError::ChatBusy)
+	});
+	let completed =
+		position(&messages, |m| *m == AppMessage::ChatCompleted(first));
+	assert!(rejected < completed);
+	assert_eq!(fragments(&messages, first).concat(), "Patience.");
+	assert!(fragments(&messages, second).is_empty());
+}
+
+#[tokio::test]
+async fn queues_chats_while_busy()
+{
+	let slow = MockResponse {
+		echo: true,
+		..Default::default()
+	};
+	let config =
+		scripted(respond(slow, 1, 20)).with_busy_policy(BusyPolicy::Queue);
+	let mut socket = connect(config.with_max_chats(1)).await;
+	let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+	send(&mut socket, &start(first, "One")).await;
+	send(&mut socket, &start(second, "Two")).await;
+	let messages = receive_until_concluded(&mut socket, 2).await;
+	// The second chat waits its turn, and starts once the first completes.
+	let queued = position(&messages, |m| *m == AppMessage::ChatQueued(second));
+	let completed =
+		position(&messages, |m| *m == AppMessage::ChatCompleted(first));
+	let started = position(
+		&messages,
+		|m| matches!(m, AppMessage::NextChatFragment(id, _) if *id == second)
+	);
+	assert!(queued < completed);
+	assert!(completed < started);
+	assert_eq!(fragments(&messages, first).concat(), "One");
+	assert_eq!(fragments(&messages, second).concat(), "Two");
+	assert_eq!(messages.last(), Some(&AppMessage::ChatCompleted(second)));
+}
+
+#[tokio::test]
+async fn rejects_duplicate_request_identifiers()
+{
+	let slow = MockResponse {
+		content: "Once only.".into(),
+		..Default::default()
+	};
+	let config =
+		scripted(respond(slow, 1, 20)).with_busy_policy(BusyPolicy::Queue);
+	let mut socket = connect(config).await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Hello")).await;
+	send(&mut socket, &start(id, "Hello again")).await;
+	let messages = receive_until_concluded(&mut socket, 2).await;
+	assert!(messages.contains(&AppMessage::Error(id, AppError::ChatError)));
+	assert!(!messages.contains(&AppMessage::ChatQueued(id)));
+	assert_eq!(fragments(&messages, id).concat(), "Once only.");
+	assert_eq!(messages.last(), Some(&AppMessage::ChatCompleted(id)));
+}
+
+#[tokio::test]
+async fn ignores_malformed_frames()
+{
+	let mut socket = connect(scripted(MockScript::default())).await;
+	socket
+		.send(WebSocketMessage::Binary(vec![0xff; 7]))
+		.await
+		.unwrap();
+	socket
+		.send(WebSocketMessage::Text("StartChat".into()))
+		.await
+		.unwrap();
+	// A valid frame cut short is as malformed as garbage.
+	let mut truncated =
+		bincode::serialize(&start(Uuid::new_v4(), "Hi")).unwrap();
+	truncated.truncate(truncated.len() / 2);
+	socket
+		.send(WebSocketMessage::Binary(truncated))
+		.await
+		.unwrap();
+	// The session survives, and answers nothing but the valid chat.
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Still there?")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert!(messages.iter().all(|m| m.request_id() == Some(id)));
+	assert_eq!(fragments(&messages, id), vec!["Still there?"]);
+	assert_eq!(messages.last(), Some(&AppMessage::ChatCompleted(id)));
+}
+
+#[tokio::test]
+async fn reports_backend_errors()
+{
+	let failing = MockResponse {
+		content: "Lost".into(),
+		error: Some(MockError {
+			kind: MockErrorKind::Backend,
+			after: 2,
+			message: "The model crashed".into()
+		}),
+		..Default::default()
+	};
+	let mut socket = connect(scripted(respond(failing, 1, 0))).await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Tell me a story")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	// The fragments before the failure arrive, and then the error.
+	assert_eq!(fragments(&messages, id), vec!["L", "o"]);
+	assert_eq!(
+		messages.last(),
+		Some(&AppMessage::Error(id, AppError::ChatError))
+	);
+	assert!(!messages.contains(&AppMessage::ChatCompleted(id)));
+}
+
+#[tokio::test]
+async fn fails_over_or_reports_unavailable_backends()
+{
+	let unavailable = MockResponse {
+		error: Some(MockError {
+			kind: MockErrorKind::Unavailable,
+			..Default::default()
+		}),
+		..Default::default()
+	};
+	// With a healthy backend to stand in, the chat completes.
+	let mut socket = connect(mocks(vec![
+		("down", respond(unavailable.clone(), 0, 0)),
+		("up", MockScript::default()),
+	]))
+	.await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Anyone?")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(fragments(&messages, id), vec!["Anyone?"]);
+	assert_eq!(messages.last(), Some(&AppMessage::ChatCompleted(id)));
+	// Without one, the client hears of the failure.
+	let mut socket = connect(scripted(respond(unavailable, 0, 0))).await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Anyone?")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(messages, vec![AppMessage::Error(id, AppError::ChatError)]);
+}
+
+#[tokio::test]
+async fn fails_over_from_unreachable_servers()
+{
+	// The replayed response reports its usage, which reaches the client.
+	let mut socket = connect(backends(vec![
+		("down", unreachable().await),
+		("up", replay("ollama_length")),
+	]))
+	.await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Describe the goblin's reaction.")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(
+		fragments(&messages, id).concat(),
+		"The goblin staggers back, clutching its side and snarling at the"
+	);
+	assert!(messages.contains(&AppMessage::ChatUsage(
+		id,
+		Usage {
+			prompt_tokens: 48,
+			completion_tokens: 12,
+			exact: true
+		}
+	)));
+	assert_eq!(messages.last(), Some(&AppMessage::ChatCompleted(id)));
+	// Without a backend to stand in, the client hears of the failure.
+	let mut socket =
+		connect(backends(vec![("down", unreachable().await)])).await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Anyone?")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(messages, vec![AppMessage::Error(id, AppError::ChatError)]);
+}
+
+#[tokio::test]
+async fn reports_errors_in_replayed_responses()
+{
+	// The server fails partway through the response.
+	let mut socket =
+		connect(backends(vec![("openai", replay("openai_stream_error"))]))
+			.await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Tell me a story.")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(fragments(&messages, id), vec!["Once", " upon"]);
+	assert_eq!(
+		messages.last(),
+		Some(&AppMessage::Error(id, AppError::ChatError))
+	);
+	// The server refuses the request. It did connect, so no other backend
+	// stands in.
+	let mut socket = connect(backends(vec![
+		("openai", replay("openai_overloaded")),
+		("mock", Arc::new(MockBackend::echo())),
+	]))
+	.await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Hello?")).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(messages, vec![AppMessage::Error(id, AppError::ChatError)]);
+}
+
+#[tokio::test]
+async fn cancels_chats_in_progress()
+{
+	let slow = MockResponse {
+		content: "This will take a while.".into(),
+		..Default::default()
+	};
+	let mut socket = connect(scripted(respond(slow, 1, 50))).await;
+	let id = Uuid::new_v4();
+	send(&mut socket, &start(id, "Go on")).await;
+	assert!(matches!(
+		receive(&mut socket).await,
+		AppMessage::NextChatFragment(chat, _) if chat == id
+	));
+	send(&mut socket, &AppMessage::CancelChat(id)).await;
+	let messages = receive_until_concluded(&mut socket, 1).await;
+	assert_eq!(messages.last(), Some(&AppMessage::ChatCancelled(id)));
+	assert!(!messages.contains(&AppMessage::ChatCompleted(id)));
+}
+
+/// How long to wait for the server to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);